@@ -0,0 +1,86 @@
+//! Accuracy and speed of the Barnes–Hut approximation of the potentials
+//! (`StewartEngine::BarnesHut`) against the exact computation
+//! (`Stewart::interp_point`, used by `stewart_interpolation`), on random
+//! points over Europe with spherical distances.
+extern crate smoomars;
+
+use smoomars::{Bbox, SmoothType, StewartEngine, StewartPotentialGrid, stewart,
+               stewart_interpolation};
+use smoomars::utils::{PtValue, SphericalPtValue};
+use std::time::Instant;
+
+
+/// Deterministic pseudo-random numbers in [0, 1).
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> f64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn seconds(start: Instant) -> f64 {
+    let elapsed = start.elapsed();
+    elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9
+}
+
+fn main() {
+    let mut rnd = Lcg(42);
+    // Half of the points spread over the window, half clustered around Paris
+    let obs_points = (0..20000)
+        .map(|k| if k % 2 == 0 {
+                 SphericalPtValue::new(-10.0 + 40.0 * rnd.next(),
+                                       35.0 + 25.0 * rnd.next(),
+                                       100.0 * rnd.next())
+             } else {
+                 SphericalPtValue::new(2.0 + 3.0 * rnd.next() * rnd.next(),
+                                       48.0 + 2.0 * rnd.next() * rnd.next(),
+                                       100.0 * rnd.next())
+             })
+        .collect::<Vec<_>>();
+    let bbox = Bbox::new(-10.0, 30.0, 35.0, 60.0);
+    let (reso_x, reso_y) = (60, 60);
+    for &(span, beta) in &[(50000.0, 2.0), (200000.0, 2.0), (100000.0, 1.0)] {
+        let start = Instant::now();
+        let exact: Vec<SphericalPtValue> = stewart_interpolation(reso_x,
+                                                                 reso_y,
+                                                                 &bbox,
+                                                                 &obs_points,
+                                                                 "exponential",
+                                                                 span,
+                                                                 Some(beta))
+                .unwrap();
+        println!("span {} m, beta {}: exact in {:.3}s", span, beta, seconds(start));
+        let max_pot = exact.iter().fold(0.0f64, |m, pt| m.max(pt.get_value()));
+        for &theta in &[0.2, 0.5, 0.8, 1.0] {
+            let conf = StewartPotentialGrid::new(span,
+                                                 beta,
+                                                 SmoothType::Exponential,
+                                                 &bbox,
+                                                 reso_x,
+                                                 reso_y,
+                                                 2)
+                    .with_engine(StewartEngine::BarnesHut(theta));
+            let start = Instant::now();
+            let approx = stewart(&conf, &obs_points).unwrap();
+            let time = seconds(start);
+            let (mut max_err, mut sum_rel, mut max_rel) = (0.0f64, 0.0, 0.0f64);
+            for (a, b) in exact.iter().zip(approx.iter()) {
+                let err = (a.get_value() - b.get_value()).abs();
+                max_err = max_err.max(err);
+                if a.get_value() > 0.0 {
+                    sum_rel += err / a.get_value();
+                    max_rel = max_rel.max(err / a.get_value());
+                }
+            }
+            println!("  theta {}: {:.3}s, max error {:.2e} of the maximum, \
+                      relative error {:.2e} on average, {:.2e} at most",
+                     theta,
+                     time,
+                     max_err / max_pot,
+                     sum_rel / exact.len() as f64,
+                     max_rel);
+        }
+    }
+}
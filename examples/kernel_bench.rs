@@ -0,0 +1,168 @@
+//! Speedup of the SIMD batched kernels used by each method (stewart,
+//! stewart_interpolation, idw and rbf) over a scalar evaluation through
+//! function pointers, on a single thread. The figures depend on the vector
+//! instructions of the target: run it with `--release` (and, to use AVX,
+//! `RUSTFLAGS="-C target-cpu=native"`) to measure them on a given machine.
+extern crate smoomars;
+
+use smoomars::{Bbox, Rbf, SmoothType, StewartEngine, StewartPotentialGrid, idw_interpolation,
+               rbf_interpolation, stewart, stewart_interpolation};
+use smoomars::parallel;
+use smoomars::utils::{CartesianPtValue, PtValue, SphericalPtValue};
+use std::time::Instant;
+
+
+fn seconds(start: Instant) -> f64 {
+    let elapsed = start.elapsed();
+    elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9
+}
+
+fn exponential(alpha: f64, beta: f64, dist: f64) -> f64 {
+    (-alpha * dist.powf(beta)).exp()
+}
+
+fn gaussian(r: f64, epsilon: f64) -> f64 {
+    (-(epsilon * r).powi(2)).exp()
+}
+
+/// Scalar evaluation of the exact potentials: one fold per cell, calling the
+/// smoothing function through a pointer.
+#[inline(never)]
+fn scalar_potentials<T>(cells: &mut [T], obs_points: &[T], func: fn(f64, f64, f64) -> f64)
+    where T: PtValue
+{
+    let (alpha, beta) = (0.69314718055994529 / 50000f64.powf(2.0), 2.0);
+    for cell in cells.iter_mut() {
+        let value = obs_points
+            .iter()
+            .fold(0.0, |sum, obs_pt| {
+                let (x, y, val) = obs_pt.get_triplet();
+                sum + val * func(alpha, beta, cell.distance(x, y))
+            });
+        cell.set_value(value);
+    }
+}
+
+/// Scalar evaluation of the inverse distance weighted means, on each cell
+/// (without the special case of the cells lying on a point).
+#[inline(never)]
+fn scalar_idw<T>(cells: &mut [T], obs_points: &[T], power: f64)
+    where T: PtValue
+{
+    for cell in cells.iter_mut() {
+        let (x, y) = cell.get_coordinates();
+        let (zw, sw) = obs_points
+            .iter()
+            .fold((0.0, 0.0), |(zw, sw), obs_pt| {
+                let w = 1.0 / obs_pt.distance(x, y).powf(power);
+                (zw + w * obs_pt.get_value(), sw + w)
+            });
+        cell.set_value(zw / sw);
+    }
+}
+
+/// Scalar evaluation of `Rbf::interp_point` (vector of distances then
+/// weighted sum), with the values standing for the weights.
+#[inline(never)]
+fn scalar_rbf<T>(cells: &mut [T], obs_points: &[T], func: fn(f64, f64) -> f64, epsilon: f64)
+    where T: PtValue
+{
+    for cell in cells.iter_mut() {
+        let (x, y) = cell.get_coordinates();
+        let mut terms = Vec::with_capacity(obs_points.len());
+        for pt in obs_points {
+            let (px, py, w) = pt.get_triplet();
+            let r = ((x - px).powi(2) + (y - py).powi(2)).sqrt();
+            terms.push(w * func(r, epsilon));
+        }
+        cell.set_value(terms.iter().sum());
+    }
+}
+
+fn report(name: &str, scalar: f64, simd: f64) {
+    println!("{:<36} scalar {:>8.3}s   simd {:>8.3}s   speedup x{:.2}",
+             name,
+             scalar,
+             simd,
+             scalar / simd);
+}
+
+fn points<T: PtValue>(n: usize, bbox: &Bbox) -> Vec<T> {
+    let mut seed: u64 = 1;
+    let mut rnd = || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 11) as f64 / (1u64 << 53) as f64
+    };
+    (0..n)
+        .map(|_| {
+                 T::new(bbox.min_x + (bbox.max_x - bbox.min_x) * rnd(),
+                        bbox.min_y + (bbox.max_y - bbox.min_y) * rnd(),
+                        100.0 * rnd())
+             })
+        .collect()
+}
+
+fn bench_stewart<T>(name: &str, bbox: &Bbox, obs_points: &[T], reso: usize)
+    where T: PtValue + Send + Sync
+{
+    let conf = StewartPotentialGrid::new(50000.0,
+                                         2.0,
+                                         SmoothType::Exponential,
+                                         bbox,
+                                         reso,
+                                         reso,
+                                         1)
+            .with_engine(StewartEngine::Exact);
+    let mut cells = stewart(&conf, &obs_points[..1]).unwrap();
+    let start = Instant::now();
+    scalar_potentials(&mut cells, obs_points, exponential);
+    let scalar = seconds(start);
+    let start = Instant::now();
+    let res = stewart(&conf, obs_points).unwrap();
+    report(&format!("stewart ({})", name), scalar, seconds(start));
+    // The vector exp and powf only differ from the scalar ones by a few ulps
+    assert!(res.iter()
+                .zip(cells.iter())
+                .all(|(a, b)| (a.get_value() - b.get_value()).abs() <= 1e-12 * b.get_value()));
+
+    let start = Instant::now();
+    scalar_potentials(&mut cells, obs_points, exponential);
+    let scalar = seconds(start);
+    let start = Instant::now();
+    stewart_interpolation(reso, reso, bbox, obs_points, "exponential", 50000.0, Some(2.0))
+        .unwrap();
+    report(&format!("stewart_interpolation ({})", name), scalar, seconds(start));
+
+    let start = Instant::now();
+    scalar_idw(&mut cells, obs_points, 2.0);
+    let scalar = seconds(start);
+    let start = Instant::now();
+    idw_interpolation(reso, reso, bbox, obs_points, 2.0).unwrap();
+    report(&format!("idw_interpolation ({})", name), scalar, seconds(start));
+}
+
+fn main() {
+    // Time the evaluation alone, without the parallel split of the grid
+    parallel::set_num_threads(1).unwrap();
+    let bbox = Bbox::new(-5.0, 10.0, 42.0, 51.0);
+    let obs_points = points::<SphericalPtValue>(5000, &bbox);
+    bench_stewart("spherical", &bbox, &obs_points, 100);
+    let bbox = Bbox::new(0.0, 1000000.0, 0.0, 1000000.0);
+    let obs_points = points::<CartesianPtValue>(5000, &bbox);
+    bench_stewart("cartesian", &bbox, &obs_points, 100);
+
+    let obs_points = points::<CartesianPtValue>(1000, &bbox);
+    let reso = 200;
+    let rbf = Rbf::new(&obs_points, "gaussian", Some(2e-5)).unwrap();
+    let mut cells = idw_interpolation(reso, reso, &bbox, &obs_points[..1], 2.0).unwrap();
+    let start = Instant::now();
+    scalar_rbf(&mut cells, &obs_points, gaussian, 2e-5);
+    let scalar = seconds(start);
+    let start = Instant::now();
+    let coords = cells.iter().map(|pt| pt.get_coordinates()).collect::<Vec<_>>();
+    rbf.interp_points(&coords);
+    report("rbf (cartesian)", scalar, seconds(start));
+    let start = Instant::now();
+    rbf_interpolation(reso, reso, &bbox, &obs_points, "gaussian", Some(2e-5)).unwrap();
+    println!("rbf_interpolation (cartesian, solve included) {:.3}s", seconds(start));
+}
@@ -28,8 +28,8 @@ fn example(c: Common) {
     fg.axes3d()
         .set_title("Population potentials", &[])
         .surface(z1.iter(),
-                 reso_lon as usize,
-                 reso_lat as usize,
+                 reso_lon,
+                 reso_lat,
                  Some((0.8, 31.8, 4.2, 35.2)),
                  &[])
         .set_x_label("X", &[])
@@ -59,8 +59,8 @@ fn example(c: Common) {
     fg.axes3d()
         .set_title("Population potentials", &[])
         .surface(z1.iter(),
-                 reso_lon as usize,
-                 reso_lat as usize,
+                 reso_lon,
+                 reso_lat,
                  Some((0.8, 31.8, 4.2, 35.2)),
                  &[])
         .set_x_label("X", &[])
@@ -82,8 +82,8 @@ fn example(c: Common) {
     fg.axes3d()
         .set_title("Idw (power 2)", &[])
         .surface(z1.iter(),
-                 reso_lon as usize,
-                 reso_lat as usize,
+                 reso_lon,
+                 reso_lat,
                  Some((0.8, 31.8, 4.2, 35.2)),
                  &[])
         .set_x_label("X", &[])
@@ -105,8 +105,8 @@ fn example(c: Common) {
     fg.axes3d()
         .set_title("Idw (power 0.1)", &[])
         .surface(z1.iter(),
-                 reso_lon as usize,
-                 reso_lat as usize,
+                 reso_lon,
+                 reso_lat,
                  Some((0.8, 31.8, 4.2, 35.2)),
                  &[])
         .set_x_label("X", &[])
@@ -141,8 +141,8 @@ fn example(c: Common) {
     fg.axes3d()
         .set_title("Two stocks. Stewart Exponential (beta=2)", &[])
         .surface(z1.iter(),
-                 reso_x as usize,
-                 reso_y as usize,
+                 reso_x,
+                 reso_y,
                  Some((0.0, 0.0, 10.0, 10.0)),
                  &[])
         .set_x_label("X", &[])
@@ -167,8 +167,8 @@ fn example(c: Common) {
     fg.axes3d()
         .set_title("Two stocks. Stewart Pareto (beta=2)", &[])
         .surface(z1.iter(),
-                 reso_x as usize,
-                 reso_y as usize,
+                 reso_x,
+                 reso_y,
                  Some((0.0, 0.0, 10.0, 10.0)),
                  &[])
         .set_x_label("X", &[])
@@ -185,7 +185,7 @@ fn example(c: Common) {
                                                            &bbox,
                                                            &obs_points_two_stocks,
                                                            "inverse_multiquadratic",
-                                                           Some(1.66))
+                                                           Some(1.0 / 1.66))
             .unwrap();
     let mut z1 = Vec::with_capacity(res_rbf.len());
     for pt in res_rbf {
@@ -198,8 +198,8 @@ fn example(c: Common) {
         .set_title("Two stocks. Inverse multiquadratic RBF interpolation (epsilon: 1.66).",
                    &[])
         .surface(z1.iter(),
-                 reso_x as usize,
-                 reso_y as usize,
+                 reso_x,
+                 reso_y,
                  Some((0.0, 0.0, 10.0, 10.0)),
                  &[])
         .set_x_label("X", &[])
@@ -0,0 +1,198 @@
+//! Barriers (e.g. mountain ridges, rivers or borders) blocking, or adding a
+//! penalty to, the influence of the observation points across them.
+//!
+//! A barrier is a set of polylines (the rings of polygons acting through
+//! their boundary); the influence of a point on a location crosses it when
+//! the straight segment joining them (in the coordinates) intersects one of
+//! its polylines.
+use std::f64;
+use std::fs::File;
+use std::io::Read;
+use geojson::{GeoJson, Value};
+use bbox::Bbox;
+use errors::*;
+use index::SegmentIndex;
+use metric::DistanceMetric;
+
+
+/// Effect of the barriers on the distance between two locations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Crossing {
+    /// The locations don't interact at all (their distance is infinite).
+    Block,
+    /// The given penalty is added to their distance for each barrier
+    /// between them.
+    Penalty(f64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Barriers {
+    segments: Vec<((f64, f64), (f64, f64))>,
+    /// Barrier each segment belongs to.
+    barrier_of: Vec<usize>,
+    nb_barriers: usize,
+    index: SegmentIndex,
+}
+
+impl Barriers {
+    /// Barriers made of polylines, given as lists of positions.
+    pub fn new(barriers: &[Vec<Vec<(f64, f64)>>]) -> Self {
+        let (mut segments, mut barrier_of) = (Vec::new(), Vec::new());
+        for (ix, lines) in barriers.iter().enumerate() {
+            for line in lines {
+                for pair in line.windows(2) {
+                    segments.push((pair[0], pair[1]));
+                    barrier_of.push(ix);
+                }
+            }
+        }
+        Barriers {
+            index: SegmentIndex::new(&segments),
+            segments: segments,
+            barrier_of: barrier_of,
+            nb_barriers: barriers.len(),
+        }
+    }
+
+    /// Barriers made of the (Multi)LineString and (Multi)Polygon features of a
+    /// GeoJSON FeatureCollection, one per feature.
+    pub fn from_geojson(path: &str) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let mut raw_json = String::new();
+        file.read_to_string(&mut raw_json)?;
+        let features = match raw_json.parse::<GeoJson>()? {
+            GeoJson::FeatureCollection(collection) => collection.features,
+            _ => return Err("Error: expected a FeatureCollection".into()),
+        };
+        let mut barriers = Vec::with_capacity(features.len());
+        for (ix, ft) in features.iter().enumerate() {
+            let lines = match ft.geometry.as_ref().map(|g| &g.value) {
+                Some(&Value::LineString(ref line)) => vec![line.clone()],
+                Some(&Value::MultiLineString(ref lines)) |
+                Some(&Value::Polygon(ref lines)) => lines.clone(),
+                Some(&Value::MultiPolygon(ref polygons)) => {
+                    polygons.iter().flat_map(|rings| rings.iter().cloned()).collect()
+                }
+                Some(_) => {
+                    return Err(format!("Feature {}: barriers are lines or polygons", ix).into())
+                }
+                None => return Err(format!("Feature {}: no geometry", ix).into()),
+            };
+            let mut barrier = Vec::with_capacity(lines.len());
+            for line in lines {
+                if line.iter().any(|pos| pos.len() < 2) {
+                    return Err(format!("Feature {}: invalid position", ix).into());
+                }
+                barrier.push(line.iter().map(|pos| (pos[0], pos[1])).collect());
+            }
+            barriers.push(barrier);
+        }
+        Ok(Barriers::new(&barriers))
+    }
+
+    pub fn nb_barriers(&self) -> usize {
+        self.nb_barriers
+    }
+
+    /// Whether the segment from `a` to `b` crosses (or touches) a barrier.
+    pub fn crosses(&self, a: (f64, f64), b: (f64, f64)) -> bool {
+        let mut crossed = false;
+        self.index
+            .query(a, b, |ix| {
+                let (c, d) = self.segments[ix];
+                crossed = segments_intersect(a, b, c, d);
+                !crossed
+            });
+        crossed
+    }
+
+    /// Number of barriers crossed (or touched) by the segment from `a` to `b`.
+    pub fn nb_crossed(&self, a: (f64, f64), b: (f64, f64)) -> usize {
+        let mut crossed = Vec::new();
+        self.index
+            .query(a, b, |ix| {
+                let (c, d) = self.segments[ix];
+                if segments_intersect(a, b, c, d) {
+                    crossed.push(self.barrier_of[ix]);
+                }
+                true
+            });
+        crossed.sort();
+        crossed.dedup();
+        crossed.len()
+    }
+}
+
+/// Twice the signed area of the triangle (a, b, c), positive if it turns left.
+fn orientation(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+/// Whether `c`, aligned with `a` and `b`, lies between them.
+fn within_bounds(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> bool {
+    c.0 >= a.0.min(b.0) && c.0 <= a.0.max(b.0) && c.1 >= a.1.min(b.1) && c.1 <= a.1.max(b.1)
+}
+
+/// Whether the segments [a, b] and [c, d] have at least one point in common.
+fn segments_intersect(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> bool {
+    let (o1, o2) = (orientation(c, d, a), orientation(c, d, b));
+    let (o3, o4) = (orientation(a, b, c), orientation(a, b, d));
+    if ((o1 > 0.0 && o2 < 0.0) || (o1 < 0.0 && o2 > 0.0)) &&
+       ((o3 > 0.0 && o4 < 0.0) || (o3 < 0.0 && o4 > 0.0)) {
+        return true;
+    }
+    (o1 == 0.0 && within_bounds(c, d, a)) || (o2 == 0.0 && within_bounds(c, d, b)) ||
+    (o3 == 0.0 && within_bounds(a, b, c)) || (o4 == 0.0 && within_bounds(a, b, d))
+}
+
+/// Distance of `metric`, with the `crossing` effect of the `barriers` between
+/// the locations.
+#[derive(Debug, Clone)]
+pub struct BarrierMetric<'a, M> {
+    metric: M,
+    barriers: &'a Barriers,
+    crossing: Crossing,
+}
+
+impl<'a, M> BarrierMetric<'a, M>
+    where M: DistanceMetric
+{
+    /// Fails if the penalty of `crossing` is negative or infinite (use
+    /// `Crossing::Block` instead).
+    pub fn new(metric: M, barriers: &'a Barriers, crossing: Crossing) -> Result<Self> {
+        if let Crossing::Penalty(penalty) = crossing {
+            if !(penalty >= 0.0) || penalty.is_infinite() {
+                return Err(format!("Invalid barrier penalty: {}", penalty).into());
+            }
+        }
+        Ok(BarrierMetric {
+               metric: metric,
+               barriers: barriers,
+               crossing: crossing,
+           })
+    }
+}
+
+impl<'a, M> DistanceMetric for BarrierMetric<'a, M>
+    where M: DistanceMetric
+{
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        let dist = self.metric.distance(x1, y1, x2, y2);
+        match self.crossing {
+            Crossing::Block => {
+                if self.barriers.crosses((x1, y1), (x2, y2)) {
+                    f64::INFINITY
+                } else {
+                    dist
+                }
+            }
+            Crossing::Penalty(penalty) => {
+                dist + penalty * self.barriers.nb_crossed((x1, y1), (x2, y2)) as f64
+            }
+        }
+    }
+    /// The barriers only lengthen the distances, so that the window of `metric` holds.
+    fn search_window(&self, x: f64, y: f64, dist: f64) -> Bbox {
+        self.metric.search_window(x, y, dist)
+    }
+}
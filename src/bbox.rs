@@ -1,7 +1,15 @@
 use std::f64::{INFINITY, NEG_INFINITY};
+use std::fmt;
+use std::str::FromStr;
+use errors::*;
+use projection::Crs;
 use utils::PtValue;
 
-#[derive(Debug, Clone, Copy)]
+/// Number of segments each edge is divided in when transforming a box to
+/// another CRS, for the curved edges of the result to be enclosed.
+const TRANSFORM_EDGE_SEGMENTS: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub struct Bbox {
     pub min_x: f64,
     pub max_x: f64,
@@ -19,6 +27,8 @@ impl Bbox {
         }
     }
 
+    /// Smallest box holding `obs_points` (of null width and height for a single
+    /// point, with infinite bounds for no points).
     pub fn from_points<T>(obs_points: &[T]) -> Self
         where T: PtValue
     {
@@ -26,16 +36,10 @@ impl Bbox {
             (INFINITY, NEG_INFINITY, INFINITY, NEG_INFINITY);
         for pt in obs_points {
             let (pt_x, pt_y) = pt.get_coordinates();
-            if pt_x > max_x {
-                max_x = pt_x;
-            } else if pt_x < min_x {
-                min_x = pt_x;
-            }
-            if pt_y > max_y {
-                max_y = pt_y;
-            } else if pt_y < min_y {
-                min_y = pt_y;
-            }
+            min_x = min_x.min(pt_x);
+            max_x = max_x.max(pt_x);
+            min_y = min_y.min(pt_y);
+            max_y = max_y.max(pt_y);
         }
         Bbox {
             min_x: min_x,
@@ -44,4 +48,136 @@ impl Bbox {
             max_y: max_y,
         }
     }
+
+    pub fn width(&self) -> f64 {
+        self.max_x - self.min_x
+    }
+
+    pub fn height(&self) -> f64 {
+        self.max_y - self.min_y
+    }
+
+    pub fn area(&self) -> f64 {
+        self.width() * self.height()
+    }
+
+    /// Whether (x, y) is inside the box or on its edges.
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.min_x && x <= self.max_x && y >= self.min_y && y <= self.max_y
+    }
+
+    /// Whether `other` is inside the box (edges included).
+    pub fn contains_bbox(&self, other: &Bbox) -> bool {
+        other.min_x >= self.min_x && other.max_x <= self.max_x && other.min_y >= self.min_y &&
+        other.max_y <= self.max_y
+    }
+
+    /// Whether the two boxes have at least one point in common.
+    pub fn intersects(&self, other: &Bbox) -> bool {
+        self.min_x <= other.max_x && other.min_x <= self.max_x && self.min_y <= other.max_y &&
+        other.min_y <= self.max_y
+    }
+
+    /// Smallest box holding both boxes.
+    pub fn union(&self, other: &Bbox) -> Bbox {
+        Bbox::new(self.min_x.min(other.min_x),
+                  self.max_x.max(other.max_x),
+                  self.min_y.min(other.min_y),
+                  self.max_y.max(other.max_y))
+    }
+
+    /// Part common to both boxes, if any.
+    pub fn intersection(&self, other: &Bbox) -> Option<Bbox> {
+        if !self.intersects(other) {
+            return None;
+        }
+        Some(Bbox::new(self.min_x.max(other.min_x),
+                       self.max_x.min(other.max_x),
+                       self.min_y.max(other.min_y),
+                       self.max_y.min(other.max_y)))
+    }
+
+    /// Box extended by `dist` on each side (shrunk if `dist` is negative).
+    pub fn buffer(&self, dist: f64) -> Bbox {
+        Bbox::new(self.min_x - dist,
+                  self.max_x + dist,
+                  self.min_y - dist,
+                  self.max_y + dist)
+    }
+
+    /// Box extended on each side by `percent` percent of its width (along x)
+    /// and of its height (along y).
+    pub fn buffer_percent(&self, percent: f64) -> Bbox {
+        let (dx, dy) = (self.width() * percent / 100.0, self.height() * percent / 100.0);
+        Bbox::new(self.min_x - dx, self.max_x + dx, self.min_y - dy, self.max_y + dy)
+    }
+
+    /// Smallest box, in the coordinates of `to`, holding this box of the
+    /// coordinates of `from` (its edges being densified, as they are no
+    /// longer straight lines once transformed).
+    pub fn transform(&self, from: &Crs, to: &Crs) -> Bbox {
+        if from == to {
+            return *self;
+        }
+        let mut res = Bbox::new(INFINITY, NEG_INFINITY, INFINITY, NEG_INFINITY);
+        let n = TRANSFORM_EDGE_SEGMENTS;
+        for k in 0..n + 1 {
+            let x = self.min_x + self.width() * k as f64 / n as f64;
+            let y = self.min_y + self.height() * k as f64 / n as f64;
+            let edge_points = [(x, self.min_y), (x, self.max_y), (self.min_x, y), (self.max_x, y)];
+            for &(px, py) in &edge_points {
+                let (tx, ty) = from.transform(to, px, py);
+                res.min_x = res.min_x.min(tx);
+                res.max_x = res.max_x.max(tx);
+                res.min_y = res.min_y.min(ty);
+                res.max_y = res.max_y.max(ty);
+            }
+        }
+        res
+    }
+
+    /// Check that the box, divided in `reso_x` by `reso_y` cells, defines a usable grid.
+    pub fn check_grid(&self, reso_x: usize, reso_y: usize) -> Result<()> {
+        if !(self.min_x.is_finite() && self.max_x.is_finite() && self.min_y.is_finite() &&
+             self.max_y.is_finite()) || self.min_x >= self.max_x ||
+           self.min_y >= self.max_y {
+            return Err(ErrorKind::InvalidBbox(self.min_x, self.max_x, self.min_y, self.max_y)
+                           .into());
+        }
+        if reso_x == 0 || reso_y == 0 || reso_x.checked_mul(reso_y).is_none() {
+            return Err(ErrorKind::InvalidResolution(reso_x, reso_y).into());
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Bbox {
+    /// Write the box as "minx,miny,maxx,maxy".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.min_x, self.min_y, self.max_x, self.max_y)
+    }
+}
+
+impl FromStr for Bbox {
+    type Err = Error;
+
+    /// Parse a box given as "minx,miny,maxx,maxy".
+    fn from_str(s: &str) -> Result<Self> {
+        let coords = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<::std::result::Result<Vec<f64>, _>>()
+            .chain_err(|| format!("Invalid bounding box: '{}' (expected minx,miny,maxx,maxy)", s))?;
+        if coords.len() != 4 {
+            return Err(format!("Invalid bounding box: '{}' (expected minx,miny,maxx,maxy)", s)
+                           .into());
+        }
+        let bbox = Bbox::new(coords[0], coords[2], coords[1], coords[3]);
+        if !coords.iter().all(|c| c.is_finite()) || bbox.min_x > bbox.max_x ||
+           bbox.min_y > bbox.max_y {
+            return Err(ErrorKind::InvalidBbox(bbox.min_x, bbox.max_x, bbox.min_y, bbox.max_y)
+                           .into());
+        }
+        Ok(bbox)
+    }
 }
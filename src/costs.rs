@@ -0,0 +1,254 @@
+//! Origin–destination cost matrices supplied from outside (e.g. travel times
+//! computed by a transport model), used instead of the distances between the
+//! observation points (the origins) and the target locations.
+use std::f64;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use csv;
+use errors::*;
+
+
+/// Costs between `nb_origins` origins and `nb_destinations` destinations,
+/// infinite for the pairs which don't interact.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostMatrix {
+    nb_origins: usize,
+    nb_destinations: usize,
+    /// Costs origin by origin.
+    costs: Vec<f64>,
+}
+
+impl CostMatrix {
+    /// Matrix of the `costs` given origin by origin. NaN costs are read as
+    /// infinite; negative costs are an error.
+    pub fn new(nb_origins: usize, nb_destinations: usize, mut costs: Vec<f64>) -> Result<Self> {
+        let size = nb_origins
+            .checked_mul(nb_destinations)
+            .ok_or("Cost matrix too large for this platform")?;
+        if costs.len() != size {
+            return Err(ErrorKind::DimensionMismatch(size, costs.len()).into());
+        }
+        for (ix, cost) in costs.iter_mut().enumerate() {
+            if cost.is_nan() {
+                *cost = f64::INFINITY;
+            } else if *cost < 0.0 {
+                return Err(format!("Negative cost from origin {} to destination {}: {}",
+                                   ix / nb_destinations,
+                                   ix % nb_destinations,
+                                   cost)
+                                   .into());
+            }
+        }
+        Ok(CostMatrix {
+               nb_origins: nb_origins,
+               nb_destinations: nb_destinations,
+               costs: costs,
+           })
+    }
+
+    /// Read a CSV file with a header row followed by one row of costs per
+    /// origin, one column per destination (empty or "NA" fields are infinite).
+    pub fn from_csv_wide(path: &str, delimiter: u8) -> Result<Self> {
+        let mut rdr = csv::Reader::from_file(path)?
+            .has_headers(true)
+            .delimiter(delimiter)
+            .flexible(true);
+        let nb_destinations = rdr.headers()?.len();
+        let (mut nb_origins, mut costs) = (0, Vec::new());
+        for (i, record) in rdr.records().enumerate() {
+            let record = record?;
+            if record.len() != nb_destinations {
+                return Err(format!("Line {}: {}",
+                                   i + 2,
+                                   Error::from(ErrorKind::DimensionMismatch(nb_destinations,
+                                                                            record.len())))
+                                   .into());
+            }
+            for field in &record {
+                costs.push(parse_cost(field)?);
+            }
+            nb_origins += 1;
+        }
+        CostMatrix::new(nb_origins, nb_destinations, costs)
+    }
+
+    /// Read a CSV file with a header row followed by rows of (origin index,
+    /// destination index, cost), the indices starting from 0. The pairs
+    /// missing from the file are infinite.
+    pub fn from_csv_long(path: &str,
+                         delimiter: u8,
+                         nb_origins: usize,
+                         nb_destinations: usize)
+                         -> Result<Self> {
+        let size = nb_origins
+            .checked_mul(nb_destinations)
+            .ok_or("Cost matrix too large for this platform")?;
+        let mut costs = vec![f64::INFINITY; size];
+        let mut rdr = csv::Reader::from_file(path)?
+            .has_headers(true)
+            .delimiter(delimiter)
+            .flexible(true);
+        for (i, record) in rdr.records().enumerate() {
+            let record = record?;
+            let line = i + 2;
+            if record.len() < 3 {
+                return Err(format!("Line {}: expected origin, destination and cost", line)
+                               .into());
+            }
+            let parse_index = |field: &str, count: usize, name: &str| -> Result<usize> {
+                match field.trim().parse::<usize>() {
+                    Ok(ix) if ix < count => Ok(ix),
+                    _ => {
+                        Err(format!("Line {}: invalid {} index '{}' (expected 0 to {})",
+                                    line,
+                                    name,
+                                    field,
+                                    count as i64 - 1)
+                                    .into())
+                    }
+                }
+            };
+            let origin = parse_index(&record[0], nb_origins, "origin")?;
+            let destination = parse_index(&record[1], nb_destinations, "destination")?;
+            costs[origin * nb_destinations + destination] = parse_cost(&record[2])?;
+        }
+        CostMatrix::new(nb_origins, nb_destinations, costs)
+    }
+
+    /// Read a two dimensional array of floats or integers (of 4 or 8 bytes)
+    /// from a NumPy .npy file, the origins along its first axis.
+    pub fn from_npy(path: &str) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let invalid = || Error::from(format!("Invalid .npy file {}", path));
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic[..6] != b"\x93NUMPY" {
+            return Err(invalid());
+        }
+        let header_len = match magic[6] {
+            1 => {
+                let mut len = [0; 2];
+                reader.read_exact(&mut len)?;
+                len[0] as usize | (len[1] as usize) << 8
+            }
+            2 | 3 => {
+                let mut len = [0; 4];
+                reader.read_exact(&mut len)?;
+                len.iter().rev().fold(0, |acc, &b| acc << 8 | b as usize)
+            }
+            _ => return Err(invalid()),
+        };
+        let mut header = vec![0; header_len];
+        reader.read_exact(&mut header)?;
+        let header = String::from_utf8(header).map_err(|_| invalid())?;
+        let descr = dict_value(&header, "descr").ok_or_else(&invalid)?;
+        let descr = descr.trim_matches(|c| c == '\'' || c == '"');
+        let fortran_order = match dict_value(&header, "fortran_order") {
+            Some("True") => true,
+            Some("False") => false,
+            _ => return Err(invalid()),
+        };
+        let shape = dict_value(&header, "shape")
+            .ok_or_else(&invalid)?
+            .trim_matches(|c| c == '(' || c == ')')
+            .split(',')
+            .map(|dim| dim.trim())
+            .filter(|dim| !dim.is_empty())
+            .map(|dim| dim.parse::<usize>().map_err(|_| invalid()))
+            .collect::<Result<Vec<usize>>>()?;
+        if shape.len() != 2 {
+            return Err(format!("Expected a two dimensional array in {}, found {} dimension(s)",
+                               path,
+                               shape.len())
+                               .into());
+        }
+        let (nb_origins, nb_destinations) = (shape[0], shape[1]);
+        let (big_endian, kind, width) = match descr.as_bytes() {
+            &[order, kind, width] if (order == b'<' || order == b'>' || order == b'=') &&
+                                     (kind == b'f' || kind == b'i') &&
+                                     (width == b'4' || width == b'8') => {
+                (order == b'>', kind, (width - b'0') as usize)
+            }
+            _ => return Err(format!("Unsupported data type '{}' in {}", descr, path).into()),
+        };
+        let size = nb_origins.checked_mul(nb_destinations).ok_or_else(&invalid)?;
+        let mut values = Vec::new();
+        let mut buf = [0; 8];
+        for _ in 0..size {
+            let bytes = &mut buf[..width];
+            reader.read_exact(bytes)?;
+            if big_endian {
+                bytes.reverse();
+            }
+            let bits = bytes.iter().rev().fold(0u64, |acc, &b| acc << 8 | b as u64);
+            values.push(match (kind, width) {
+                            (b'f', 4) => f32::from_bits(bits as u32) as f64,
+                            (b'f', _) => f64::from_bits(bits),
+                            (_, 4) => bits as u32 as i32 as f64,
+                            _ => bits as i64 as f64,
+                        });
+        }
+        if reader.read(&mut buf)? != 0 {
+            return Err(invalid());
+        }
+        let costs = if fortran_order {
+            // Stored destination by destination
+            let mut costs = vec![0.0; size];
+            for (ix, val) in values.into_iter().enumerate() {
+                costs[(ix % nb_origins) * nb_destinations + ix / nb_origins] = val;
+            }
+            costs
+        } else {
+            values
+        };
+        CostMatrix::new(nb_origins, nb_destinations, costs)
+    }
+
+    pub fn nb_origins(&self) -> usize {
+        self.nb_origins
+    }
+
+    pub fn nb_destinations(&self) -> usize {
+        self.nb_destinations
+    }
+
+    /// Cost from `origin` to `destination`.
+    pub fn get(&self, origin: usize, destination: usize) -> f64 {
+        self.costs[origin * self.nb_destinations + destination]
+    }
+
+    /// Fail (with `ErrorKind::DimensionMismatch`) unless the matrix goes from
+    /// `nb_origins` observation points to `nb_destinations` targets.
+    pub fn check_dimensions(&self, nb_origins: usize, nb_destinations: usize) -> Result<()> {
+        if self.nb_origins != nb_origins {
+            return Err(ErrorKind::DimensionMismatch(nb_origins, self.nb_origins).into());
+        }
+        if self.nb_destinations != nb_destinations {
+            return Err(ErrorKind::DimensionMismatch(nb_destinations, self.nb_destinations).into());
+        }
+        Ok(())
+    }
+}
+
+fn parse_cost(field: &str) -> Result<f64> {
+    let f = field.trim();
+    if f.is_empty() || f == "NA" {
+        Ok(f64::INFINITY)
+    } else {
+        Ok(f.parse::<f64>()?)
+    }
+}
+
+/// Value of `key` in the header of a .npy file (the repr of a python dict).
+fn dict_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header
+        .find(&format!("'{}'", key))
+        .or_else(|| header.find(&format!("\"{}\"", key)))? + key.len() + 2;
+    let rest = header[start..].trim_start().trim_start_matches(':').trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|ix| ix + 1)
+    } else {
+        rest.find(|c| c == ',' || c == '}')
+    }?;
+    Some(rest[..end].trim())
+}
@@ -0,0 +1,98 @@
+//! Truncation of the interaction kernels beyond a cutoff distance.
+use errors::*;
+
+
+/// Number of grid cells on which the contribution dropped by the
+/// truncation is measured.
+const NB_SAMPLES: usize = 64;
+
+/// Where to truncate an interaction kernel: contributions of observation
+/// points farther than a distance, or whose weight is below a threshold,
+/// are dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cutoff {
+    Distance(f64),
+    Weight(f64),
+}
+
+impl Cutoff {
+    /// Cutoff distance for the decreasing weight function `kernel`.
+    pub fn distance<K>(&self, kernel: K) -> Result<f64>
+        where K: Fn(f64) -> f64
+    {
+        match *self {
+            Cutoff::Distance(dist) if dist > 0.0 && dist.is_finite() => Ok(dist),
+            Cutoff::Weight(w) if w > 0.0 && w < kernel(0.0) => {
+                // Bracket the distance at which the weight drops below `w`
+                // then narrow it down by bisection
+                let (mut lo, mut hi) = (0.0, 1.0);
+                while kernel(hi) > w {
+                    lo = hi;
+                    hi *= 2.0;
+                    if !hi.is_finite() {
+                        return Err(format!("Invalid cutoff: {:?}", self).into());
+                    }
+                }
+                for _ in 0..100 {
+                    let mid = (lo + hi) / 2.0;
+                    if kernel(mid) > w {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                Ok(hi)
+            }
+            _ => Err(format!("Invalid cutoff: {:?}", self).into()),
+        }
+    }
+}
+
+/// Contribution dropped by the truncation of the kernel: the potential for
+/// Stewart, the sum of the weights for IDW.
+#[derive(Debug, Clone, Default)]
+pub struct TruncationReport {
+    /// Distance beyond which contributions were dropped.
+    pub cutoff_distance: f64,
+    /// Upper bound of the contribution dropped at any cell: the weight at the
+    /// cutoff distance times the sum of the absolute values of the observations
+    /// (Stewart) or times the number of observations (IDW).
+    pub dropped_bound: f64,
+    /// Largest and mean dropped contribution, relative to the full one,
+    /// measured on a sample of cells.
+    pub sampled_max_dropped: f64,
+    pub sampled_mean_dropped: f64,
+}
+
+/// Indexes of the cells (among `nb_cells`) on which the dropped contribution is measured.
+pub fn sample_cells(nb_cells: usize) -> Vec<usize> {
+    let step = (nb_cells / NB_SAMPLES).max(1);
+    (0..nb_cells).step_by(step).take(NB_SAMPLES).collect()
+}
+
+/// Build the report from the (full, kept) contributions measured on the sampled cells.
+pub fn truncation_report(cutoff_distance: f64,
+                         dropped_bound: f64,
+                         samples: &[(f64, f64)])
+                         -> TruncationReport {
+    let (mut max, mut sum) = (0.0f64, 0.0);
+    for &(full, kept) in samples {
+        let dropped = if full != 0.0 {
+            ((full - kept) / full).abs()
+        } else {
+            0.0
+        };
+        max = max.max(dropped);
+        sum += dropped;
+    }
+    TruncationReport {
+        cutoff_distance: cutoff_distance,
+        dropped_bound: dropped_bound,
+        sampled_max_dropped: max,
+        sampled_mean_dropped: if samples.is_empty() {
+            0.0
+        } else {
+            sum / samples.len() as f64
+        },
+    }
+}
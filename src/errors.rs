@@ -6,4 +6,39 @@ error_chain! {
         ParseFloatError(::std::num::ParseFloatError);
         GeoJsonError(::geojson::Error);
     }
+
+    errors {
+        UnknownMethod(name: String) {
+            description("unknown method")
+            display("Unknown method or function name: '{}'", name)
+        }
+        SingularSystem {
+            description("singular system")
+            display("The interpolation system is singular (duplicated observation points?)")
+        }
+        MissingField(name: String) {
+            description("missing field")
+            display("Missing field: '{}'", name)
+        }
+        EmptyInput {
+            description("empty input")
+            display("No observation points to interpolate from")
+        }
+        InvalidBbox(min_x: f64, max_x: f64, min_y: f64, max_y: f64) {
+            description("invalid bounding box")
+            display("Invalid bounding box: ({}, {}, {}, {})", min_x, max_x, min_y, max_y)
+        }
+        InvalidResolution(reso_x: usize, reso_y: usize) {
+            description("invalid resolution")
+            display("Invalid resolution: {}x{}", reso_x, reso_y)
+        }
+        DimensionMismatch(expected: usize, found: usize) {
+            description("dimension mismatch")
+            display("Dimension mismatch: expected {} values, found {}", expected, found)
+        }
+        NoConvergence(iterations: usize) {
+            description("no convergence")
+            display("The iterative solver did not converge after {} iterations", iterations)
+        }
+    }
 }
@@ -0,0 +1,153 @@
+//! Fast Fourier transform and FFT-based convolution on regular grids.
+use std::f64::consts::PI;
+
+
+/// In-place iterative radix-2 FFT of the complex sequence given by its real
+/// and imaginary parts, whose length must be a power of two.
+/// The inverse transform is not normalized.
+pub fn fft(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let n = re.len();
+    debug_assert!(n.is_power_of_two() && im.len() == n);
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * PI / len as f64;
+        let half = len / 2;
+        for start in (0..n).step_by(len) {
+            for k in 0..half {
+                let (w_im, w_re) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + half);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// In-place 2D FFT of a `n0` by `n1` complex array stored as `[i0 * n1 + i1]`.
+pub fn fft2d(re: &mut [f64], im: &mut [f64], n0: usize, n1: usize, inverse: bool) {
+    for row in 0..n0 {
+        let range = row * n1..(row + 1) * n1;
+        fft(&mut re[range.clone()], &mut im[range], inverse);
+    }
+    let (mut col_re, mut col_im) = (vec![0.0; n0], vec![0.0; n0]);
+    for col in 0..n1 {
+        for row in 0..n0 {
+            col_re[row] = re[row * n1 + col];
+            col_im[row] = im[row * n1 + col];
+        }
+        fft(&mut col_re, &mut col_im, inverse);
+        for row in 0..n0 {
+            re[row * n1 + col] = col_re[row];
+            im[row * n1 + col] = col_im[row];
+        }
+    }
+}
+
+/// Sum of the contributions of the weighted points `obs` (as (x, y, value)
+/// triplets) at each node of a regular grid of `reso` nodes, starting at
+/// `origin` and spaced by `step`, for a radial `kernel` of the euclidean
+/// distance. The result is stored as `[i * reso.1 + j]`.
+///
+/// Each point is spread on the four surrounding nodes (bilinear weights) of
+/// the grid, extended if needed to cover all the points, which is then
+/// convolved with the kernel. Returns `None` if the extended grid would
+/// exceed `max_extent` times the size of the output grid in a dimension, or
+/// if the transforms would have more than `max_nodes` nodes (checked before
+/// allocating the four arrays of that size they need).
+pub fn grid_convolution<K>(obs: &[(f64, f64, f64)],
+                           origin: (f64, f64),
+                           step: (f64, f64),
+                           reso: (usize, usize),
+                           max_extent: usize,
+                           max_nodes: usize,
+                           kernel: K)
+                           -> Option<Vec<f64>>
+    where K: Fn(f64) -> f64
+{
+    let (reso_x, reso_y) = reso;
+    // Fractional grid coordinates of the points and bounds of the extended grid
+    let coords = obs.iter()
+        .map(|&(x, y, v)| ((x - origin.0) / step.0, (y - origin.1) / step.1, v))
+        .collect::<Vec<(f64, f64, f64)>>();
+    let (mut lo_x, mut hi_x) = (0i64, reso_x as i64 - 1);
+    let (mut lo_y, mut hi_y) = (0i64, reso_y as i64 - 1);
+    for &(u, v, _) in &coords {
+        if !(u.is_finite() && v.is_finite()) {
+            return None;
+        }
+        lo_x = lo_x.min(u.floor() as i64);
+        hi_x = hi_x.max(u.floor() as i64 + 1);
+        lo_y = lo_y.min(v.floor() as i64);
+        hi_y = hi_y.max(v.floor() as i64 + 1);
+    }
+    let (src_x, src_y) = ((hi_x - lo_x + 1) as usize, (hi_y - lo_y + 1) as usize);
+    if src_x > max_extent * reso_x || src_y > max_extent * reso_y {
+        return None;
+    }
+    // Offsets between an output node and a source node range over
+    // [-(src - 1), reso - 1 - lo]; the transforms are large enough for
+    // the circular convolution not to wrap around.
+    let n0 = (src_x + reso_x + (-lo_x) as usize).checked_next_power_of_two()?;
+    let n1 = (src_y + reso_y + (-lo_y) as usize).checked_next_power_of_two()?;
+    if n0.checked_mul(n1).map_or(true, |nodes| nodes > max_nodes) {
+        return None;
+    }
+    let (mut src_re, mut src_im) = (vec![0.0; n0 * n1], vec![0.0; n0 * n1]);
+    for &(u, v, val) in &coords {
+        let (fu, fv) = (u.floor(), v.floor());
+        let (wu, wv) = (u - fu, v - fv);
+        let (p, q) = ((fu as i64 - lo_x) as usize, (fv as i64 - lo_y) as usize);
+        src_re[p * n1 + q] += val * (1.0 - wu) * (1.0 - wv);
+        src_re[(p + 1) * n1 + q] += val * wu * (1.0 - wv);
+        src_re[p * n1 + q + 1] += val * (1.0 - wu) * wv;
+        src_re[(p + 1) * n1 + q + 1] += val * wu * wv;
+    }
+    let (mut ker_re, mut ker_im) = (vec![0.0; n0 * n1], vec![0.0; n0 * n1]);
+    let (max_a, max_b) = (reso_x as i64 - 1 - lo_x, reso_y as i64 - 1 - lo_y);
+    for a in -(src_x as i64 - 1)..max_a + 1 {
+        let ia = ((a + n0 as i64) as usize) % n0;
+        let dx = a as f64 * step.0;
+        for b in -(src_y as i64 - 1)..max_b + 1 {
+            let ib = ((b + n1 as i64) as usize) % n1;
+            let dy = b as f64 * step.1;
+            ker_re[ia * n1 + ib] = kernel((dx * dx + dy * dy).sqrt());
+        }
+    }
+    fft2d(&mut src_re, &mut src_im, n0, n1, false);
+    fft2d(&mut ker_re, &mut ker_im, n0, n1, false);
+    for ix in 0..n0 * n1 {
+        let (a_re, a_im, b_re, b_im) = (src_re[ix], src_im[ix], ker_re[ix], ker_im[ix]);
+        src_re[ix] = a_re * b_re - a_im * b_im;
+        src_im[ix] = a_re * b_im + a_im * b_re;
+    }
+    fft2d(&mut src_re, &mut src_im, n0, n1, true);
+    let norm = (n0 * n1) as f64;
+    let mut res = Vec::with_capacity(reso_x * reso_y);
+    for i in 0..reso_x {
+        let k = (i as i64 - lo_x) as usize;
+        for j in 0..reso_y {
+            let l = (j as i64 - lo_y) as usize;
+            res.push(src_re[k * n1 + l] / norm);
+        }
+    }
+    Some(res)
+}
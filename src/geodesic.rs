@@ -0,0 +1,571 @@
+//! Distance along the geodesics of the WGS84 ellipsoid, with the algorithm of
+//! C. F. F. Karney, "Algorithms for geodesics", J. Geodesy 87 (2013), as
+//! implemented in GeographicLib (series to the sixth order in the third
+//! flattening): accurate to 15 nm, and converging for any pair of points,
+//! including nearly antipodal ones (unlike Vincenty's method).
+use std::f64::consts::PI;
+use std::f64;
+
+
+const ORDER: usize = 6;
+const NC3X: usize = (ORDER * (ORDER - 1)) / 2;
+const MAXIT1: usize = 20;
+const MAXIT2: usize = MAXIT1 + 53 + 10;
+
+const WGS84_A: f64 = 6378137.0;
+const WGS84_F: f64 = 1.0 / 298.257223563;
+
+thread_local! {
+    static WGS84: Geodesic = Geodesic::new(WGS84_A, WGS84_F);
+}
+
+/// Length (in metres) of the shortest geodesic between (lon1, lat1) and
+/// (lon2, lat2) (in degrees) on the WGS84 ellipsoid.
+pub fn wgs84_distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    WGS84.with(|geod| geod.distance(lat1, lon1, lat2, lon2))
+}
+
+fn tiny() -> f64 {
+    f64::MIN_POSITIVE.sqrt()
+}
+
+fn tol0() -> f64 {
+    f64::EPSILON
+}
+
+fn tol1() -> f64 {
+    200.0 * tol0()
+}
+
+fn tol2() -> f64 {
+    tol0().sqrt()
+}
+
+fn tolb() -> f64 {
+    tol0() * tol2()
+}
+
+fn xthresh() -> f64 {
+    1000.0 * tol2()
+}
+
+fn sq(x: f64) -> f64 {
+    x * x
+}
+
+/// (s, t) with s = round(u + v) and u + v = s + t exactly.
+fn sum(u: f64, v: f64) -> (f64, f64) {
+    let s = u + v;
+    let up = s - v;
+    let vpp = s - up;
+    (s, -((up - u) + (vpp - v)))
+}
+
+/// Polynomial of degree `n` of coefficients `p[s..s + n + 1]` (highest first) at `x`.
+fn polyval(n: usize, p: &[f64], s: usize, x: f64) -> f64 {
+    p[s + 1..s + n + 1].iter().fold(p[s], |y, &c| y * x + c)
+}
+
+fn ang_normalize(x: f64) -> f64 {
+    let y = x % 360.0;
+    let y = if y < -180.0 {
+        y + 360.0
+    } else if y > 180.0 {
+        y - 360.0
+    } else {
+        y
+    };
+    if y == -180.0 { 180.0 } else { y }
+}
+
+/// Exact difference y - x, reduced to [-180, 180], as (difference, error).
+fn ang_diff(x: f64, y: f64) -> (f64, f64) {
+    let (d, t) = sum(ang_normalize(-x), ang_normalize(y));
+    let d = ang_normalize(d);
+    sum(if d == 180.0 && t > 0.0 { -180.0 } else { d }, t)
+}
+
+/// Round tiny angles, to avoid underflows.
+fn ang_round(x: f64) -> f64 {
+    let z = 1.0 / 16.0;
+    if x == 0.0 {
+        return 0.0;
+    }
+    let y = x.abs();
+    let y = if y < z { z - (z - y) } else { y };
+    if x < 0.0 { -y } else { y }
+}
+
+/// Sine and cosine of `x` (in degrees), exact for multiples of 90.
+fn sincosd(x: f64) -> (f64, f64) {
+    let r = x % 360.0;
+    let q = if r.is_nan() { 0 } else { (r / 90.0).round() as i64 };
+    let r = (r - 90.0 * q as f64).to_radians();
+    let (s, c) = (r.sin(), r.cos());
+    let (s, c) = match (q % 4 + 4) % 4 {
+        1 => (c, -s),
+        2 => (-s, -c),
+        3 => (-c, s),
+        _ => (s, c),
+    };
+    (if x == 0.0 { x } else { s }, c + 0.0)
+}
+
+fn norm(x: f64, y: f64) -> (f64, f64) {
+    let r = x.hypot(y);
+    (x / r, y / r)
+}
+
+/// Sum of `c[l] * sin(2 l x)` for l in 1..c.len(), by Clenshaw summation.
+fn sin_series(sinx: f64, cosx: f64, c: &[f64]) -> f64 {
+    let mut k = c.len();
+    let mut n = k - 1;
+    let ar = 2.0 * (cosx - sinx) * (cosx + sinx);
+    let mut y1 = 0.0;
+    let mut y0 = if n & 1 == 1 {
+        k -= 1;
+        c[k]
+    } else {
+        0.0
+    };
+    n /= 2;
+    while n > 0 {
+        n -= 1;
+        k -= 1;
+        y1 = ar * y0 - y1 + c[k];
+        k -= 1;
+        y0 = ar * y1 - y0 + c[k];
+    }
+    2.0 * sinx * cosx * y0
+}
+
+/// Fill `c[1..]` with the coefficients of the series of `coeff`, in powers of `eps`.
+fn fill_series(coeff: &[f64], eps: f64, c: &mut [f64]) {
+    let eps2 = sq(eps);
+    let mut d = eps;
+    let mut o = 0;
+    for l in 1..ORDER + 1 {
+        let m = (ORDER - l) / 2;
+        c[l] = d * polyval(m, coeff, o, eps2) / coeff[o + m + 1];
+        o += m + 2;
+        d *= eps;
+    }
+}
+
+fn a1m1f(eps: f64) -> f64 {
+    let coeff = [1.0, 4.0, 64.0, 0.0, 256.0];
+    let m = ORDER / 2;
+    let t = polyval(m, &coeff, 0, sq(eps)) / coeff[m + 1];
+    (t + eps) / (1.0 - eps)
+}
+
+fn c1f(eps: f64, c: &mut [f64]) {
+    let coeff = [-1.0, 6.0, -16.0, 32.0, -9.0, 64.0, -128.0, 2048.0, 9.0, -16.0, 768.0, 3.0,
+                 -5.0, 512.0, -7.0, 1280.0, -7.0, 2048.0];
+    fill_series(&coeff, eps, c);
+}
+
+fn a2m1f(eps: f64) -> f64 {
+    let coeff = [-11.0, -28.0, -192.0, 0.0, 256.0];
+    let m = ORDER / 2;
+    let t = polyval(m, &coeff, 0, sq(eps)) / coeff[m + 1];
+    (t - eps) / (1.0 + eps)
+}
+
+fn c2f(eps: f64, c: &mut [f64]) {
+    let coeff = [1.0, 2.0, 16.0, 32.0, 35.0, 64.0, 384.0, 2048.0, 15.0, 80.0, 768.0, 7.0, 35.0,
+                 512.0, 63.0, 1280.0, 77.0, 2048.0];
+    fill_series(&coeff, eps, c);
+}
+
+/// Solution for k of the astroid equation, used for nearly antipodal points.
+fn astroid(x: f64, y: f64) -> f64 {
+    let (p, q) = (sq(x), sq(y));
+    let r = (p + q - 1.0) / 6.0;
+    if q == 0.0 && r <= 0.0 {
+        return 0.0;
+    }
+    let s = p * q / 4.0;
+    let (r2, r3) = (sq(r), r * sq(r));
+    let disc = s * (s + 2.0 * r3);
+    let mut u = r;
+    if disc >= 0.0 {
+        let mut t3 = s + r3;
+        t3 += if t3 < 0.0 { -disc.sqrt() } else { disc.sqrt() };
+        let t = t3.cbrt();
+        u += t + if t != 0.0 { r2 / t } else { 0.0 };
+    } else {
+        let ang = (-disc).sqrt().atan2(-(s + r3));
+        u += 2.0 * r * (ang / 3.0).cos();
+    }
+    let v = (sq(u) + q).sqrt();
+    let uv = if u < 0.0 { q / (v - u) } else { u + v };
+    let w = (uv - q) / (2.0 * v);
+    uv / ((uv + sq(w)).sqrt() + w)
+}
+
+struct Geodesic {
+    a: f64,
+    f: f64,
+    f1: f64,
+    ep2: f64,
+    n: f64,
+    b: f64,
+    etol2: f64,
+    a3x: [f64; ORDER],
+    c3x: [f64; NC3X],
+}
+
+/// Results of the integration along a geodesic.
+struct Segment {
+    sig12: f64,
+    ssig1: f64,
+    csig1: f64,
+    ssig2: f64,
+    csig2: f64,
+    eps: f64,
+}
+
+impl Geodesic {
+    fn new(a: f64, f: f64) -> Self {
+        let f1 = 1.0 - f;
+        let e2 = f * (2.0 - f);
+        let n = f / (2.0 - f);
+        let mut geod = Geodesic {
+            a: a,
+            f: f,
+            f1: f1,
+            ep2: e2 / sq(f1),
+            n: n,
+            b: a * f1,
+            etol2: 0.1 * tol2() / ((f.abs().max(0.001) * (1.0 - f / 2.0).min(1.0) / 2.0).sqrt()),
+            a3x: [0.0; ORDER],
+            c3x: [0.0; NC3X],
+        };
+        let coeff = [-3.0, 128.0, -2.0, -3.0, 64.0, -1.0, -3.0, -1.0, 16.0, 3.0, -1.0, -2.0, 8.0,
+                     1.0, -1.0, 2.0, 1.0, 1.0];
+        let (mut o, mut k) = (0, 0);
+        for j in (0..ORDER).rev() {
+            let m = (ORDER - j - 1).min(j);
+            geod.a3x[k] = polyval(m, &coeff, o, n) / coeff[o + m + 1];
+            k += 1;
+            o += m + 2;
+        }
+        let coeff = [3.0, 128.0, 2.0, 5.0, 128.0, -1.0, 3.0, 3.0, 64.0, -1.0, 0.0, 1.0, 8.0, -1.0,
+                     1.0, 4.0, 5.0, 256.0, 1.0, 3.0, 128.0, -3.0, -2.0, 3.0, 64.0, 1.0, -3.0, 2.0,
+                     32.0, 7.0, 512.0, -10.0, 9.0, 384.0, 5.0, -9.0, 5.0, 192.0, 7.0, 512.0,
+                     -14.0, 7.0, 512.0, 21.0, 2560.0];
+        let (mut o, mut k) = (0, 0);
+        for l in 1..ORDER {
+            for j in (l..ORDER).rev() {
+                let m = (ORDER - j - 1).min(j);
+                geod.c3x[k] = polyval(m, &coeff, o, n) / coeff[o + m + 1];
+                k += 1;
+                o += m + 2;
+            }
+        }
+        geod
+    }
+
+    fn a3f(&self, eps: f64) -> f64 {
+        polyval(ORDER - 1, &self.a3x, 0, eps)
+    }
+
+    fn c3f(&self, eps: f64, c: &mut [f64]) {
+        let (mut mult, mut o) = (1.0, 0);
+        for l in 1..ORDER {
+            let m = ORDER - l - 1;
+            mult *= eps;
+            c[l] = mult * polyval(m, &self.c3x, o, eps);
+            o += m + 1;
+        }
+    }
+
+    /// Distance and reduced length (for a unit minor axis) of a segment.
+    fn lengths(&self, seg: &Segment, dn1: f64, dn2: f64) -> (f64, f64) {
+        let (mut c1a, mut c2a) = ([0.0; ORDER + 1], [0.0; ORDER + 1]);
+        let a1 = a1m1f(seg.eps);
+        c1f(seg.eps, &mut c1a);
+        let a2 = a2m1f(seg.eps);
+        c2f(seg.eps, &mut c2a);
+        let m0x = a1 - a2;
+        let (a1, a2) = (1.0 + a1, 1.0 + a2);
+        let b1 = sin_series(seg.ssig2, seg.csig2, &c1a) -
+                 sin_series(seg.ssig1, seg.csig1, &c1a);
+        let b2 = sin_series(seg.ssig2, seg.csig2, &c2a) -
+                 sin_series(seg.ssig1, seg.csig1, &c2a);
+        let j12 = m0x * seg.sig12 + (a1 * b1 - a2 * b2);
+        let m12b = dn2 * (seg.csig1 * seg.ssig2) - dn1 * (seg.ssig1 * seg.csig2) -
+                   seg.csig1 * seg.csig2 * j12;
+        (a1 * (seg.sig12 + b1), m12b)
+    }
+
+    /// First guess of the azimuth at the first point, as (sig12, salp1, calp1, dnm),
+    /// sig12 being the solution (and dnm set) for short lines, negative otherwise.
+    fn inverse_start(&self,
+                     sbet1: f64,
+                     cbet1: f64,
+                     sbet2: f64,
+                     cbet2: f64,
+                     lam12: f64,
+                     slam12: f64,
+                     clam12: f64)
+                     -> (f64, f64, f64, f64) {
+        let mut sig12 = -1.0;
+        let mut dnm = f64::NAN;
+        let sbet12 = sbet2 * cbet1 - cbet2 * sbet1;
+        let cbet12 = cbet2 * cbet1 + sbet2 * sbet1;
+        let sbet12a = sbet2 * cbet1 + cbet2 * sbet1;
+        let shortline = cbet12 >= 0.0 && sbet12 < 0.5 && cbet2 * lam12 < 0.5;
+        let (mut somg12, mut comg12) = (slam12, clam12);
+        if shortline {
+            let mut sbetm2 = sq(sbet1 + sbet2);
+            sbetm2 /= sbetm2 + sq(cbet1 + cbet2);
+            dnm = (1.0 + self.ep2 * sbetm2).sqrt();
+            let omg12 = lam12 / (self.f1 * dnm);
+            somg12 = omg12.sin();
+            comg12 = omg12.cos();
+        }
+        let mut salp1 = cbet2 * somg12;
+        let mut calp1 = if comg12 >= 0.0 {
+            sbet12 + cbet2 * sbet1 * sq(somg12) / (1.0 + comg12)
+        } else {
+            sbet12a - cbet2 * sbet1 * sq(somg12) / (1.0 - comg12)
+        };
+        let ssig12 = salp1.hypot(calp1);
+        let csig12 = sbet1 * sbet2 + cbet1 * cbet2 * comg12;
+        if shortline && ssig12 < self.etol2 {
+            sig12 = ssig12.atan2(csig12);
+        } else if self.n.abs() >= 0.1 || csig12 >= 0.0 ||
+                  ssig12 >= 6.0 * self.n.abs() * PI * sq(cbet1) {
+            // Zeroth order spherical approximation is fine
+        } else {
+            // Nearly antipodal points
+            let lam12x = (-slam12).atan2(-clam12);
+            let k2 = sq(sbet1) * self.ep2;
+            let eps = k2 / (2.0 * (1.0 + (1.0 + k2).sqrt()) + k2);
+            let lamscale = self.f * cbet1 * self.a3f(eps) * PI;
+            let betscale = lamscale * cbet1;
+            let (x, y) = (lam12x / lamscale, sbet12a / betscale);
+            if y > -tol1() && x > -1.0 - xthresh() {
+                salp1 = (-x).min(1.0);
+                calp1 = -(1.0 - sq(salp1)).sqrt();
+            } else {
+                let k = astroid(x, y);
+                let omg12a = lamscale * (-x * k / (1.0 + k));
+                somg12 = omg12a.sin();
+                comg12 = -omg12a.cos();
+                salp1 = cbet2 * somg12;
+                calp1 = sbet12a - cbet2 * sbet1 * sq(somg12) / (1.0 - comg12);
+            }
+        }
+        if salp1 > 0.0 {
+            let (s, c) = norm(salp1, calp1);
+            salp1 = s;
+            calp1 = c;
+        } else {
+            salp1 = 1.0;
+            calp1 = 0.0;
+        }
+        (sig12, salp1, calp1, dnm)
+    }
+
+    /// Difference between the longitude reached by the geodesic of azimuth
+    /// alp1 and the target one, with its derivative (if `diffp`).
+    fn lambda12(&self,
+                sbet1: f64,
+                cbet1: f64,
+                dn1: f64,
+                sbet2: f64,
+                cbet2: f64,
+                dn2: f64,
+                salp1: f64,
+                calp1: f64,
+                slam120: f64,
+                clam120: f64,
+                diffp: bool)
+                -> (f64, Segment, f64) {
+        let calp1 = if sbet1 == 0.0 && calp1 == 0.0 {
+            -tiny()
+        } else {
+            calp1
+        };
+        let salp0 = salp1 * cbet1;
+        let calp0 = calp1.hypot(salp1 * sbet1);
+        let somg1 = salp0 * sbet1;
+        let comg1 = calp1 * cbet1;
+        let (ssig1, csig1) = norm(sbet1, comg1);
+        let calp2 = if cbet2 != cbet1 || sbet2.abs() != -sbet1 {
+            let dbet = if cbet1 < -sbet1 {
+                (cbet2 - cbet1) * (cbet1 + cbet2)
+            } else {
+                (sbet1 - sbet2) * (sbet1 + sbet2)
+            };
+            (sq(calp1 * cbet1) + dbet).sqrt() / cbet2
+        } else {
+            calp1.abs()
+        };
+        let somg2 = salp0 * sbet2;
+        let comg2 = calp2 * cbet2;
+        let (ssig2, csig2) = norm(sbet2, comg2);
+        let sig12 = (csig1 * ssig2 - ssig1 * csig2)
+            .max(0.0)
+            .atan2(csig1 * csig2 + ssig1 * ssig2);
+        let somg12 = (comg1 * somg2 - somg1 * comg2).max(0.0);
+        let comg12 = comg1 * comg2 + somg1 * somg2;
+        let eta = (somg12 * clam120 - comg12 * slam120)
+            .atan2(comg12 * clam120 + somg12 * slam120);
+        let k2 = sq(calp0) * self.ep2;
+        let eps = k2 / (2.0 * (1.0 + (1.0 + k2).sqrt()) + k2);
+        let mut c3a = [0.0; ORDER];
+        self.c3f(eps, &mut c3a);
+        let b312 = sin_series(ssig2, csig2, &c3a) -
+                   sin_series(ssig1, csig1, &c3a);
+        let domg12 = -self.f * self.a3f(eps) * salp0 * (sig12 + b312);
+        let seg = Segment {
+            sig12: sig12,
+            ssig1: ssig1,
+            csig1: csig1,
+            ssig2: ssig2,
+            csig2: csig2,
+            eps: eps,
+        };
+        let dlam12 = if !diffp {
+            f64::NAN
+        } else if calp2 == 0.0 {
+            -2.0 * self.f1 * dn1 / sbet1
+        } else {
+            self.lengths(&seg, dn1, dn2).1 * self.f1 / (calp2 * cbet2)
+        };
+        (eta + domg12, seg, dlam12)
+    }
+
+    /// Length of the geodesic between (lat1, lon1) and (lat2, lon2).
+    fn distance(&self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        if lat1.abs() > 90.0 || lat2.abs() > 90.0 {
+            return f64::NAN;
+        }
+        let (lon12, lon12s) = ang_diff(lon1, lon2);
+        let lonsign = if lon12 >= 0.0 { 1.0 } else { -1.0 };
+        let lon12 = lonsign * ang_round(lon12);
+        let lon12s = ang_round((180.0 - lon12) - lonsign * lon12s);
+        let lam12 = lon12.to_radians();
+        let (slam12, clam12) = if lon12 > 90.0 {
+            let (s, c) = sincosd(lon12s);
+            (s, -c)
+        } else {
+            sincosd(lon12)
+        };
+        let (mut lat1, mut lat2) = (ang_round(lat1), ang_round(lat2));
+        if lat1.abs() < lat2.abs() {
+            ::std::mem::swap(&mut lat1, &mut lat2);
+        }
+        let latsign = if lat1 < 0.0 { 1.0 } else { -1.0 };
+        lat1 *= latsign;
+        lat2 *= latsign;
+        let (sbet1, cbet1) = sincosd(lat1);
+        let (sbet1, cbet1) = norm(self.f1 * sbet1, cbet1);
+        let cbet1 = cbet1.max(tiny());
+        let (sbet2, cbet2) = sincosd(lat2);
+        let (mut sbet2, cbet2) = norm(self.f1 * sbet2, cbet2);
+        let mut cbet2 = cbet2.max(tiny());
+        if cbet1 < -sbet1 {
+            if cbet2 == cbet1 {
+                sbet2 = if sbet2 < 0.0 { -sbet1.abs() } else { sbet1.abs() };
+            }
+        } else if sbet2.abs() == -sbet1 {
+            cbet2 = cbet1;
+        }
+        let dn1 = (1.0 + self.ep2 * sq(sbet1)).sqrt();
+        let dn2 = (1.0 + self.ep2 * sq(sbet2)).sqrt();
+
+        if lat1 == -90.0 || slam12 == 0.0 {
+            // Along a meridian
+            let ssig1 = sbet1;
+            let csig1 = clam12 * cbet1;
+            let (ssig2, csig2) = (sbet2, cbet2);
+            let sig12 = (csig1 * ssig2 - ssig1 * csig2)
+                .max(0.0)
+                .atan2(csig1 * csig2 + ssig1 * ssig2);
+            let seg = Segment {
+                sig12: sig12,
+                ssig1: ssig1,
+                csig1: csig1,
+                ssig2: ssig2,
+                csig2: csig2,
+                eps: self.n,
+            };
+            let (s12x, m12x) = self.lengths(&seg, dn1, dn2);
+            if sig12 < 1.0 || m12x >= 0.0 {
+                if sig12 < 3.0 * tiny() || (sig12 < tol0() && (s12x < 0.0 || m12x < 0.0)) {
+                    return 0.0;
+                }
+                return s12x * self.b;
+            }
+        }
+
+        if sbet1 == 0.0 && (self.f <= 0.0 || lon12s >= self.f * 180.0) {
+            // Along the equator
+            return self.a * lam12;
+        }
+
+        let (sig12, mut salp1, mut calp1, dnm) =
+            self.inverse_start(sbet1, cbet1, sbet2, cbet2, lam12, slam12, clam12);
+        if sig12 >= 0.0 {
+            // Short line
+            return sig12 * self.b * dnm;
+        }
+        // Solve for the azimuth at the first point by Newton's method,
+        // falling back to bisection
+        let (mut salp1a, mut calp1a) = (tiny(), 1.0);
+        let (mut salp1b, mut calp1b) = (tiny(), -1.0);
+        let (mut tripn, mut tripb) = (false, false);
+        let mut numit = 0;
+        let mut seg;
+        loop {
+            let (v, s, dv) = self.lambda12(sbet1,
+                                           cbet1,
+                                           dn1,
+                                           sbet2,
+                                           cbet2,
+                                           dn2,
+                                           salp1,
+                                           calp1,
+                                           slam12,
+                                           clam12,
+                                           numit < MAXIT1);
+            seg = s;
+            if numit >= MAXIT2 || tripb ||
+               !(v.abs() >= if tripn { 8.0 } else { 1.0 } * tol0()) {
+                break;
+            }
+            if v > 0.0 && (numit > MAXIT1 || calp1 / salp1 > calp1b / salp1b) {
+                salp1b = salp1;
+                calp1b = calp1;
+            } else if v < 0.0 && (numit > MAXIT1 || calp1 / salp1 < calp1a / salp1a) {
+                salp1a = salp1;
+                calp1a = calp1;
+            }
+            numit += 1;
+            if numit < MAXIT1 && dv > 0.0 {
+                let dalp1 = -v / dv;
+                let (sdalp1, cdalp1) = (dalp1.sin(), dalp1.cos());
+                let nsalp1 = salp1 * cdalp1 + calp1 * sdalp1;
+                if nsalp1 > 0.0 && dalp1.abs() < PI {
+                    let (s, c) = norm(nsalp1, calp1 * cdalp1 - salp1 * sdalp1);
+                    salp1 = s;
+                    calp1 = c;
+                    tripn = v.abs() <= 16.0 * tol0();
+                    continue;
+                }
+            }
+            let (s, c) = norm((salp1a + salp1b) / 2.0, (calp1a + calp1b) / 2.0);
+            salp1 = s;
+            calp1 = c;
+            tripn = false;
+            tripb = (salp1a - salp1).abs() + (calp1a - calp1) < tolb() ||
+                    (salp1 - salp1b).abs() + (calp1 - calp1b) < tolb();
+        }
+        self.lengths(&seg, dn1, dn2).0 * self.b
+    }
+}
@@ -0,0 +1,232 @@
+use geojson::Value;
+use std::cmp::Ordering;
+use serde_json;
+
+
+/// How a (multi)polygon feature is turned into an observation point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolygonRepresentation {
+    /// Area-weighted centroid (which may fall outside of concave polygons).
+    Centroid,
+    /// A point guaranteed to lie in the interior of the polygon.
+    PointOnSurface,
+}
+
+/// Signed area and centroid of a closed ring, given as a list of positions.
+fn ring_area_centroid(ring: &[Vec<f64>]) -> (f64, f64, f64) {
+    let (mut area, mut cx, mut cy) = (0.0, 0.0, 0.0);
+    if ring.len() < 3 {
+        return (0.0, 0.0, 0.0);
+    }
+    for i in 0..ring.len() {
+        let (a, b) = (&ring[i], &ring[(i + 1) % ring.len()]);
+        let cross = a[0] * b[1] - b[0] * a[1];
+        area += cross;
+        cx += (a[0] + b[0]) * cross;
+        cy += (a[1] + b[1]) * cross;
+    }
+    area /= 2.0;
+    if area == 0.0 {
+        (0.0, 0.0, 0.0)
+    } else {
+        (area, cx / (6.0 * area), cy / (6.0 * area))
+    }
+}
+
+fn vertices_mean(rings: &[Vec<Vec<f64>>]) -> Option<(f64, f64)> {
+    let (mut sx, mut sy, mut n) = (0.0, 0.0, 0);
+    for pos in rings.iter().flat_map(|ring| ring.iter()) {
+        sx += pos[0];
+        sy += pos[1];
+        n += 1;
+    }
+    if n == 0 {
+        None
+    } else {
+        Some((sx / n as f64, sy / n as f64))
+    }
+}
+
+/// Area-weighted centroid and (unsigned) area of a polygon given as
+/// an exterior ring followed by its holes.
+pub fn polygon_centroid(rings: &[Vec<Vec<f64>>]) -> Option<(f64, f64, f64)> {
+    let (mut area, mut cx, mut cy) = (0.0, 0.0, 0.0);
+    for (i, ring) in rings.iter().enumerate() {
+        let (ring_area, ring_cx, ring_cy) = ring_area_centroid(ring);
+        let a = if i == 0 {
+            ring_area.abs()
+        } else {
+            -ring_area.abs()
+        };
+        area += a;
+        cx += a * ring_cx;
+        cy += a * ring_cy;
+    }
+    if area > 0.0 {
+        Some((cx / area, cy / area, area))
+    } else {
+        vertices_mean(rings).map(|(x, y)| (x, y, 0.0))
+    }
+}
+
+/// Area-weighted centroid of a set of polygons.
+pub fn multipolygon_centroid(polygons: &[Vec<Vec<Vec<f64>>>]) -> Option<(f64, f64)> {
+    let (mut area, mut cx, mut cy) = (0.0, 0.0, 0.0);
+    let mut fallback = None;
+    for polygon in polygons {
+        if let Some((x, y, a)) = polygon_centroid(polygon) {
+            area += a;
+            cx += a * x;
+            cy += a * y;
+            if fallback.is_none() {
+                fallback = Some((x, y));
+            }
+        }
+    }
+    if area > 0.0 {
+        Some((cx / area, cy / area))
+    } else {
+        fallback
+    }
+}
+
+/// A point lying inside a polygon: the middle of the widest interior
+/// interval of the horizontal line crossing the polygon at mid-height.
+pub fn polygon_point_on_surface(rings: &[Vec<Vec<f64>>]) -> Option<(f64, f64)> {
+    let (mut min_y, mut max_y) = (::std::f64::INFINITY, ::std::f64::NEG_INFINITY);
+    for pos in rings.iter().flat_map(|ring| ring.iter()) {
+        min_y = min_y.min(pos[1]);
+        max_y = max_y.max(pos[1]);
+    }
+    if !(min_y < max_y) {
+        return vertices_mean(rings);
+    }
+    let y = (min_y + max_y) / 2.0;
+    let mut crossings = Vec::new();
+    for ring in rings {
+        for i in 0..ring.len() {
+            let (a, b) = (&ring[i], &ring[(i + 1) % ring.len()]);
+            // Half-open rule, so a vertex lying on the line is only counted once
+            if (a[1] > y) != (b[1] > y) {
+                crossings.push(a[0] + (y - a[1]) * (b[0] - a[0]) / (b[1] - a[1]));
+            }
+        }
+    }
+    crossings.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let mut best: Option<(f64, f64)> = None;
+    for pair in crossings.chunks(2) {
+        if pair.len() == 2 {
+            let width = pair[1] - pair[0];
+            if best.map_or(true, |(w, _)| width > w) {
+                best = Some((width, (pair[0] + pair[1]) / 2.0));
+            }
+        }
+    }
+    match best {
+        Some((_, x)) => Some((x, y)),
+        None => vertices_mean(rings),
+    }
+}
+
+fn position(pos: &[f64]) -> ::std::result::Result<(f64, f64), String> {
+    if pos.len() < 2 {
+        Err("invalid position".into())
+    } else {
+        Ok((pos[0], pos[1]))
+    }
+}
+
+/// Check that each position of the rings has (at least) two coordinates, as
+/// the polygon functions expect.
+fn check_rings(rings: &[Vec<Vec<f64>>]) -> ::std::result::Result<(), String> {
+    for pos in rings.iter().flat_map(|ring| ring.iter()) {
+        position(pos)?;
+    }
+    Ok(())
+}
+
+/// Positions of the observation point(s) representing a GeoJSON geometry,
+/// or the reason why the geometry can't be used.
+pub fn geometry_positions(value: &Value,
+                          polygon_repr: PolygonRepresentation)
+                          -> ::std::result::Result<Vec<(f64, f64)>, String> {
+    let positions = match *value {
+        Value::Point(ref pos) => vec![position(pos)?],
+        Value::MultiPoint(ref positions) => {
+            positions
+                .iter()
+                .map(|pos| position(pos))
+                .collect::<::std::result::Result<Vec<_>, _>>()?
+        }
+        Value::Polygon(ref rings) => {
+            check_rings(rings)?;
+            let pt = match polygon_repr {
+                PolygonRepresentation::Centroid => {
+                    polygon_centroid(rings).map(|(x, y, _)| (x, y))
+                }
+                PolygonRepresentation::PointOnSurface => polygon_point_on_surface(rings),
+            };
+            pt.into_iter().collect()
+        }
+        Value::MultiPolygon(ref polygons) => {
+            for rings in polygons {
+                check_rings(rings)?;
+            }
+            let pt = match polygon_repr {
+                PolygonRepresentation::Centroid => multipolygon_centroid(polygons),
+                PolygonRepresentation::PointOnSurface => {
+                    // Use the largest of the polygons
+                    polygons
+                        .iter()
+                        .filter_map(|p| polygon_centroid(p).map(|(_, _, area)| (area, p)))
+                        .fold(None, |best: Option<(f64, &Vec<Vec<Vec<f64>>>)>, (area, p)| {
+                            match best {
+                                Some((a, _)) if a >= area => best,
+                                _ => Some((area, p)),
+                            }
+                        })
+                        .and_then(|(_, p)| polygon_point_on_surface(p))
+                }
+            };
+            pt.into_iter().collect()
+        }
+        Value::LineString(_) => return Err("unsupported geometry type LineString".into()),
+        Value::MultiLineString(_) => {
+            return Err("unsupported geometry type MultiLineString".into())
+        }
+        Value::GeometryCollection(_) => {
+            return Err("unsupported geometry type GeometryCollection".into())
+        }
+    };
+    if positions.is_empty() {
+        Err("empty geometry".into())
+    } else {
+        Ok(positions)
+    }
+}
+
+/// Observation points (as (x, y, value) triplets) built from the geometry
+/// and the `field_name` property of a feature, or the reason why the
+/// feature has to be skipped.
+pub fn feature_observations(geometry: Option<&Value>,
+                            properties: Option<&serde_json::Map<String, serde_json::Value>>,
+                            field_name: &str,
+                            polygon_repr: PolygonRepresentation,
+                            split_multipoint_value: bool)
+                            -> ::std::result::Result<Vec<(f64, f64, f64)>, String> {
+    let geometry = geometry.ok_or_else(|| String::from("no geometry"))?;
+    let value = match properties.and_then(|prop| prop.get(field_name)) {
+        Some(&serde_json::Value::Number(ref val)) => val.as_f64(),
+        Some(&serde_json::Value::String(ref val)) => val.trim().parse::<f64>().ok(),
+        Some(_) => None,
+        None => return Err(format!("missing field \"{}\"", field_name)),
+    };
+    let value = value.ok_or_else(|| format!("invalid value for field \"{}\"", field_name))?;
+    let positions = geometry_positions(geometry, polygon_repr)?;
+    let value = if split_multipoint_value {
+        value / positions.len() as f64
+    } else {
+        value
+    };
+    Ok(positions.into_iter().map(|(x, y)| (x, y, value)).collect())
+}
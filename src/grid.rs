@@ -0,0 +1,170 @@
+//! Specification of the output grid: its extent, its number of cells and the
+//! location, in each cell, of the value computed for it.
+//!
+//! The grid functions take a `Bbox` divided in `reso_x` by `reso_y` cells and
+//! compute the value of each cell at its lower-left corner
+//! (`x = min_x + i * x_step`, `y = min_y + j * y_step`), so that the last
+//! column and row never reach `max_x` and `max_y`. A `GridSpec` gives the box
+//! to pass them (`sampling_bbox`) for the values to be computed at the
+//! corners or at the centres of its cells, and the extent covered by the
+//! cells (`extent`) to give to the raster writers.
+use std::path::Path;
+use gdal::raster::Dataset;
+use bbox::Bbox;
+use errors::*;
+
+
+/// Relative tolerance below which a number of cells is taken as a whole
+/// number (e.g. for extents computed in floating point).
+const CELL_COUNT_TOLERANCE: f64 = 1e-9;
+
+/// Location, in each cell of the grid, of the value computed for it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellPosition {
+    /// The lower-left corner of the cell.
+    Corner,
+    /// The centre of the cell (the convention of rasters).
+    Centre,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GridSpec {
+    /// Extent covered by the cells.
+    pub extent: Bbox,
+    pub reso_x: usize,
+    pub reso_y: usize,
+    pub position: CellPosition,
+}
+
+impl GridSpec {
+    /// Grid of `reso_x` by `reso_y` cells covering `extent`, the values being
+    /// computed at their lower-left corners.
+    pub fn new(extent: Bbox, reso_x: usize, reso_y: usize) -> Self {
+        GridSpec {
+            extent: extent,
+            reso_x: reso_x,
+            reso_y: reso_y,
+            position: CellPosition::Corner,
+        }
+    }
+
+    /// Grid of cells of `cell_x` by `cell_y` covering `bbox`, the values being
+    /// computed at their lower-left corners.
+    ///
+    /// Without `snap_origin`, the grid starts at the minimum corner of `bbox`
+    /// and its maximum edges are moved out to a whole number of cells. With it,
+    /// the edges of the grid are moved out to the nearest multiples of the cell
+    /// size from this origin (e.g. `(0.0, 0.0)` for the cells of a reference
+    /// grid to line up with it).
+    pub fn from_cell_size(bbox: &Bbox,
+                          cell_x: f64,
+                          cell_y: f64,
+                          snap_origin: Option<(f64, f64)>)
+                          -> Result<Self> {
+        if !(cell_x > 0.0 && cell_y > 0.0) || cell_x.is_infinite() || cell_y.is_infinite() {
+            return Err(format!("Invalid cell size: {}x{}", cell_x, cell_y).into());
+        }
+        if !(bbox.min_x.is_finite() && bbox.max_x.is_finite() && bbox.min_y.is_finite() &&
+             bbox.max_y.is_finite()) || bbox.min_x > bbox.max_x ||
+           bbox.min_y > bbox.max_y {
+            return Err(ErrorKind::InvalidBbox(bbox.min_x, bbox.max_x, bbox.min_y, bbox.max_y)
+                           .into());
+        }
+        let ((min_x, max_x, reso_x), (min_y, max_y, reso_y)) = match snap_origin {
+            Some((origin_x, origin_y)) => {
+                (snapped_edges(bbox.min_x, bbox.max_x, cell_x, origin_x)?,
+                 snapped_edges(bbox.min_y, bbox.max_y, cell_y, origin_y)?)
+            }
+            None => {
+                (snapped_edges(bbox.min_x, bbox.max_x, cell_x, bbox.min_x)?,
+                 snapped_edges(bbox.min_y, bbox.max_y, cell_y, bbox.min_y)?)
+            }
+        };
+        let spec = GridSpec::new(Bbox::new(min_x, max_x, min_y, max_y), reso_x, reso_y);
+        spec.check()?;
+        Ok(spec)
+    }
+
+    /// Grid of the pixels of the (north up) raster at `path`, the values being
+    /// computed at their centres. Written with its `extent`, the result has
+    /// the origin, size and pixel size of the template.
+    pub fn from_template(path: &str) -> Result<Self> {
+        let dataset = Dataset::open(Path::new(path))
+            .map_err(|e| Error::from(format!("Unable to open {}: {:?}", path, e)))?;
+        let (reso_x, reso_y) = dataset.size();
+        let transform = dataset
+            .geo_transform()
+            .map_err(|e| Error::from(format!("No geotransform in {}: {:?}", path, e)))?;
+        if transform[2] != 0.0 || transform[4] != 0.0 {
+            return Err(format!("Rotated rasters can't be used as a template ({})", path).into());
+        }
+        // The rasters are written north up, from the upper-left corner
+        if !(transform[1] > 0.0 && transform[5] < 0.0) {
+            return Err(format!("Only north up rasters (of positive pixel width and negative \
+                                pixel height) can be used as a template ({})",
+                               path)
+                               .into());
+        }
+        let (width, height) = (transform[1] * reso_x as f64, -transform[5] * reso_y as f64);
+        let extent = Bbox::new(transform[0],
+                               transform[0] + width,
+                               transform[3] - height,
+                               transform[3]);
+        let spec = GridSpec::new(extent, reso_x, reso_y).with_position(CellPosition::Centre);
+        spec.check()?;
+        Ok(spec)
+    }
+
+    pub fn with_position(mut self, position: CellPosition) -> Self {
+        self.position = position;
+        self
+    }
+
+    /// Check that the grid is usable.
+    pub fn check(&self) -> Result<()> {
+        self.extent.check_grid(self.reso_x, self.reso_y)
+    }
+
+    /// Size of the cells along x and y.
+    pub fn cell_size(&self) -> (f64, f64) {
+        ((self.extent.max_x - self.extent.min_x) / self.reso_x as f64,
+         (self.extent.max_y - self.extent.min_y) / self.reso_y as f64)
+    }
+
+    /// Box to give, with `reso_x` and `reso_y`, to the grid functions for the
+    /// values to be computed at the `position` of the cells.
+    pub fn sampling_bbox(&self) -> Bbox {
+        match self.position {
+            CellPosition::Corner => self.extent,
+            CellPosition::Centre => {
+                let (cell_x, cell_y) = self.cell_size();
+                Bbox::new(self.extent.min_x + cell_x / 2.0,
+                          self.extent.max_x + cell_x / 2.0,
+                          self.extent.min_y + cell_y / 2.0,
+                          self.extent.max_y + cell_y / 2.0)
+            }
+        }
+    }
+}
+
+/// Round `value` with `round`, unless it is a whole number up to the tolerance.
+fn whole(value: f64, round: fn(f64) -> f64) -> f64 {
+    let nearest = value.round();
+    if (value - nearest).abs() <= CELL_COUNT_TOLERANCE * nearest.abs().max(1.0) {
+        nearest
+    } else {
+        round(value)
+    }
+}
+
+/// Edges, at multiples of `cell` from `origin`, enclosing [`min`, `max`] and
+/// the number of cells between them (at least one).
+fn snapped_edges(min: f64, max: f64, cell: f64, origin: f64) -> Result<(f64, f64, usize)> {
+    let first = whole((min - origin) / cell, f64::floor);
+    let last = whole((max - origin) / cell, f64::ceil).max(first + 1.0);
+    let count = last - first;
+    if !(count < (1u64 << 52) as f64) {
+        return Err(format!("Too many cells of size {} between {} and {}", cell, min, max).into());
+    }
+    Ok((origin + first * cell, origin + last * cell, count as usize))
+}
@@ -1,61 +1,265 @@
 use errors::*;
+use costs::CostMatrix;
 use utils::{almost_equal, PtValue};
 use bbox::Bbox;
+use cutoff::{Cutoff, TruncationReport, sample_cells, truncation_report};
+use index::GridIndex;
+use parallel::grid_cells;
+use rayon::prelude::*;
+use tiled::{compute_tiled_batched, TileWriter};
+use kernel::{PointColumns, fill_cells, idw_means};
+use metric::{DistanceMetric, PointMetric};
 use std::f64;
+use std::marker::PhantomData;
 
 
-pub fn idw_interpolation1<T>(reso_x: u32,
-                             reso_y: u32,
+pub fn idw_interpolation1<T>(reso_x: usize,
+                             reso_y: usize,
                              bbox: &Bbox,
                              obs_points: &[T],
                              b: f64)
                              -> Result<Vec<T>>
+    where T: PtValue + Send + Sync
+{
+    idw_interpolation_with_metric(reso_x, reso_y, bbox, obs_points, b, &PointMetric::<T>::new())
+}
+
+/// Same as `idw_interpolation`, the distances being computed with `metric`.
+pub fn idw_interpolation_with_metric<T, M>(reso_x: usize,
+                                           reso_y: usize,
+                                           bbox: &Bbox,
+                                           obs_points: &[T],
+                                           b: f64,
+                                           metric: &M)
+                                           -> Result<Vec<T>>
+    where T: PtValue + Send + Sync,
+          M: DistanceMetric
+{
+    bbox.check_grid(reso_x, reso_y)?;
+    if obs_points.is_empty() {
+        return Err(ErrorKind::EmptyInput.into());
+    }
+    let idw = Idw::new(obs_points, b, metric);
+    let mut plots = grid_cells(bbox, reso_x, reso_y);
+    fill_cells(&mut plots, true, |cells, out| idw.interp_batch(cells, out));
+    Ok(plots)
+}
+
+/// IDW interpolation on a grid, computed by tiles of `tile_size` cells
+/// passed to `writer` (see `tiled::compute_tiled`).
+pub fn idw_tiled<T, W>(reso_x: usize,
+                       reso_y: usize,
+                       bbox: &Bbox,
+                       obs_points: &[T],
+                       b: f64,
+                       tile_size: usize,
+                       writer: &mut W)
+                       -> Result<()>
+    where T: PtValue + Sync,
+          W: TileWriter
+{
+    idw_tiled_with_metric(reso_x,
+                          reso_y,
+                          bbox,
+                          obs_points,
+                          b,
+                          &PointMetric::<T>::new(),
+                          tile_size,
+                          writer)
+}
+
+/// Same as `idw_tiled`, the distances being computed with `metric`.
+pub fn idw_tiled_with_metric<T, M, W>(reso_x: usize,
+                                      reso_y: usize,
+                                      bbox: &Bbox,
+                                      obs_points: &[T],
+                                      b: f64,
+                                      metric: &M,
+                                      tile_size: usize,
+                                      writer: &mut W)
+                                      -> Result<()>
+    where T: PtValue + Sync,
+          M: DistanceMetric,
+          W: TileWriter
+{
+    if obs_points.is_empty() {
+        return Err(ErrorKind::EmptyInput.into());
+    }
+    let idw = Idw::new(obs_points, b, metric);
+    compute_tiled_batched(bbox,
+                          reso_x,
+                          reso_y,
+                          tile_size,
+                          |cells, out| idw.interp_batch(cells, out),
+                          writer)
+}
+
+/// IDW interpolation at `targets`, the distances being read from `costs`
+/// (from the points to the targets) instead of being computed. Each target
+/// at a zero cost from a point takes its value, and the targets at an
+/// infinite cost from every point are set to NaN.
+pub fn idw_from_costs<T>(obs_points: &[T],
+                         targets: &[T],
+                         costs: &CostMatrix,
+                         b: f64)
+                         -> Result<Vec<T>>
+    where T: PtValue + Send + Sync
+{
+    if obs_points.is_empty() {
+        return Err(ErrorKind::EmptyInput.into());
+    }
+    costs.check_dimensions(obs_points.len(), targets.len())?;
+    Ok(targets
+           .par_iter()
+           .enumerate()
+           .map(|(t, target)| {
+                    let (x, y) = target.get_coordinates();
+                    T::new(x, y, idw_cost_mean(obs_points, costs, t, b))
+                })
+           .collect())
+}
+
+fn idw_cost_mean<T>(obs_points: &[T], costs: &CostMatrix, target: usize, b: f64) -> f64
     where T: PtValue
 {
-    let x_step = (bbox.max_x - bbox.min_x) / reso_x as f64;
-    let y_step = (bbox.max_y - bbox.min_y) / reso_y as f64;
-    let mut plots = Vec::with_capacity((reso_x * reso_y) as usize);
-    let idw = Idw::new(obs_points, b);
-    for i in 0..reso_x {
-        for j in 0..reso_y {
-            let x = bbox.min_x + x_step * i as f64;
-            let y = bbox.min_y + y_step * j as f64;
-            let val = idw.interp_point((x, y));
-            plots.push(T::new(x, y, val));
+    let (mut sum, mut sum_weights) = (0.0, 0.0);
+    for (p, pt) in obs_points.iter().enumerate() {
+        let cost = costs.get(p, target);
+        if cost == 0.0 {
+            return pt.get_value();
+        } else if cost.is_finite() {
+            let w = 1.0 / cost.powf(b);
+            sum += w * pt.get_value();
+            sum_weights += w;
         }
     }
-    Ok(plots)
+    if sum_weights > 0.0 {
+        sum / sum_weights
+    } else {
+        f64::NAN
+    }
+}
+
+/// IDW interpolation on a grid, only using the observation points within
+/// `cutoff` of each cell (the cells without any are set to NaN).
+///
+/// The points are looked up through a spatial index, so the cost depends on
+/// the number of points within the cutoff distance of each cell rather than
+/// on the total number of points.
+pub fn idw_truncated<T>(reso_x: usize,
+                        reso_y: usize,
+                        bbox: &Bbox,
+                        obs_points: &[T],
+                        b: f64,
+                        cutoff: Cutoff)
+                        -> Result<(Vec<T>, TruncationReport)>
+    where T: PtValue + Send + Sync
+{
+    idw_truncated_with_metric(reso_x,
+                              reso_y,
+                              bbox,
+                              obs_points,
+                              b,
+                              cutoff,
+                              &PointMetric::<T>::new())
+}
+
+/// Same as `idw_truncated`, the distances being computed with `metric`.
+pub fn idw_truncated_with_metric<T, M>(reso_x: usize,
+                                       reso_y: usize,
+                                       bbox: &Bbox,
+                                       obs_points: &[T],
+                                       b: f64,
+                                       cutoff: Cutoff,
+                                       metric: &M)
+                                       -> Result<(Vec<T>, TruncationReport)>
+    where T: PtValue + Send + Sync,
+          M: DistanceMetric
+{
+    bbox.check_grid(reso_x, reso_y)?;
+    if obs_points.is_empty() {
+        return Err(ErrorKind::EmptyInput.into());
+    }
+    let weight = |dist: f64| 1.0 / dist.powf(b);
+    let cutoff_dist = cutoff.distance(&weight)?;
+    let window = metric.search_window((bbox.min_x + bbox.max_x) / 2.0,
+                                      (bbox.min_y + bbox.max_y) / 2.0,
+                                      cutoff_dist);
+    let index = GridIndex::new(obs_points,
+                               (window.max_x - window.min_x, window.max_y - window.min_y));
+    let mut plots = grid_cells(bbox, reso_x, reso_y);
+    let sum_weights = plots
+        .par_iter_mut()
+        .map(|cell| {
+            let (x, y) = cell.get_coordinates();
+            let (mut zw, mut sw, mut exact) = (0.0, 0.0, None);
+            index.within(obs_points, metric, x, y, cutoff_dist, |ix, dist| {
+                let val = obs_points[ix].get_value();
+                if almost_equal(dist, 0.0, 1.0e-5) {
+                    if exact.is_none() {
+                        exact = Some(val);
+                    }
+                    return;
+                }
+                let w = weight(dist);
+                zw += w * val;
+                sw += w;
+            });
+            let val = match exact {
+                Some(val) => val,
+                None if sw > 0.0 => zw / sw,
+                None => f64::NAN,
+            };
+            cell.set_value(val);
+            sw
+        })
+        .collect::<Vec<f64>>();
+    let samples = sample_cells(plots.len())
+        .into_par_iter()
+        .map(|ix| {
+            let (x, y) = plots[ix].get_coordinates();
+            let full = obs_points
+                .iter()
+                .fold(0.0, |sum, pt| {
+                    let (px, py) = pt.get_coordinates();
+                    sum + weight(metric.distance(px, py, x, y))
+                });
+            (full, sum_weights[ix])
+        })
+        .collect::<Vec<(f64, f64)>>();
+    let report = truncation_report(cutoff_dist,
+                                   weight(cutoff_dist) * obs_points.len() as f64,
+                                   &samples);
+    Ok((plots, report))
 }
 
-pub struct Idw<'a, T: 'a> {
-    obs_points: &'a [T],
+pub struct Idw<'a, T: 'a, M: 'a> {
+    points: PointColumns,
     power: f64,
+    metric: &'a M,
+    obs_points: PhantomData<&'a [T]>,
 }
 
-impl<'a, T> Idw<'a, T>
-    where T: PtValue
+impl<'a, T, M> Idw<'a, T, M>
+    where T: PtValue,
+          M: DistanceMetric
 {
-    pub fn new(obs_points: &'a [T], power: f64) -> Self {
+    pub fn new(obs_points: &'a [T], power: f64, metric: &'a M) -> Self {
         Idw {
-            obs_points: obs_points,
+            points: PointColumns::new(obs_points),
             power: power,
+            metric: metric,
+            obs_points: PhantomData,
         }
     }
 
     pub fn interp_point(&self, pt: (f64, f64)) -> f64 {
-        let (zw, sw): (f64, f64) = self.obs_points
-            .iter()
-            .fold((0.0, 0.0), |mut data, obs_pt| {
-                let val = obs_pt.get_value();
-                let dist = obs_pt.distance(pt.0, pt.1);
-                if almost_equal(dist, 0.0, 1.0e-5) {
-                    data.0 = val;
-                }
-                let w = 1.0 / dist.powf(self.power);
-                data.0 += w * val;
-                data.1 += w;
-                data
-            });
-        zw / sw
+        let mut value = [0.0];
+        self.interp_batch(&[pt], &mut value);
+        value[0]
+    }
+
+    fn interp_batch(&self, cells: &[(f64, f64)], out: &mut [f64]) {
+        idw_means(cells, &self.points, self.metric, self.power, out)
     }
 }
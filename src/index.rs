@@ -0,0 +1,255 @@
+//! Bucket grid spatial indexes over the coordinates of observation points
+//! and over line segments.
+use std::f64;
+use bbox::Bbox;
+use metric::DistanceMetric;
+use utils::PtValue;
+
+
+/// Maximum number of buckets per indexed point.
+const MAX_BUCKETS_PER_POINT: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct GridIndex {
+    min_x: f64,
+    min_y: f64,
+    size_x: f64,
+    size_y: f64,
+    nx: usize,
+    ny: usize,
+    buckets: Vec<Vec<usize>>,
+}
+
+impl GridIndex {
+    /// Index `obs_points` in buckets of about `bucket_size` (in the units of the coordinates).
+    pub fn new<T>(obs_points: &[T], bucket_size: (f64, f64)) -> Self
+        where T: PtValue
+    {
+        let (mut min_x, mut max_x, mut min_y, mut max_y) = (0.0, 0.0, 0.0, 0.0);
+        for (ix, pt) in obs_points.iter().enumerate() {
+            let (x, y) = pt.get_coordinates();
+            if ix == 0 || x < min_x {
+                min_x = x;
+            }
+            if ix == 0 || x > max_x {
+                max_x = x;
+            }
+            if ix == 0 || y < min_y {
+                min_y = y;
+            }
+            if ix == 0 || y > max_y {
+                max_y = y;
+            }
+        }
+        let (width, height) = (max_x - min_x, max_y - min_y);
+        let max_buckets = MAX_BUCKETS_PER_POINT * obs_points.len().max(1);
+        let mut nx = bucket_count(width, bucket_size.0).min(max_buckets);
+        let mut ny = bucket_count(height, bucket_size.1).min(max_buckets);
+        while nx * ny > max_buckets {
+            nx = (nx + 1) / 2;
+            ny = (ny + 1) / 2;
+        }
+        let size_x = if width > 0.0 { width / nx as f64 } else { 1.0 };
+        let size_y = if height > 0.0 { height / ny as f64 } else { 1.0 };
+        let mut index = GridIndex {
+            min_x: min_x,
+            min_y: min_y,
+            size_x: size_x,
+            size_y: size_y,
+            nx: nx,
+            ny: ny,
+            buckets: vec![Vec::new(); nx * ny],
+        };
+        for (ix, pt) in obs_points.iter().enumerate() {
+            let (x, y) = pt.get_coordinates();
+            let (i, j) = (index.column(x), index.row(y));
+            index.buckets[i * ny + j].push(ix);
+        }
+        index
+    }
+
+    fn column(&self, x: f64) -> usize {
+        clamp_bucket((x - self.min_x) / self.size_x, self.nx)
+    }
+
+    fn row(&self, y: f64) -> usize {
+        clamp_bucket((y - self.min_y) / self.size_y, self.ny)
+    }
+
+    /// Call `f` with the index of every point whose coordinates may lie in `window`.
+    pub fn query<F>(&self, window: &Bbox, mut f: F)
+        where F: FnMut(usize)
+    {
+        let (i_min, i_max) = (self.column(window.min_x), self.column(window.max_x));
+        let (j_min, j_max) = (self.row(window.min_y), self.row(window.max_y));
+        for i in i_min..i_max + 1 {
+            for j in j_min..j_max + 1 {
+                for &ix in &self.buckets[i * self.ny + j] {
+                    f(ix);
+                }
+            }
+        }
+    }
+
+    /// Call `f` with the index of, and the distance (according to `metric`) to,
+    /// every point of `obs_points` (the points this index was built from) lying
+    /// within `dist` of (x, y).
+    pub fn within<T, M, F>(&self,
+                           obs_points: &[T],
+                           metric: &M,
+                           x: f64,
+                           y: f64,
+                           dist: f64,
+                           mut f: F)
+        where T: PtValue,
+              M: DistanceMetric,
+              F: FnMut(usize, f64)
+    {
+        let window = metric.search_window(x, y, dist);
+        self.query(&window, |ix| {
+            let (px, py) = obs_points[ix].get_coordinates();
+            let d = metric.distance(px, py, x, y);
+            if d <= dist {
+                f(ix, d);
+            }
+        });
+    }
+}
+
+/// Bucket grid spatial index over line segments, each segment being listed in
+/// every bucket it goes through.
+#[derive(Debug, Clone)]
+pub struct SegmentIndex {
+    min_x: f64,
+    min_y: f64,
+    size_x: f64,
+    size_y: f64,
+    nx: usize,
+    ny: usize,
+    buckets: Vec<Vec<usize>>,
+}
+
+impl SegmentIndex {
+    /// Index `segments`, given as their two ends, in about as many buckets as segments.
+    pub fn new(segments: &[((f64, f64), (f64, f64))]) -> Self {
+        let (mut min_x, mut max_x, mut min_y, mut max_y) =
+            (f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY);
+        for &((x1, y1), (x2, y2)) in segments {
+            min_x = min_x.min(x1).min(x2);
+            max_x = max_x.max(x1).max(x2);
+            min_y = min_y.min(y1).min(y2);
+            max_y = max_y.max(y1).max(y2);
+        }
+        if segments.is_empty() {
+            min_x = 0.0;
+            max_x = 0.0;
+            min_y = 0.0;
+            max_y = 0.0;
+        }
+        let (width, height) = (max_x - min_x, max_y - min_y);
+        let nb_segments = segments.len().max(1);
+        let bucket_size = if width > 0.0 && height > 0.0 {
+            (width * height / nb_segments as f64).sqrt()
+        } else {
+            (width + height) / nb_segments as f64
+        };
+        let max_buckets = MAX_BUCKETS_PER_POINT * nb_segments;
+        let mut nx = bucket_count(width, bucket_size).min(max_buckets);
+        let mut ny = bucket_count(height, bucket_size).min(max_buckets);
+        while nx * ny > max_buckets {
+            nx = (nx + 1) / 2;
+            ny = (ny + 1) / 2;
+        }
+        let mut index = SegmentIndex {
+            min_x: min_x,
+            min_y: min_y,
+            size_x: if width > 0.0 { width / nx as f64 } else { 1.0 },
+            size_y: if height > 0.0 { height / ny as f64 } else { 1.0 },
+            nx: nx,
+            ny: ny,
+            buckets: Vec::new(),
+        };
+        let mut buckets = vec![Vec::new(); nx * ny];
+        for (ix, &(a, b)) in segments.iter().enumerate() {
+            index.traverse(a, b, |bucket| buckets[bucket].push(ix));
+        }
+        index.buckets = buckets;
+        index
+    }
+
+    /// Call `f` with the index of every segment which may intersect the
+    /// segment from `a` to `b` (some of them several times), until it
+    /// returns false.
+    pub fn query<F>(&self, a: (f64, f64), b: (f64, f64), mut f: F)
+        where F: FnMut(usize) -> bool
+    {
+        let mut stop = false;
+        self.traverse(a, b, |bucket| if !stop {
+            for &ix in &self.buckets[bucket] {
+                if !f(ix) {
+                    stop = true;
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Call `f` with every bucket the segment from `a` to `b` goes through
+    /// (with a margin for rounding errors), column by column.
+    fn traverse<F>(&self, a: (f64, f64), b: (f64, f64), mut f: F)
+        where F: FnMut(usize)
+    {
+        let ((x1, y1), (x2, y2)) = if a.0 <= b.0 { (a, b) } else { (b, a) };
+        let margin = 1e-9 * self.size_x.max(self.size_y);
+        let (i_min, i_max) = (self.column(x1 - margin), self.column(x2 + margin));
+        for i in i_min..i_max + 1 {
+            // Part of the segment within the column (the outer columns extending to infinity)
+            let lo = if i == 0 {
+                x1
+            } else {
+                x1.max(self.min_x + i as f64 * self.size_x)
+            };
+            let hi = if i == self.nx - 1 {
+                x2
+            } else {
+                x2.min(self.min_x + (i + 1) as f64 * self.size_x)
+            };
+            let (ya, yb) = if x2 > x1 {
+                (y1 + (y2 - y1) * ((lo - x1) / (x2 - x1)).max(0.0).min(1.0),
+                 y1 + (y2 - y1) * ((hi - x1) / (x2 - x1)).max(0.0).min(1.0))
+            } else {
+                (y1, y2)
+            };
+            let (j_min, j_max) = (self.row(ya.min(yb) - margin), self.row(ya.max(yb) + margin));
+            for j in j_min..j_max + 1 {
+                f(i * self.ny + j);
+            }
+        }
+    }
+
+    fn column(&self, x: f64) -> usize {
+        clamp_bucket((x - self.min_x) / self.size_x, self.nx)
+    }
+
+    fn row(&self, y: f64) -> usize {
+        clamp_bucket((y - self.min_y) / self.size_y, self.ny)
+    }
+}
+
+fn bucket_count(extent: f64, bucket_size: f64) -> usize {
+    if extent > 0.0 && bucket_size > 0.0 && (extent / bucket_size).is_finite() {
+        ((extent / bucket_size).ceil() as usize).max(1)
+    } else {
+        1
+    }
+}
+
+fn clamp_bucket(pos: f64, n: usize) -> usize {
+    if pos <= 0.0 || pos.is_nan() {
+        0
+    } else if pos >= (n - 1) as f64 {
+        n - 1
+    } else {
+        pos as usize
+    }
+}
@@ -0,0 +1,366 @@
+//! Interaction matrices between the cells of a grid and observation points.
+//!
+//! The weight of each observation point in each cell (the smoothing function
+//! of their distance) is computed once, and the matrix is then applied to
+//! any number of value vectors (e.g. several stocks on the same points).
+use std::f64::{INFINITY, NEG_INFINITY};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use rayon::prelude::*;
+use costs::CostMatrix;
+use errors::*;
+use index::GridIndex;
+use metric::DistanceMetric;
+use utils::PtValue;
+
+
+const MAGIC: &'static [u8; 8] = b"SMOOIM01";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Weights {
+    /// Weights of every point, cell by cell.
+    Dense(Vec<f64>),
+    /// Compressed rows: the (point, weight) pairs of cell `i` are at
+    /// `row_start[i]..row_start[i + 1]` of `points` and `weights`.
+    Sparse {
+        row_start: Vec<usize>,
+        points: Vec<usize>,
+        weights: Vec<f64>,
+    },
+}
+
+/// Weights of a set of observation points in each cell of a grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InteractionMatrix {
+    cells: Vec<(f64, f64)>,
+    nb_points: usize,
+    weights: Weights,
+}
+
+impl InteractionMatrix {
+    /// Dense matrix of `kernel(distance)` between every cell and every point.
+    pub fn dense<T, M, K>(cells: &[T], obs_points: &[T], metric: &M, kernel: K) -> Self
+        where T: PtValue + Sync,
+              M: DistanceMetric,
+              K: Fn(f64) -> f64 + Sync
+    {
+        let nb_points = obs_points.len();
+        let mut weights = vec![0.0; cells.len() * nb_points];
+        if nb_points > 0 {
+            weights
+                .par_chunks_mut(nb_points)
+                .zip(cells.par_iter())
+                .for_each(|(row, cell)| {
+                    let (cx, cy) = cell.get_coordinates();
+                    for (w, pt) in row.iter_mut().zip(obs_points) {
+                        let (x, y) = pt.get_coordinates();
+                        *w = kernel(metric.distance(cx, cy, x, y));
+                    }
+                });
+        }
+        InteractionMatrix {
+            cells: cells.iter().map(|cell| cell.get_coordinates()).collect(),
+            nb_points: nb_points,
+            weights: Weights::Dense(weights),
+        }
+    }
+
+    /// Sparse matrix of `kernel(distance)` between every cell and the points
+    /// within `cutoff_dist` of it.
+    pub fn sparse<T, M, K>(cells: &[T],
+                           obs_points: &[T],
+                           metric: &M,
+                           kernel: K,
+                           cutoff_dist: f64)
+                           -> Self
+        where T: PtValue + Sync,
+              M: DistanceMetric,
+              K: Fn(f64) -> f64 + Sync
+    {
+        let rows = if obs_points.is_empty() {
+            vec![Vec::new(); cells.len()]
+        } else {
+            // Buckets sized after the search window at the middle of the grid
+            let (mut min_x, mut max_x, mut min_y, mut max_y) =
+                (INFINITY, NEG_INFINITY, INFINITY, NEG_INFINITY);
+            for cell in cells {
+                let (x, y) = cell.get_coordinates();
+                min_x = min_x.min(x);
+                max_x = max_x.max(x);
+                min_y = min_y.min(y);
+                max_y = max_y.max(y);
+            }
+            let window = metric.search_window((min_x + max_x) / 2.0,
+                                              (min_y + max_y) / 2.0,
+                                              cutoff_dist);
+            let index = GridIndex::new(obs_points,
+                                       (window.max_x - window.min_x,
+                                        window.max_y - window.min_y));
+            cells
+                .par_iter()
+                .map(|cell| {
+                    let (x, y) = cell.get_coordinates();
+                    let mut row = Vec::new();
+                    index.within(obs_points, metric, x, y, cutoff_dist, |ix, dist| {
+                        row.push((ix, kernel(dist)));
+                    });
+                    row
+                })
+                .collect::<Vec<Vec<(usize, f64)>>>()
+        };
+        let nnz = rows.iter().map(|row| row.len()).sum();
+        let mut row_start = Vec::with_capacity(cells.len() + 1);
+        let (mut points, mut weights) = (Vec::with_capacity(nnz), Vec::with_capacity(nnz));
+        row_start.push(0);
+        for row in rows {
+            for (ix, w) in row {
+                points.push(ix);
+                weights.push(w);
+            }
+            row_start.push(points.len());
+        }
+        InteractionMatrix {
+            cells: cells.iter().map(|cell| cell.get_coordinates()).collect(),
+            nb_points: obs_points.len(),
+            weights: Weights::Sparse {
+                row_start: row_start,
+                points: points,
+                weights: weights,
+            },
+        }
+    }
+
+    /// Dense matrix of `kernel(cost)` from the origins of `costs` (the
+    /// observation points) to `targets`, which are its destinations.
+    pub fn from_costs<T, K>(targets: &[T], costs: &CostMatrix, kernel: K) -> Result<Self>
+        where T: PtValue + Sync,
+              K: Fn(f64) -> f64 + Sync
+    {
+        costs.check_dimensions(costs.nb_origins(), targets.len())?;
+        let nb_points = costs.nb_origins();
+        let mut weights = vec![0.0; targets.len() * nb_points];
+        if nb_points > 0 {
+            weights
+                .par_chunks_mut(nb_points)
+                .enumerate()
+                .for_each(|(t, row)| for (p, w) in row.iter_mut().enumerate() {
+                              *w = kernel(costs.get(p, t));
+                          });
+        }
+        Ok(InteractionMatrix {
+               cells: targets.iter().map(|target| target.get_coordinates()).collect(),
+               nb_points: nb_points,
+               weights: Weights::Dense(weights),
+           })
+    }
+
+    /// Number of cells (rows) of the matrix.
+    pub fn nb_cells(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Number of observation points (columns) of the matrix.
+    pub fn nb_points(&self) -> usize {
+        self.nb_points
+    }
+
+    /// Number of stored weights.
+    pub fn nb_weights(&self) -> usize {
+        match self.weights {
+            Weights::Dense(ref weights) => weights.len(),
+            Weights::Sparse { ref weights, .. } => weights.len(),
+        }
+    }
+
+    /// Sum, for each cell, of the `values` of the points (given in the order
+    /// of the points the matrix was built from) times their weight.
+    pub fn apply(&self, values: &[f64]) -> Result<Vec<f64>> {
+        if values.len() != self.nb_points {
+            return Err(ErrorKind::DimensionMismatch(self.nb_points, values.len()).into());
+        }
+        let mut res = vec![0.0; self.cells.len()];
+        match self.weights {
+            Weights::Dense(ref weights) => {
+                if self.nb_points > 0 {
+                    res.par_iter_mut()
+                        .zip(weights.par_chunks(self.nb_points))
+                        .for_each(|(r, row)| {
+                                      *r = row.iter()
+                                          .zip(values)
+                                          .fold(0.0, |sum, (w, val)| sum + val * w);
+                                  });
+                }
+            }
+            Weights::Sparse { ref row_start, ref points, ref weights } => {
+                res.par_iter_mut()
+                    .enumerate()
+                    .for_each(|(i, r)| {
+                                  let range = row_start[i]..row_start[i + 1];
+                                  *r = points[range.clone()]
+                                      .iter()
+                                      .zip(&weights[range])
+                                      .fold(0.0, |sum, (&ix, w)| sum + values[ix] * w);
+                              });
+            }
+        }
+        Ok(res)
+    }
+
+    /// Same as `apply`, returning the cells of the grid with their value.
+    pub fn apply_grid<T>(&self, values: &[f64]) -> Result<Vec<T>>
+        where T: PtValue
+    {
+        let res = self.apply(values)?;
+        Ok(self.cells
+               .iter()
+               .zip(res)
+               .map(|(&(x, y), val)| T::new(x, y, val))
+               .collect())
+    }
+
+    /// Write the matrix to a (binary) file, to be read with `load`.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        write_usize(&mut writer, self.cells.len())?;
+        write_usize(&mut writer, self.nb_points)?;
+        for &(x, y) in &self.cells {
+            write_f64(&mut writer, x)?;
+            write_f64(&mut writer, y)?;
+        }
+        match self.weights {
+            Weights::Dense(ref weights) => {
+                writer.write_all(&[0])?;
+                for &w in weights {
+                    write_f64(&mut writer, w)?;
+                }
+            }
+            Weights::Sparse { ref row_start, ref points, ref weights } => {
+                writer.write_all(&[1])?;
+                for &start in row_start {
+                    write_usize(&mut writer, start)?;
+                }
+                for &ix in points {
+                    write_usize(&mut writer, ix)?;
+                }
+                for &w in weights {
+                    write_f64(&mut writer, w)?;
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read a matrix written by `save`. The sizes read from the file are
+    /// checked against its length before anything is allocated for them.
+    pub fn load(path: &str) -> Result<Self> {
+        let file = File::open(path)?;
+        // Bytes left after the magic number and the two sizes
+        let mut remaining = file.metadata()?.len().saturating_sub(24);
+        let mut reader = BufReader::new(file);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(format!("{} is not an interaction matrix file", path).into());
+        }
+        let nb_cells = read_usize(&mut reader)?;
+        let nb_points = read_usize(&mut reader)?;
+        let mut consume = |count: usize, size: u64| -> Result<()> {
+            match (count as u64).checked_mul(size) {
+                Some(len) if len <= remaining => {
+                    remaining -= len;
+                    Ok(())
+                }
+                _ => Err(format!("Invalid interaction matrix file {} (truncated)", path).into()),
+            }
+        };
+        consume(nb_cells, 16)?;
+        consume(1, 1)?;
+        let mut cells = Vec::with_capacity(nb_cells);
+        for _ in 0..nb_cells {
+            let x = read_f64(&mut reader)?;
+            let y = read_f64(&mut reader)?;
+            cells.push((x, y));
+        }
+        let mut kind = [0; 1];
+        reader.read_exact(&mut kind)?;
+        let weights = match kind[0] {
+            0 => {
+                let nb_weights = nb_cells
+                    .checked_mul(nb_points)
+                    .ok_or_else(|| format!("Invalid interaction matrix file {}", path))?;
+                consume(nb_weights, 8)?;
+                Weights::Dense(read_f64s(&mut reader, nb_weights)?)
+            }
+            1 => {
+                consume(nb_cells + 1, 8)?;
+                let mut row_start = Vec::with_capacity(nb_cells + 1);
+                for _ in 0..nb_cells + 1 {
+                    row_start.push(read_usize(&mut reader)?);
+                }
+                if row_start[0] != 0 || row_start.windows(2).any(|w| w[0] > w[1]) {
+                    return Err(format!("Invalid interaction matrix file {}", path).into());
+                }
+                let nnz = row_start[nb_cells];
+                // The points then the weights of the non-zero entries
+                consume(nnz, 16)?;
+                let mut points = Vec::with_capacity(nnz);
+                for _ in 0..nnz {
+                    let ix = read_usize(&mut reader)?;
+                    if ix >= nb_points {
+                        return Err(format!("Invalid interaction matrix file {}", path).into());
+                    }
+                    points.push(ix);
+                }
+                Weights::Sparse {
+                    row_start: row_start,
+                    points: points,
+                    weights: read_f64s(&mut reader, nnz)?,
+                }
+            }
+            _ => return Err(format!("Invalid interaction matrix file {}", path).into()),
+        };
+        Ok(InteractionMatrix {
+               cells: cells,
+               nb_points: nb_points,
+               weights: weights,
+           })
+    }
+}
+
+fn write_usize<W: Write>(writer: &mut W, val: usize) -> Result<()> {
+    writer.write_all(&(val as u64).to_le_bytes())?;
+    Ok(())
+}
+
+fn write_f64<W: Write>(writer: &mut W, val: f64) -> Result<()> {
+    writer.write_all(&val.to_bits().to_le_bytes())?;
+    Ok(())
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_usize<R: Read>(reader: &mut R) -> Result<usize> {
+    let val = read_u64(reader)?;
+    if val > usize::max_value() as u64 {
+        return Err("Interaction matrix too large for this platform".into());
+    }
+    Ok(val as usize)
+}
+
+fn read_f64<R: Read>(reader: &mut R) -> Result<f64> {
+    Ok(f64::from_bits(read_u64(reader)?))
+}
+
+fn read_f64s<R: Read>(reader: &mut R, count: usize) -> Result<Vec<f64>> {
+    let mut values = Vec::with_capacity(count);
+    for _ in 0..count {
+        values.push(read_f64(reader)?);
+    }
+    Ok(values)
+}
@@ -0,0 +1,191 @@
+//! Batched evaluation of sums of distance kernels, with portable SIMD.
+//!
+//! The observation points are stored as separate arrays of coordinates and
+//! values, and the cells are processed `LANES` at a time, in the lanes of a
+//! `f64x4` (from the `wide` crate, which uses the vector instructions of the
+//! target and falls back to scalar code elsewhere): for each point, the
+//! distances to the cells of a batch (`DistanceMetric::distance_lanes`) and
+//! their weights are computed by vector operations, so that each point is
+//! loaded once per batch rather than once per cell. The kernels are generic
+//! parameters, so each combination is compiled (and inlined) separately.
+//!
+//! The lanes don't interact, so the results don't depend on the batching;
+//! the vector `exp`, `ln` and `powf` are accurate to a few ulps, so they
+//! may differ from the scalar functions of `std` in the last digits.
+use rayon::prelude::*;
+use utils::PtValue;
+use metric::DistanceMetric;
+use wide::{f64x4, CmpLt};
+
+
+/// Number of cells processed together (the lanes of a `f64x4`).
+pub const LANES: usize = 4;
+/// Number of cells per parallel task.
+const CELLS_PER_TASK: usize = 64 * LANES;
+/// Distance below which a cell lies on a point, as `almost_equal(dist, 0.0, 1e-5)`.
+const EXACT_DISTANCE: f64 = 1.0e-5 * ::std::f64::MIN_POSITIVE;
+
+/// Observation points in a structure-of-arrays layout.
+#[derive(Debug, Clone)]
+pub struct PointColumns {
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+    values: Vec<f64>,
+}
+
+impl PointColumns {
+    pub fn new<T>(obs_points: &[T]) -> Self
+        where T: PtValue
+    {
+        let mut cols = PointColumns {
+            xs: Vec::with_capacity(obs_points.len()),
+            ys: Vec::with_capacity(obs_points.len()),
+            values: Vec::with_capacity(obs_points.len()),
+        };
+        for pt in obs_points {
+            let (x, y, val) = pt.get_triplet();
+            cols.xs.push(x);
+            cols.ys.push(y);
+            cols.values.push(val);
+        }
+        cols
+    }
+
+    /// Coordinates of `obs_points`, with other values.
+    pub fn with_values<T>(obs_points: &[T], values: Vec<f64>) -> Self
+        where T: PtValue
+    {
+        debug_assert_eq!(values.len(), obs_points.len());
+        PointColumns {
+            xs: obs_points.iter().map(|pt| pt.get_coordinates().0).collect(),
+            ys: obs_points.iter().map(|pt| pt.get_coordinates().1).collect(),
+            values: values,
+        }
+    }
+
+    /// Coordinates and value of the point `ix`.
+    pub fn get(&self, ix: usize) -> (f64, f64, f64) {
+        (self.xs[ix], self.ys[ix], self.values[ix])
+    }
+}
+
+/// `f(x1, y1, x2, y2)` between each lane of (`xs`, `ys`) and (`x`, `y`),
+/// one lane after the other (for the distances without a vector form).
+#[inline(always)]
+pub fn per_lane<F>(xs: f64x4, ys: f64x4, x: f64, y: f64, f: F) -> f64x4
+    where F: Fn(f64, f64, f64, f64) -> f64
+{
+    let (xs, ys) = (xs.to_array(), ys.to_array());
+    let mut res = [0.0; LANES];
+    for l in 0..LANES {
+        res[l] = f(xs[l], ys[l], x, y);
+    }
+    f64x4::new(res)
+}
+
+/// Coordinates of up to `LANES` cells, the unused lanes repeating the first cell.
+#[inline(always)]
+fn lanes(cells: &[(f64, f64)]) -> (f64x4, f64x4) {
+    let (mut xs, mut ys) = ([cells[0].0; LANES], [cells[0].1; LANES]);
+    for (l, &(x, y)) in cells.iter().enumerate() {
+        xs[l] = x;
+        ys[l] = y;
+    }
+    (f64x4::new(xs), f64x4::new(ys))
+}
+
+/// Sum, for each cell, of `value * kernel(distance)` over the points, the
+/// distances being computed with `metric`, written to `out`.
+pub fn weighted_sums<M, K>(cells: &[(f64, f64)],
+                           points: &PointColumns,
+                           metric: &M,
+                           kernel: &K,
+                           out: &mut [f64])
+    where M: DistanceMetric,
+          K: Fn(f64x4) -> f64x4
+{
+    for (batch, out) in cells.chunks(LANES).zip(out.chunks_mut(LANES)) {
+        let (cx, cy) = lanes(batch);
+        let mut sum = f64x4::ZERO;
+        for p in 0..points.values.len() {
+            let dist = metric.distance_lanes(cx, cy, points.xs[p], points.ys[p]);
+            sum += f64x4::splat(points.values[p]) * kernel(dist);
+        }
+        out.copy_from_slice(&sum.to_array()[..batch.len()]);
+    }
+}
+
+/// Inverse distance weighted mean of the values of the points, with a weight
+/// of `1 / distance^power`, for each cell, written to `out`. A cell at a
+/// null distance from a point takes the value of the (first) one.
+pub fn idw_means<M>(cells: &[(f64, f64)],
+                    points: &PointColumns,
+                    metric: &M,
+                    power: f64,
+                    out: &mut [f64])
+    where M: DistanceMetric
+{
+    let exact_distance = f64x4::splat(EXACT_DISTANCE);
+    for (batch, out) in cells.chunks(LANES).zip(out.chunks_mut(LANES)) {
+        let (cx, cy) = lanes(batch);
+        let (mut zw, mut sw) = (f64x4::ZERO, f64x4::ZERO);
+        let mut exact = [None; LANES];
+        for p in 0..points.values.len() {
+            let val = points.values[p];
+            let dist = metric.distance_lanes(cx, cy, points.xs[p], points.ys[p]);
+            let at_point = dist.cmp_lt(exact_distance);
+            if at_point.any() {
+                // The weight would be infinite
+                let d = dist.to_array();
+                for l in 0..LANES {
+                    if d[l] < EXACT_DISTANCE && exact[l].is_none() {
+                        exact[l] = Some(val);
+                    }
+                }
+            }
+            let w = at_point.blend(f64x4::ZERO, f64x4::ONE / dist.powf(power));
+            zw += w * f64x4::splat(val);
+            sw += w;
+        }
+        let (zw, sw) = (zw.to_array(), sw.to_array());
+        for l in 0..batch.len() {
+            out[l] = match exact[l] {
+                Some(val) => val,
+                None => zw[l] / sw[l],
+            };
+        }
+    }
+}
+
+/// Set the value of each cell to `batch(coordinates, values)`, by tasks of
+/// several batches, in parallel unless `parallel` is false.
+pub fn fill_cells<T, F>(cells: &mut [T], parallel: bool, batch: F)
+    where T: PtValue + Send,
+          F: Fn(&[(f64, f64)], &mut [f64]) + Sync
+{
+    let fill = |chunk: &mut [T]| {
+        let coords = chunk.iter().map(|cell| cell.get_coordinates()).collect::<Vec<_>>();
+        let mut values = vec![0.0; chunk.len()];
+        batch(&coords, &mut values);
+        for (cell, val) in chunk.iter_mut().zip(values) {
+            cell.set_value(val);
+        }
+    };
+    if parallel {
+        cells.par_chunks_mut(CELLS_PER_TASK).for_each(fill);
+    } else {
+        cells.chunks_mut(CELLS_PER_TASK).for_each(fill);
+    }
+}
+
+/// Same as `fill_cells` for bare coordinates, in parallel.
+pub fn map_coordinates<F>(coords: &[(f64, f64)], batch: F) -> Vec<f64>
+    where F: Fn(&[(f64, f64)], &mut [f64]) + Sync
+{
+    let mut values = vec![0.0; coords.len()];
+    values
+        .par_chunks_mut(CELLS_PER_TASK)
+        .zip(coords.par_chunks(CELLS_PER_TASK))
+        .for_each(|(out, chunk)| batch(chunk, out));
+    values
+}
@@ -18,22 +18,63 @@ extern crate serde_json;
 extern crate csv;
 extern crate gdal;
 extern crate geojson;
-extern crate jobsteal;
+extern crate rayon;
 extern crate rulinalg;
+extern crate wide;
 
 mod errors;
+mod barrier;
 mod bbox;
+mod costs;
+mod cutoff;
+mod fft;
+mod geodesic;
+mod geometry;
+mod grid;
 mod idw;
+mod index;
+mod interaction;
+mod kernel;
+mod normalize;
 mod pot_stewart;
+mod pycno;
+mod quadtree;
 mod rbf;
+mod sparse;
 
+pub mod metric;
+pub mod network;
+pub mod parallel;
+pub mod projection;
+pub mod stream;
+pub mod tiled;
 pub mod utils;
 
+pub use self::barrier::{BarrierMetric, Barriers, Crossing};
 pub use self::bbox::Bbox;
+pub use self::costs::CostMatrix;
+pub use self::cutoff::{Cutoff, TruncationReport};
+pub use self::grid::{CellPosition, GridSpec};
+pub use self::interaction::InteractionMatrix;
 pub use self::idw::idw_interpolation1 as idw_interpolation;
-pub use self::pot_stewart::{SmoothType, StewartPotentialGrid, stewart, stewart_interpolation};
-pub use self::rbf::{Rbf, rbf_interpolation};
-pub use self::utils::{PtValue, SphericalPtValue, CartesianPtValue};
+pub use self::idw::{idw_from_costs, idw_interpolation_with_metric, idw_tiled,
+                    idw_tiled_with_metric, idw_truncated, idw_truncated_with_metric};
+pub use self::metric::{Chebyshev, DistanceMetric, Euclidean, Geodesic, Haversine, Manhattan,
+                       Minkowski, PointMetric};
+pub use self::network::{Network, NetworkMetric};
+pub use self::normalize::{Normalization, cell_areas, normalize};
+pub use self::projection::Crs;
+pub use self::pot_stewart::{SmoothType, StewartEngine, StewartPotentialGrid, FFT_MIN_SPAN_CELLS,
+                            stewart, stewart_from_costs, stewart_interaction_matrix,
+                            stewart_interaction_matrix_with_metric, stewart_interpolation,
+                            stewart_interpolation_with_metric, stewart_tiled,
+                            stewart_tiled_with_metric, stewart_truncated,
+                            stewart_truncated_with_metric, stewart_with_metric};
+pub use self::pycno::{PycnoConfig, PycnoReport, Zone, parse_geojson_zones, pycnophylactic,
+                      read_weight_raster};
+pub use self::rbf::{LocalRbf, Polynomial, Rbf, rbf_interpolation, rbf_interpolation_with_metric,
+                    rbf_tiled, rbf_tiled_with_metric};
+pub use self::utils::{PtValue, SphericalPtValue, GeodesicPtValue, CartesianPtValue};
 pub use errors::*;
 
 #[cfg(test)]
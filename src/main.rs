@@ -1,28 +1,677 @@
 extern crate clap;
-extern crate num_cpus;
+extern crate serde;
 extern crate smoomars;
-#[macro_use]
-extern crate scan_rules;
 
 
 use smoomars::*;
-use clap::{Arg, App};
+use clap::{Arg, App, ArgMatches};
+use serde::Serialize;
 
 
-fn main() {
+/// Newline-delimited GeoJSON files, read and written one feature per line.
+fn is_geojsonseq(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path.ends_with(".geojsonl") || path.ends_with(".geojsons") || path.ends_with(".geojsonseq")
+}
+
+fn is_geojson(path: &str) -> bool {
+    path.contains("geojson") || path.contains("GEOJSON")
+}
+
+fn parse_delimiter(delim: &str) -> Result<u8> {
+    if delim == "\\t" {
+        Ok(b'\t')
+    } else if delim.len() == 1 {
+        Ok(delim.as_bytes()[0])
+    } else {
+        Err(format!("Invalid delimiter: '{}'", delim).into())
+    }
+}
+
+fn parse_arg<F>(matches: &ArgMatches, name: &str) -> Result<Option<F>>
+    where F: ::std::str::FromStr
+{
+    match matches.value_of(name) {
+        Some(value) => {
+            value
+                .parse::<F>()
+                .map(Some)
+                .map_err(|_| format!("Invalid value for --{}: '{}'", name, value).into())
+        }
+        None => Ok(None),
+    }
+}
+
+/// Parse the resolution of the grid, given as "resoX-resoY".
+fn parse_scale(scale: &str) -> Result<(usize, usize)> {
+    let parts = scale.split('-').collect::<Vec<&str>>();
+    if parts.len() != 2 {
+        return Err(format!("Invalid scale: '{}' (expected resoX-resoY)", scale).into());
+    }
+    match (parts[0].trim().parse::<usize>(), parts[1].trim().parse::<usize>()) {
+        (Ok(reso_x), Ok(reso_y)) => Ok((reso_x, reso_y)),
+        _ => Err(format!("Invalid scale: '{}' (expected resoX-resoY)", scale).into()),
+    }
+}
+
+/// Parse the CRS given as argument `name` (as an EPSG code).
+fn parse_crs(matches: &ArgMatches, name: &str) -> Result<Option<Crs>> {
+    match matches.value_of(name) {
+        Some(code) => Ok(Some(code.parse::<Crs>()?)),
+        None => Ok(None),
+    }
+}
+
+/// Parse the size of the cells, given as "size" or "sizeX,sizeY".
+fn parse_cell_size(size: &str) -> Result<(f64, f64)> {
+    let parts = size
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<::std::result::Result<Vec<f64>, _>>()?;
+    match parts.len() {
+        1 => Ok((parts[0], parts[0])),
+        2 => Ok((parts[0], parts[1])),
+        _ => Err(format!("Invalid cell size: '{}' (expected size or sizeX,sizeY)", size).into()),
+    }
+}
+
+/// Parse a buffer, given as a distance or as a percentage (e.g. "10%").
+fn parse_buffer(buffer: &str) -> Result<(f64, bool)> {
+    let buffer = buffer.trim();
+    let (value, percent) = if buffer.ends_with('%') {
+        (&buffer[..buffer.len() - 1], true)
+    } else {
+        (buffer, false)
+    };
+    match value.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok((value, percent)),
+        _ => Err(format!("Invalid buffer: '{}' (expected a distance or a percentage)", buffer)
+                     .into()),
+    }
+}
+
+fn csv_options(matches: &ArgMatches) -> Result<utils::CsvOptions> {
+    let mut options = utils::CsvOptions::default();
+    if let Some(col) = matches.value_of("x_column") {
+        options.x_column = utils::CsvColumn::from(col);
+    }
+    if let Some(col) = matches.value_of("y_column") {
+        options.y_column = utils::CsvColumn::from(col);
+    }
+    if let Some(col) = matches.value_of("field") {
+        options.value_column = utils::CsvColumn::from(col);
+    }
+    if let Some(delim) = matches.value_of("delimiter") {
+        options.delimiter = parse_delimiter(delim)?;
+    }
+    if let Some(quote) = matches.value_of("quote") {
+        options.quote = parse_delimiter(quote)?;
+    }
+    if let Some(dec) = parse_arg::<char>(matches, "decimal")? {
+        options.decimal_separator = dec;
+    }
+    options.has_headers = !matches.is_present("no_header");
+    options.skip_missing = matches.is_present("skip_missing");
+    Ok(options)
+}
+
+fn csv_write_options(matches: &ArgMatches) -> Result<utils::CsvWriteOptions> {
+    let mut options = utils::CsvWriteOptions::default();
+    if let Some(delim) = matches.value_of("delimiter") {
+        options.delimiter = parse_delimiter(delim)?;
+    }
+    options.precision = parse_arg::<usize>(matches, "precision")?;
+    options.include_nodata = !matches.is_present("skip_nodata");
+    options.nodata = parse_arg::<f64>(matches, "nodata")?;
+    Ok(options)
+}
+
+fn read_geojson_points<T>(file_path: &str, matches: &ArgMatches) -> Result<Vec<T>>
+    where T: PtValue
+{
+    let field_name = match matches.value_of("field") {
+        Some(field_name) => field_name,
+        None => {
+            return Err("Field name is required for GeoJSON input (arg. --field=name).".into())
+        }
+    };
+    let mut options = utils::GeoJsonOptions::default();
+    if matches.value_of("polygon") == Some("point_on_surface") {
+        options.polygon_representation = utils::PolygonRepresentation::PointOnSurface;
+    }
+    let mut points = Vec::new();
+    let report = if is_geojsonseq(file_path) {
+        let mut reader = stream::GeoJsonSeqReader::<T>::open_with(file_path, field_name, &options)?;
+        for pt in reader.by_ref() {
+            points.push(pt?);
+        }
+        reader.report().clone()
+    } else {
+        stream::stream_geojson_points_with(file_path, field_name, &options, |pt| points.push(pt))?
+    };
+    if report.nb_skipped() > 0 {
+        println!("Skipped {} feature(s):", report.nb_skipped());
+        for &(ix, ref reason) in &report.skipped_features {
+            println!("  feature {}: {}", ix, reason);
+        }
+    }
+    Ok(points)
+}
+
+fn read_csv_points<T>(file_path: &str, matches: &ArgMatches) -> Result<Vec<T>>
+    where T: PtValue
+{
+    let (points, report) = utils::parse_csv_points_with::<T>(file_path, &csv_options(matches)?)?;
+    if report.nb_skipped() > 0 {
+        println!("Skipped {} row(s) with missing or invalid values:", report.nb_skipped());
+        for &(line, ref reason) in &report.skipped_rows {
+            println!("  line {}: {}", line, reason);
+        }
+    }
+    Ok(points)
+}
+
+fn read_points<T>(file_path: &str, matches: &ArgMatches) -> Result<Vec<T>>
+    where T: PtValue
+{
+    if is_geojson(file_path) {
+        read_geojson_points(file_path, matches)
+    } else if file_path.contains("json") || file_path.contains("JSON") {
+        let mut points = Vec::new();
+        stream::stream_json_points(file_path, |pt| points.push(pt))?;
+        Ok(points)
+    } else {
+        read_csv_points(file_path, matches)
+    }
+}
+
+fn save_points<T>(output_path: &str,
+                  result: Vec<T>,
+                  bbox: &Bbox,
+                  reso: (usize, usize),
+                  matches: &ArgMatches,
+                  crs: Option<&Crs>)
+                  -> Result<()>
+    where T: PtValue + Serialize
+{
+    if is_geojsonseq(output_path) {
+        stream::save_geojsonseq_points(output_path, &result)?;
+        save_crs_sidecar(output_path, crs)
+    } else if is_geojson(output_path) {
+        utils::save_geojson_points_with_crs(output_path, result, crs)
+    } else if output_path.contains("csv") || output_path.contains("CSV") {
+        let options = csv_write_options(matches)?;
+        if matches.is_present("csv_grid") {
+            utils::save_csv_grid(output_path, &result, reso, &options)?;
+        } else {
+            utils::save_csv_points(output_path, &result, &options)?;
+        }
+        save_crs_sidecar(output_path, crs)
+    } else if output_path.contains("geotiff") {
+        utils::write_to_raster_with_crs(result, bbox, reso, output_path, crs)
+    } else {
+        utils::save_json_points(output_path, result)?;
+        save_crs_sidecar(output_path, crs)
+    }
+}
+
+/// Record `crs` in a .prj file next to an output whose format can't hold it,
+/// warning if it has no definition to write.
+fn save_crs_sidecar(output_path: &str, crs: Option<&Crs>) -> Result<()> {
+    let crs = match crs {
+        Some(crs) => crs,
+        None => return Ok(()),
+    };
+    if crs.epsg().is_none() {
+        println!("Warning: the CRS {} of the output can't be recorded along with it", crs);
+        return Ok(());
+    }
+    utils::save_prj(output_path, crs)?;
+    println!("CRS written to {}", utils::prj_path(output_path).display());
+    Ok(())
+}
+
+fn print_truncation_report(report: &TruncationReport) {
+    println!("Contributions dropped beyond {} (at most {} per cell; on sampled cells: {:.3e} \
+              relative on average, {:.3e} at most)",
+             report.cutoff_distance,
+             report.dropped_bound,
+             report.sampled_mean_dropped,
+             report.sampled_max_dropped);
+}
+
+/// GeoTIFF output written tile by tile (--tile_size).
+fn tile_writer(matches: &ArgMatches,
+               bbox: &Bbox,
+               reso: (usize, usize),
+               cutoff: &Option<Cutoff>,
+               crs: Option<&Crs>)
+               -> Result<tiled::RasterTileWriter> {
+    let output_path = matches.value_of("output").unwrap();
+    if !output_path.contains("geotiff") {
+        return Err("Tiled computation (--tile_size) requires a .geotiff output".into());
+    }
+    if cutoff.is_some() {
+        return Err("Tiled computation (--tile_size) can't be used with a cutoff".into());
+    }
+    if matches.is_present("output_crs") {
+        return Err("Tiled computation (--tile_size) can't be used with --output_crs".into());
+    }
+    if matches.is_present("normalize") {
+        return Err("Tiled computation (--tile_size) can't be used with --normalize".into());
+    }
+    let mut writer = tiled::RasterTileWriter::create(output_path, bbox, reso.0, reso.1)?;
+    if let Some(crs) = crs {
+        writer.set_crs(crs)?;
+    }
+    Ok(writer)
+}
+
+/// Write the result, with its coordinates transformed from `crs` to the CRS
+/// given by --output_crs if any (only for lists of points, as the grid is no
+/// longer regular).
+fn save_result<T>(result: Vec<T>,
+                  bbox: &Bbox,
+                  reso: (usize, usize),
+                  matches: &ArgMatches,
+                  crs: Option<&Crs>)
+                  -> Result<()>
+    where T: PtValue + Serialize + Sync
+{
+    let output_path = matches.value_of("output").unwrap();
+    let output_crs = match parse_crs(matches, "output_crs")? {
+        Some(output_crs) => output_crs,
+        None => return save_points(output_path, result, bbox, reso, matches, crs),
+    };
+    let crs = crs.ok_or("The CRS of the input (--crs) is required by --output_crs")?;
+    if output_path.contains("geotiff") || matches.is_present("csv_grid") {
+        return Err("--output_crs can only be used for outputs listing the points of the grid"
+                       .into());
+    }
+    if output_crs.is_geographic() {
+        let result: Vec<SphericalPtValue> = projection::reproject(&result, crs, &output_crs);
+        save_points(output_path, result, bbox, reso, matches, Some(&output_crs))
+    } else {
+        let result: Vec<CartesianPtValue> = projection::reproject(&result, crs, &output_crs);
+        save_points(output_path, result, bbox, reso, matches, Some(&output_crs))
+    }
+}
+
+/// Grid of the result rescaled as given by --normalize, `stock` being the
+/// total of the input.
+fn normalize_result<T>(mut result: Vec<T>,
+                       grid: &GridSpec,
+                       matches: &ArgMatches,
+                       stock: f64)
+                       -> Result<Vec<T>>
+    where T: PtValue
+{
+    let normalization = match matches.value_of("normalize") {
+        Some("volume") => Normalization::Volume(stock),
+        Some("percent") => Normalization::PercentOfMax,
+        Some("density") => Normalization::Density,
+        _ => return Ok(result),
+    };
+    normalize(&mut result, grid, normalization)?;
+    Ok(result)
+}
+
+/// Network given by --network, as a GeoJSON file of LineStrings or a CSV
+/// list of edges.
+fn read_network<T>(matches: &ArgMatches) -> Result<Option<Network>>
+    where T: PtValue
+{
+    let path = match matches.value_of("network") {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let network = if is_geojson(path) {
+        Network::from_geojson(path, matches.value_of("network_cost"), &PointMetric::<T>::new())?
+    } else if matches.is_present("network_cost") {
+        return Err("--network_cost only applies to a GeoJSON network".into());
+    } else {
+        Network::from_csv(path)?
+    };
+    println!("Network of {} nodes and {} edges",
+             network.nb_nodes(),
+             network.nb_edges());
+    Ok(Some(network))
+}
+
+/// Values at the points of --targets, the distances being read from the cost
+/// matrix given by --costs (.npy, or CSV in the format of --costs_format).
+fn compute_from_costs<T>(obs_points: Vec<T>,
+                         matches: &ArgMatches,
+                         crs: Option<Crs>)
+                         -> Result<()>
+    where T: PtValue + Serialize + Send + Sync
+{
+    let targets_path = matches
+        .value_of("targets")
+        .ok_or("The target locations (--targets) are required by --costs")?;
+    let targets = read_points::<T>(targets_path, matches)?;
+    let costs_path = matches.value_of("costs").unwrap();
+    let delimiter = match matches.value_of("delimiter") {
+        Some(delim) => parse_delimiter(delim)?,
+        None => b',',
+    };
+    let costs = if costs_path.to_lowercase().ends_with(".npy") {
+        CostMatrix::from_npy(costs_path)?
+    } else if matches.value_of("costs_format") == Some("long") {
+        CostMatrix::from_csv_long(costs_path, delimiter, obs_points.len(), targets.len())?
+    } else {
+        CostMatrix::from_csv_wide(costs_path, delimiter)?
+    };
+    costs
+        .check_dimensions(obs_points.len(), targets.len())
+        .chain_err(|| {
+                       format!("The cost matrix must have a row per observation point ({}) \
+                                and a column per target ({})",
+                               obs_points.len(),
+                               targets.len())
+                   })?;
+    let output_path = matches.value_of("output").unwrap();
+    if output_path.contains("geotiff") || matches.is_present("csv_grid") {
+        return Err("The targets of a cost matrix can only be written as a list of points".into());
+    }
+    let b = parse_arg::<f64>(matches, "power")?.unwrap();
+    let result = match matches.value_of("method").unwrap() {
+        "idw" => {
+            println!("IDW");
+            idw_from_costs(&obs_points, &targets, &costs, b)?
+        }
+        "stewart" | "par_stewart" => {
+            let span = parse_arg::<f64>(matches, "span")?.unwrap_or(0.0);
+            if !(span > 0.0) {
+                return Err("Invalid or missing span value (arg. --span=value).".into());
+            }
+            let smooth_type = matches.value_of("function").unwrap().parse::<SmoothType>()?;
+            println!("stewart");
+            stewart_from_costs(&obs_points, &targets, &costs, smooth_type, span, b)?
+        }
+        other => return Err(ErrorKind::UnknownMethod(other.to_string()).into()),
+    };
+    let bbox = Bbox::from_points(&result);
+    save_result(result, &bbox, (targets.len(), 1), matches, crs.as_ref())
+}
+
+fn compute<T>(obs_points: Vec<T>, matches: &ArgMatches, crs: Option<Crs>) -> Result<()>
+    where T: PtValue + Serialize + Send + Sync
+{
+    if matches.is_present("costs") {
+        return compute_from_costs(obs_points, matches, crs);
+    }
+    match read_network::<T>(matches)? {
+        Some(network) => {
+            // Snap the cells once rather than for each of their distances
+            let grid = grid_spec(matches, &Bbox::from_points(&obs_points))?;
+            let cells = parallel::grid_cells::<T>(&grid.sampling_bbox(), grid.reso_x, grid.reso_y)
+                .iter()
+                .map(|cell| cell.get_coordinates())
+                .collect::<Vec<(f64, f64)>>();
+            let metric = NetworkMetric::new(&network, &obs_points).with_locations(&cells);
+            compute_with_barriers(obs_points, matches, crs, metric)
+        }
+        None => compute_with_barriers(obs_points, matches, crs, PointMetric::<T>::new()),
+    }
+}
+
+/// Compute with `metric`, or with the barriers given by --barriers added to it.
+fn compute_with_barriers<T, M>(obs_points: Vec<T>,
+                               matches: &ArgMatches,
+                               crs: Option<Crs>,
+                               metric: M)
+                               -> Result<()>
+    where T: PtValue + Serialize + Send + Sync,
+          M: DistanceMetric
+{
+    let path = match matches.value_of("barriers") {
+        Some(path) => path,
+        None => return compute_with_metric(obs_points, matches, crs, &metric),
+    };
+    let barriers = Barriers::from_geojson(path)?;
+    println!("{} barrier(s)", barriers.nb_barriers());
+    let crossing = match parse_arg::<f64>(matches, "barrier_penalty")? {
+        Some(penalty) => Crossing::Penalty(penalty),
+        None => Crossing::Block,
+    };
+    let metric = BarrierMetric::new(metric, &barriers, crossing)?;
+    compute_with_metric(obs_points, matches, crs, &metric)
+}
+
+/// Output grid of --template (or --weights), or of --cell_size or --scale
+/// over --window (or the extent of the data) extended by --buffer, the values
+/// being computed at the position given by --cell_position. An extent of the
+/// data without width or height (e.g. a single point) is only accepted along
+/// with a --window, a --buffer distance or a --cell_size.
+fn grid_spec(matches: &ArgMatches, data_extent: &Bbox) -> Result<GridSpec> {
+    let template = matches
+        .value_of("template")
+        .or_else(|| matches.value_of("weights"));
+    let grid = if let Some(path) = template {
+        GridSpec::from_template(path)?
+    } else {
+        let bbox = match matches.value_of("window") {
+            Some(window) => {
+                window
+                    .parse::<Bbox>()
+                    .chain_err(|| {
+                                   "Invalid --window (given as minx,miny,maxx,maxy since the \
+                                    order of earlier versions, minx,maxx,miny,maxy)"
+                               })?
+            }
+            None => *data_extent,
+        };
+        let bbox = match matches.value_of("buffer") {
+            Some(buffer) => {
+                match parse_buffer(buffer)? {
+                    (percent, true) => bbox.buffer_percent(percent),
+                    (dist, false) => bbox.buffer(dist),
+                }
+            }
+            None => bbox,
+        };
+        match matches.value_of("cell_size") {
+            Some(size) => {
+                let (cell_x, cell_y) = parse_cell_size(size)?;
+                let snap_origin = if matches.is_present("snap") {
+                    Some((0.0, 0.0))
+                } else {
+                    None
+                };
+                GridSpec::from_cell_size(&bbox, cell_x, cell_y, snap_origin)?
+            }
+            None => {
+                let (reso_x, reso_y) = parse_scale(matches.value_of("scale").unwrap())?;
+                if matches.value_of("window").is_none() &&
+                   !(bbox.width() > 0.0 && bbox.height() > 0.0) {
+                    return Err(format!("The extent of the data ({}) has no width or height \
+                                        (a single point, or points along a line): give the \
+                                        grid a size by --window, a --buffer distance or \
+                                        --cell_size",
+                                       bbox)
+                                       .into());
+                }
+                GridSpec::new(bbox, reso_x, reso_y)
+            }
+        }
+    };
+    Ok(match matches.value_of("cell_position") {
+           Some("centre") => grid.with_position(CellPosition::Centre),
+           Some("corner") => grid.with_position(CellPosition::Corner),
+           _ => grid,
+       })
+}
+
+fn compute_with_metric<T, M>(obs_points: Vec<T>,
+                             matches: &ArgMatches,
+                             crs: Option<Crs>,
+                             metric: &M)
+                             -> Result<()>
+    where T: PtValue + Serialize + Send + Sync,
+          M: DistanceMetric
+{
+    let method = matches.value_of("method").unwrap();
+    let b = parse_arg::<f64>(matches, "power")?.unwrap();
+    let span = parse_arg::<f64>(matches, "span")?.unwrap_or(0.0);
+    let grid = grid_spec(matches, &Bbox::from_points(&obs_points))?;
+    let (reso_x, reso_y) = (grid.reso_x, grid.reso_y);
+    let bbox = grid.sampling_bbox();
+    let cutoff = match (parse_arg::<f64>(matches, "cutoff")?,
+                        parse_arg::<f64>(matches, "cutoff_weight")?) {
+        (Some(dist), _) => Some(Cutoff::Distance(dist)),
+        (None, Some(weight)) => Some(Cutoff::Weight(weight)),
+        (None, None) => None,
+    };
+    let tile_size = parse_arg::<usize>(matches, "tile_size")?;
+    let result = match method {
+        "idw" => {
+            println!("IDW");
+            if let Some(tile_size) = tile_size {
+                let mut writer =
+                    tile_writer(matches, &grid.extent, (reso_x, reso_y), &cutoff, crs.as_ref())?;
+                return idw_tiled_with_metric(reso_x,
+                                             reso_y,
+                                             &bbox,
+                                             &obs_points,
+                                             b,
+                                             metric,
+                                             tile_size,
+                                             &mut writer);
+            }
+            match cutoff {
+                Some(cutoff) => {
+                    let (result, report) = idw_truncated_with_metric(reso_x,
+                                                                     reso_y,
+                                                                     &bbox,
+                                                                     &obs_points,
+                                                                     b,
+                                                                     cutoff,
+                                                                     metric)?;
+                    print_truncation_report(&report);
+                    result
+                }
+                None => {
+                    idw_interpolation_with_metric(reso_x, reso_y, &bbox, &obs_points, b, metric)?
+                }
+            }
+        }
+        "stewart" | "par_stewart" => {
+            if !(span > 0.0) {
+                return Err("Invalid or missing span value (arg. --span=value).".into());
+            }
+            let nb_core = if method == "par_stewart" {
+                parallel::num_threads() as u32
+            } else {
+                1
+            };
+            let smooth_type = matches.value_of("function").unwrap().parse::<SmoothType>()?;
+            let conf = StewartPotentialGrid::new(span,
+                                                 b,
+                                                 smooth_type,
+                                                 &bbox,
+                                                 reso_x,
+                                                 reso_y,
+                                                 nb_core);
+            let conf = match parse_arg::<f64>(matches, "theta")? {
+                Some(theta) => conf.with_engine(StewartEngine::BarnesHut(theta)),
+                None => conf,
+            };
+            if nb_core > 1 {
+                println!("stewart (using {:?} core)", nb_core);
+            } else {
+                println!("stewart");
+            }
+            if let Some(tile_size) = tile_size {
+                let mut writer =
+                    tile_writer(matches, &grid.extent, (reso_x, reso_y), &cutoff, crs.as_ref())?;
+                return stewart_tiled_with_metric(&conf,
+                                                 &obs_points,
+                                                 metric,
+                                                 tile_size,
+                                                 &mut writer);
+            }
+            match cutoff {
+                Some(cutoff) => {
+                    let (result, report) =
+                        stewart_truncated_with_metric(&conf, &obs_points, cutoff, metric)?;
+                    print_truncation_report(&report);
+                    result
+                }
+                None => stewart_with_metric(&conf, &obs_points, metric)?,
+            }
+        }
+        other => return Err(ErrorKind::UnknownMethod(other.to_string()).into()),
+    };
+    let stock = obs_points.iter().map(|pt| pt.get_value()).sum::<f64>();
+    let result = normalize_result(result, &grid, matches, stock)?;
+    save_result(result, &grid.extent, (reso_x, reso_y), matches, crs.as_ref())
+}
+
+/// Counts of the polygons of the input (in the field given by --field) spread
+/// on the grid by pycnophylactic interpolation, weighted by the raster given
+/// by --weights if any.
+fn compute_pycno<T>(matches: &ArgMatches, crs: Option<Crs>) -> Result<()>
+    where T: PtValue + Serialize + Send + Sync
+{
+    let file_path = matches.value_of("input").unwrap();
+    if !is_geojson(file_path) {
+        return Err("The pycno method requires a GeoJSON input of polygons".into());
+    }
+    let field_name = matches
+        .value_of("field")
+        .ok_or("Field name is required for the pycno method (arg. --field=name).")?;
+    let zones = parse_geojson_zones(file_path, field_name)?;
+    let no_extent = Bbox::new(::std::f64::INFINITY,
+                              ::std::f64::NEG_INFINITY,
+                              ::std::f64::INFINITY,
+                              ::std::f64::NEG_INFINITY);
+    let extent = zones
+        .iter()
+        .fold(no_extent, |extent, zone| extent.union(&zone.bbox()));
+    let grid = grid_spec(matches, &extent)?;
+    let weights = match matches.value_of("weights") {
+        Some(path) => Some(read_weight_raster(path, &grid)?),
+        None => None,
+    };
+    let (result, report) = pycnophylactic::<T>(&zones,
+                                                &grid,
+                                                weights.as_ref().map(|w| &w[..]),
+                                                &PycnoConfig::default())?;
+    if report.converged {
+        println!("pycno ({} iterations)", report.nb_iterations);
+    } else {
+        println!("pycno (not converged after {} iterations)", report.nb_iterations);
+    }
+    if !report.unassigned_zones.is_empty() {
+        println!("No cell for {} zone(s), whose counts are left out: {:?}",
+                 report.unassigned_zones.len(),
+                 report.unassigned_zones);
+    }
+    if !report.shared_zones.is_empty() {
+        println!("{} zone(s) holding no cell added to the nearest cell of another zone: {:?}",
+                 report.shared_zones.len(),
+                 report.shared_zones);
+    }
+    let stock = zones.iter().map(|zone| zone.count).sum::<f64>();
+    let result = normalize_result(result, &grid, matches, stock)?;
+    save_result(result, &grid.extent, (grid.reso_x, grid.reso_y), matches, crs.as_ref())
+}
+
+fn run() -> Result<()> {
     let matches = App::new("smoomars").version("0.1.0")
        .about("Compute inverse distance interpolation or population potentials.")
        .arg(Arg::with_name("method")
             .index(1)
             .value_name("METHOD")
             .required(true)
-            .possible_values(&["idw", "stewart", "par_stewart"])
-            .help("The method to use."))
+            .possible_values(&["idw", "stewart", "par_stewart", "pycno"])
+            .help("The method to use (pycno spreads the counts of the polygons of a GeoJSON input on the grid, preserving the total of each polygon)."))
        .arg(Arg::with_name("input")
             .short("i").long("input")
             .required(true).takes_value(true)
             .value_name("FILE")
-            .help("Input file to use (.csv, .json or .geojson). If .geojson, default to spherical distance."))
+            .help("Input file to use (.csv, .json, .geojson or newline-delimited .geojsonl/.geojsons). If GeoJSON, default to spherical distance (unless geodesic distance is requested)."))
         .arg(Arg::with_name("power")
              .short("p").long("power")
              .default_value("2")
@@ -34,22 +683,50 @@ fn main() {
              .takes_value(true)
              .default_value("Spherical")
              .value_name("TYPEDISTANCE")
-             .help("Cartesian/Spherical regarding to use euclidian distance or spherical distance"))
+             .help("Euclidian/Spherical/Geodesic regarding to use euclidian distance, spherical distance or distance along the geodesics of the WGS84 ellipsoid"))
         .arg(Arg::with_name("scale")
              .short("s").long("scale")
-             .required(true).takes_value(true)
+             .required_unless_one(&["costs", "cell_size", "template", "weights"]).takes_value(true)
              .value_name("SCALE")
+             .conflicts_with_all(&["cell_size", "template", "weights"])
              .help("Resolution of the output in number of cells as resoX-resoY."))
+        .arg(Arg::with_name("cell_size")
+             .long("cell_size")
+             .takes_value(true)
+             .value_name("SIZE")
+             .conflicts_with_all(&["template", "weights"])
+             .help("Size of the cells of the output, given as size or sizeX,sizeY (instead of --scale); the grid is extended to a whole number of cells."))
+        .arg(Arg::with_name("snap")
+             .long("snap")
+             .requires("cell_size")
+             .help("Move the edges of the grid out to multiples of the cell size, for it to line up with reference grids of the same cell size."))
+        .arg(Arg::with_name("template")
+             .long("template")
+             .takes_value(true)
+             .value_name("FILE")
+             .conflicts_with_all(&["window", "weights"])
+             .help("Compute the values on the grid of the pixels of this (north up) raster, in the CRS of the input (instead of --scale and --window)."))
+        .arg(Arg::with_name("cell_position")
+             .long("cell_position")
+             .takes_value(true)
+             .possible_values(&["corner", "centre"])
+             .help("Compute the value of each cell at its lower-left corner or at its centre (default: corner, centre with --template)."))
         .arg(Arg::with_name("window")
              .short("w").long("window")
              .takes_value(true).require_equals(true)
              .value_name("WINDOW")
-             .help("Coordinates of the visualisation window, given as minimum latitude,minimum longitude,maximum latitude,maximum longitude."))
+             .help("Coordinates of the visualisation window, given as minx,miny,maxx,maxy (minimum longitude,minimum latitude,maximum longitude,maximum latitude for geographic coordinates; in the CRS of --project if given). Earlier versions took minx,maxx,miny,maxy."))
+        .arg(Arg::with_name("buffer")
+             .long("buffer")
+             .takes_value(true)
+             .value_name("DISTANCE")
+             .conflicts_with_all(&["template", "weights"])
+             .help("Extend the window (or the extent of the observation points) by this distance on each side, or by this percentage of its width and height (e.g. 10%)."))
         .arg(Arg::with_name("output")
              .short("o").long("output")
              .required(true).takes_value(true)
              .value_name("FILE")
-             .help("Path for output file (according to the outfile extension, .json, .csv, .geojson and .geotiff are accepted)."))
+             .help("Path for output file (according to the outfile extension, .json, .csv, .geojson, .geojsonl/.geojsons and .geotiff are accepted)."))
          .arg(Arg::with_name("span")
             .long("span")
             .takes_value(true)
@@ -65,168 +742,233 @@ fn main() {
              .short("c").long("field_name")
              .takes_value(true)
              .value_name("FIELD")
-             .help("(Required for GeoJSON input) Field name containing the stock values to use."))
+             .help("(Required for GeoJSON input) Field name containing the stock values to use (name or index of the value column for CSV input)."))
+        .arg(Arg::with_name("cutoff")
+             .long("cutoff")
+             .takes_value(true)
+             .value_name("DISTANCE")
+             .conflicts_with("cutoff_weight")
+             .help("Ignore the observation points farther than this distance from a cell (idw and stewart methods)."))
+        .arg(Arg::with_name("cutoff_weight")
+             .long("cutoff_weight")
+             .takes_value(true)
+             .value_name("WEIGHT")
+             .help("Ignore the observation points whose weight is below this threshold (idw and stewart methods)."))
+        .arg(Arg::with_name("polygon")
+             .long("polygon")
+             .takes_value(true)
+             .default_value("centroid")
+             .possible_values(&["centroid", "point_on_surface"])
+             .help("How (multi)polygon features of GeoJSON input are turned into observation points."))
+        .arg(Arg::with_name("x_column")
+             .long("x_column")
+             .takes_value(true)
+             .value_name("COLUMN")
+             .help("Name or index of the column containing the x (or longitude) coordinate in CSV input (default: 0)."))
+        .arg(Arg::with_name("y_column")
+             .long("y_column")
+             .takes_value(true)
+             .value_name("COLUMN")
+             .help("Name or index of the column containing the y (or latitude) coordinate in CSV input (default: 1)."))
+        .arg(Arg::with_name("delimiter")
+             .long("delimiter")
+             .takes_value(true)
+             .value_name("CHAR")
+             .help("Field delimiter of the CSV input and output (default: ','; use '\\t' for tabulations)."))
+        .arg(Arg::with_name("quote")
+             .long("quote")
+             .takes_value(true)
+             .value_name("CHAR")
+             .help("Quote character of the CSV input (default: '\"')."))
+        .arg(Arg::with_name("decimal")
+             .long("decimal")
+             .takes_value(true)
+             .value_name("CHAR")
+             .help("Decimal separator used in the CSV input (default: '.')."))
+        .arg(Arg::with_name("no_header")
+             .long("no_header")
+             .help("The CSV input has no header row."))
+        .arg(Arg::with_name("skip_missing")
+             .long("skip_missing")
+             .help("Skip the CSV rows with missing or invalid values instead of failing."))
+        .arg(Arg::with_name("precision")
+             .long("precision")
+             .takes_value(true)
+             .value_name("DIGITS")
+             .help("Number of decimals of the values written in CSV output (the coordinates are written in full)."))
+        .arg(Arg::with_name("csv_grid")
+             .long("csv_grid")
+             .help("Write the CSV output as a matrix of the grid values instead of a list of points."))
+        .arg(Arg::with_name("skip_nodata")
+             .long("skip_nodata")
+             .help("Leave out nodata cells from the CSV output."))
+        .arg(Arg::with_name("nodata")
+             .long("nodata")
+             .takes_value(true)
+             .requires("skip_nodata")
+             .value_name("VALUE")
+             .help("Value also left out by --skip_nodata, besides the non-finite ones."))
+        .arg(Arg::with_name("theta")
+             .long("theta")
+             .takes_value(true)
+             .value_name("ANGLE")
+             .conflicts_with("network")
+             .help("Approximate the stewart potentials with a Barnes-Hut quadtree using this opening angle (e.g. 0.5; 0 is exact)."))
+        .arg(Arg::with_name("tile_size")
+             .long("tile_size")
+             .takes_value(true)
+             .value_name("CELLS")
+             .help("Compute the grid by tiles of CELLS x CELLS cells written one at a time to the .geotiff output, to bound the memory used."))
+        .arg(Arg::with_name("crs")
+             .long("crs")
+             .takes_value(true)
+             .value_name("EPSG")
+             .help("EPSG code of the CRS of the input coordinates (default: 4326 for spherical distance). The euclidian distance is used for projected CRS. The CRS is recorded in the output (in a .prj file next to CSV, JSON and GeoJSONSeq outputs)."))
+        .arg(Arg::with_name("project")
+             .long("project")
+             .takes_value(true)
+             .value_name("EPSG")
+             .help("Project the input points to this (projected) CRS and compute the grid in it, with the euclidian distance (e.g. 2154, 3035, 3857 or 326xx for UTM)."))
+        .arg(Arg::with_name("output_crs")
+             .long("output_crs")
+             .takes_value(true)
+             .value_name("EPSG")
+             .help("Transform the coordinates of the output points to this CRS (point outputs only)."))
+        .arg(Arg::with_name("network")
+             .long("network")
+             .takes_value(true)
+             .value_name("FILE")
+             .conflicts_with("project")
+             .help("Compute the distances along this network (a .geojson file of LineStrings, or a .csv list of edges as start x, start y, end x, end y, cost), from the nodes nearest to the points and cells (e.g. travel times; --span and --cutoff are then in the units of the costs)."))
+        .arg(Arg::with_name("network_cost")
+             .long("network_cost")
+             .takes_value(true)
+             .value_name("FIELD")
+             .requires("network")
+             .help("Property of the GeoJSON network features holding their cost (default: their length)."))
+        .arg(Arg::with_name("barriers")
+             .long("barriers")
+             .takes_value(true)
+             .value_name("FILE")
+             .conflicts_with("project")
+             .help("GeoJSON file of lines or polygons blocking the influence of the observation points across them (idw and stewart methods)."))
+        .arg(Arg::with_name("barrier_penalty")
+             .long("barrier_penalty")
+             .takes_value(true)
+             .value_name("DISTANCE")
+             .requires("barriers")
+             .help("Add this distance for each barrier crossed instead of blocking the influence."))
+        .arg(Arg::with_name("costs")
+             .long("costs")
+             .takes_value(true)
+             .value_name("FILE")
+             .requires("targets")
+             .conflicts_with_all(&["network", "barriers", "project"])
+             .help("Read the distances from the observation points (rows) to the --targets (columns) from this cost matrix (.npy, or .csv in the format of --costs_format), instead of computing them."))
+        .arg(Arg::with_name("costs_format")
+             .long("costs_format")
+             .takes_value(true)
+             .default_value("wide")
+             .possible_values(&["wide", "long"])
+             .help("Format of a CSV cost matrix: a header row then a row of costs per observation point (wide), or a header row then rows of observation point index, target index, cost (long, indices from 0)."))
+        .arg(Arg::with_name("targets")
+             .long("targets")
+             .takes_value(true)
+             .value_name("FILE")
+             .requires("costs")
+             .help("Locations at which the values are computed from the --costs matrix (read as the input, their values being ignored)."))
+        .arg(Arg::with_name("weights")
+             .long("weights")
+             .takes_value(true)
+             .value_name("FILE")
+             .conflicts_with("window")
+             .help("(pycno method) Raster of the weights of the cells (e.g. built-up areas) refining the result, its pixels being used as the grid (instead of --scale and --window)."))
+        .arg(Arg::with_name("normalize")
+             .long("normalize")
+             .takes_value(true)
+             .possible_values(&["volume", "percent", "density"])
+             .conflicts_with("costs")
+             .help("Rescale the grid: for its integral (the sum of the values times the areas of the cells, in square metres for geographic coordinates) to equal the total stock of the input (volume), as a percentage of its maximum (percent), or dividing each value by the area of its cell (density)."))
+        .arg(Arg::with_name("threads")
+             .long("threads")
+             .takes_value(true)
+             .value_name("N")
+             .help("Number of threads used by the computations (default: number of CPUs)."))
         .get_matches();
 
-    let b: f64 = matches.value_of("power").unwrap().parse::<f64>().unwrap();
-    let_scan!(matches.value_of("scale").unwrap(); (
-        let reso_lat: u32, "-", let reso_lon: u32));
-    let method = matches.value_of("method").unwrap();
+    if let Some(n_thread) = parse_arg::<usize>(&matches, "threads")? {
+        parallel::set_num_threads(n_thread)?;
+    }
     let file_path = matches.value_of("input").unwrap();
-    let mut dist = matches.value_of("distance").unwrap();
-    if file_path.contains("geojson") || file_path.contains("GEOJSON") {
-        dist = "Spherical";
+    let input_crs = parse_crs(&matches, "crs")?;
+    if matches.value_of("method") == Some("pycno") {
+        if matches.is_present("project") {
+            return Err("--project can't be used with the pycno method".into());
+        }
+        return match input_crs {
+                   Some(crs) if !crs.is_geographic() => {
+                       compute_pycno::<utils::CartesianPtValue>(&matches, Some(crs))
+                   }
+                   _ if matches.value_of("distance") == Some("Euclidian") => {
+                       compute_pycno::<utils::CartesianPtValue>(&matches, input_crs)
+                   }
+                   Some(crs) => compute_pycno::<utils::SphericalPtValue>(&matches, Some(crs)),
+                   None => {
+                       compute_pycno::<utils::SphericalPtValue>(&matches,
+                                                                Some(Crs::from_epsg(4326)?))
+                   }
+               };
+    }
+    if let Some(target) = parse_crs(&matches, "project")? {
+        if target.is_geographic() {
+            return Err(format!("Can't project to the geographic CRS {}", target).into());
+        }
+        let source = match input_crs {
+            Some(crs) => crs,
+            None => Crs::from_epsg(4326)?,
+        };
+        let obs_points = read_points::<utils::CartesianPtValue>(file_path, &matches)?;
+        let obs_points = projection::reproject(&obs_points, &source, &target);
+        return compute::<utils::CartesianPtValue>(obs_points, &matches, Some(target));
     }
-    let span = if matches.is_present("span") {
-        matches.value_of("span").unwrap().parse::<f64>().unwrap()
+    let dist = if input_crs.map_or(false, |crs| !crs.is_geographic()) {
+        "Euclidian"
+    } else if is_geojson(file_path) && matches.value_of("distance") != Some("Geodesic") {
+        "Spherical"
     } else {
-        0.0
+        matches.value_of("distance").unwrap()
     };
     match dist {
         "Spherical" => {
-            let obs_points_spherical;
-            if file_path.contains("geojson") || file_path.contains("GEOJSON") {
-                let field_name = if matches.is_present("field") {
-                    matches.value_of("field")
-                } else {
-                    None
-                };
-                if field_name.is_none() {
-                    panic!("Error: Field name is required for GeoJSON input (arg. --field=name).");
-                }
-                obs_points_spherical =
-                    utils::parse_geojson_points::<utils::SphericalPtValue>(file_path,
-                                                                           field_name.unwrap())
-                            .unwrap();
-            } else if file_path.contains("json") || file_path.contains("JSON") {
-                obs_points_spherical =
-                    utils::parse_json_points::<utils::SphericalPtValue>(file_path).unwrap();
-            } else {
-                obs_points_spherical =
-                    utils::parse_csv_points::<utils::SphericalPtValue>(file_path).unwrap();
-            }
-            let bbox = if matches.is_present("window") {
-                let_scan!(matches.value_of("window").unwrap(); (
-                    let min_lat: f64, ",", let max_lat: f64, ",", let min_lon: f64, ",", let max_lon: f64));
-                Bbox::new(min_lat, max_lat, min_lon, max_lon)
-            } else {
-                Bbox::from_points(&obs_points_spherical)
+            let crs = match input_crs {
+                Some(crs) => crs,
+                None => Crs::from_epsg(4326)?,
             };
-            let result = match method {
-                "idw" => {
-                    println!("IDW");
-                    idw_interpolation(reso_lat as u32,
-                                      reso_lon as u32,
-                                      &bbox,
-                                      &obs_points_spherical,
-                                      b)
-                            .unwrap()
-                }
-                "stewart" => {
-                    if span == 0.0 {
-                        panic!("Invalid or missing span value !")
-                    }
-                    println!("stewart");
-                    let conf = StewartPotentialGrid::new(span,
-                                                         b as f64,
-                                                         SmoothType::Exponential,
-                                                         &bbox,
-                                                         reso_lat as u32,
-                                                         reso_lon as u32,
-                                                         1);
-                    stewart(&conf, &obs_points_spherical).unwrap()
-                }
-                "par_stewart" => {
-                    if span == 0.0 {
-                        panic!("Invalid or missing span value !")
-                    }
-                    let nb_core = num_cpus::get() as u32;
-                    let conf = StewartPotentialGrid::new(span,
-                                                         b as f64,
-                                                         SmoothType::Exponential,
-                                                         &bbox,
-                                                         reso_lat as u32,
-                                                         reso_lon as u32,
-                                                         nb_core);
-                    println!("stewart (using {:?} core)", nb_core);
-                    stewart(&conf, &obs_points_spherical).unwrap()
-                }
-                &_ => unreachable!(),
+            let obs_points = read_points::<utils::SphericalPtValue>(file_path, &matches)?;
+            compute(obs_points, &matches, Some(crs))
+        }
+        "Geodesic" => {
+            let crs = match input_crs {
+                Some(crs) => crs,
+                None => Crs::from_epsg(4326)?,
             };
-            let output_path = matches.value_of("output").unwrap();
-            if output_path.contains("geojson") || output_path.contains("GEOJSON") {
-                utils::save_geojson_points(output_path, result).unwrap();
-            } else if output_path.contains("geotiff") {
-                utils::write_to_raster(result,
-                                       &bbox,
-                                       (reso_lat as u32, reso_lon as u32),
-                                       output_path)
-                        .unwrap();
-            } else {
-                utils::save_json_points(output_path, result).unwrap();
-            }
+            let obs_points = read_points::<utils::GeodesicPtValue>(file_path, &matches)?;
+            compute(obs_points, &matches, Some(crs))
         }
         "Euclidian" => {
-            let obs_points = if file_path.contains("json") || file_path.contains("JSON") {
-                utils::parse_json_points::<utils::CartesianPtValue>(file_path).unwrap()
-            } else {
-                utils::parse_csv_points::<utils::CartesianPtValue>(file_path).unwrap()
-            };
-            let bbox = if matches.is_present("window") {
-                let_scan!(matches.value_of("window").unwrap(); (
-                    let min_lat: f64, ",", let max_lat: f64, ",", let min_lon: f64, ",", let max_lon: f64));
-                Bbox::new(min_lat, max_lat, min_lon, max_lon)
-            } else {
-                Bbox::from_points(&obs_points)
-            };
-            let result = match method {
-                "idw" => {
-                    println!("IDW");
-                    idw_interpolation(reso_lat as u32, reso_lon as u32, &bbox, &obs_points, b)
-                        .unwrap()
-                }
-                "stewart" => {
-                    if span == 0.0 {
-                        panic!("Invalid or missing span value !")
-                    }
-                    println!("stewart");
-                    let conf = StewartPotentialGrid::new(span,
-                                                         b,
-                                                         SmoothType::Exponential,
-                                                         &bbox,
-                                                         reso_lat as u32,
-                                                         reso_lon as u32,
-                                                         1);
-                    stewart(&conf, &obs_points).unwrap()
-                }
-                "par_stewart" => {
-                    if span == 0.0 {
-                        panic!("Invalid or missing span value !")
-                    }
-                    let nb_core = num_cpus::get() as u32;
-                    let conf = StewartPotentialGrid::new(span,
-                                                         b,
-                                                         SmoothType::Exponential,
-                                                         &bbox,
-                                                         reso_lat as u32,
-                                                         reso_lon as u32,
-                                                         nb_core);
-                    println!("stewart (using {:?} core)", nb_core);
-                    stewart(&conf, &obs_points).unwrap()
-                }
-                &_ => unreachable!(),
-            };
-            let output_path = matches.value_of("output").unwrap();
-            if output_path.contains("geotiff") {
-                utils::write_to_raster(result,
-                                       &bbox,
-                                       (reso_lat as u32, reso_lon as u32),
-                                       output_path)
-                        .unwrap();
-            } else {
-                utils::save_json_points(output_path, result).unwrap();
-            }
+            let obs_points = read_points::<utils::CartesianPtValue>(file_path, &matches)?;
+            compute(obs_points, &matches, input_crs)
         }
-        &_ => panic!("Invalid distance type"),
-    };
+        other => Err(format!("Invalid distance type: '{}'", other).into()),
+    }
+}
+
+fn main() {
+    if let Err(ref e) = run() {
+        eprintln!("Error: {}", e);
+        for cause in e.iter().skip(1) {
+            eprintln!("Caused by: {}", cause);
+        }
+        ::std::process::exit(1);
+    }
 }
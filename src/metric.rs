@@ -0,0 +1,260 @@
+//! Distance metrics used by the interpolators.
+//!
+//! Each point type has its own distance (`PtValue::distance`), used by
+//! default; the `*_with_metric` functions (and `Rbf::with_metric`,
+//! `LocalRbf::with_metric`) compute the distances with any `DistanceMetric`
+//! instead, including closures of the coordinates of two locations,
+//! `|x1, y1, x2, y2| distance`.
+//!
+//! The batched kernels compute the distances from several cells at once
+//! (`DistanceMetric::distance_lanes`): the euclidean, Manhattan and Chebyshev
+//! distances do it with vector operations, the other metrics lane by lane.
+use std::f64;
+use std::marker::PhantomData;
+use bbox::Bbox;
+use errors::*;
+use geodesic::wgs84_distance;
+use kernel::per_lane;
+use utils::PtValue;
+use wide::f64x4;
+
+
+/// Radius (in metres) of the sphere used by the spherical distance.
+const R: f64 = 6372.8 * 1000.0;
+
+pub trait DistanceMetric: Send + Sync {
+    /// Distance between (x1, y1) and (x2, y2).
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64;
+    /// Distances between each lane of (`xs`, `ys`) and (x, y), by default
+    /// computed by `distance` one lane after the other.
+    #[inline(always)]
+    fn distance_lanes(&self, xs: f64x4, ys: f64x4, x: f64, y: f64) -> f64x4 {
+        per_lane(xs, ys, x, y, |x1, y1, x2, y2| self.distance(x1, y1, x2, y2))
+    }
+    /// Box (in the units of the coordinates) containing every location lying
+    /// within `dist` of (x, y), by default the whole plane (which is always
+    /// right, but makes the spatial indexes useless).
+    #[allow(unused_variables)]
+    fn search_window(&self, x: f64, y: f64, dist: f64) -> Bbox {
+        Bbox::new(f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY)
+    }
+    /// Whether `distance` is the euclidean distance between the coordinates.
+    fn is_planar(&self) -> bool {
+        false
+    }
+}
+
+/// Any function of the coordinates of two locations.
+impl<F> DistanceMetric for F
+    where F: Fn(f64, f64, f64, f64) -> f64 + Send + Sync
+{
+    #[inline(always)]
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        self(x1, y1, x2, y2)
+    }
+}
+
+/// Box of half-side `dist` around (x, y), for the metrics never smaller than
+/// the largest difference of coordinates.
+fn square(x: f64, y: f64, dist: f64) -> Bbox {
+    Bbox::new(x - dist, x + dist, y - dist, y + dist)
+}
+
+/// Euclidean distance between the coordinates.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Euclidean;
+
+impl DistanceMetric for Euclidean {
+    #[inline(always)]
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        let (dx, dy) = (x1 - x2, y1 - y2);
+        ((dx * dx) + (dy * dy)).sqrt()
+    }
+    #[inline(always)]
+    fn distance_lanes(&self, xs: f64x4, ys: f64x4, x: f64, y: f64) -> f64x4 {
+        let (dx, dy) = (xs - f64x4::splat(x), ys - f64x4::splat(y));
+        ((dx * dx) + (dy * dy)).sqrt()
+    }
+    fn search_window(&self, x: f64, y: f64, dist: f64) -> Bbox {
+        square(x, y, dist)
+    }
+    fn is_planar(&self) -> bool {
+        true
+    }
+}
+
+/// Sum of the absolute differences of the coordinates.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Manhattan;
+
+impl DistanceMetric for Manhattan {
+    #[inline(always)]
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        (x1 - x2).abs() + (y1 - y2).abs()
+    }
+    #[inline(always)]
+    fn distance_lanes(&self, xs: f64x4, ys: f64x4, x: f64, y: f64) -> f64x4 {
+        (xs - f64x4::splat(x)).abs() + (ys - f64x4::splat(y)).abs()
+    }
+    fn search_window(&self, x: f64, y: f64, dist: f64) -> Bbox {
+        square(x, y, dist)
+    }
+}
+
+/// Largest absolute difference of the coordinates.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Chebyshev;
+
+impl DistanceMetric for Chebyshev {
+    #[inline(always)]
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        (x1 - x2).abs().max((y1 - y2).abs())
+    }
+    #[inline(always)]
+    fn distance_lanes(&self, xs: f64x4, ys: f64x4, x: f64, y: f64) -> f64x4 {
+        (xs - f64x4::splat(x)).abs().max((ys - f64x4::splat(y)).abs())
+    }
+    fn search_window(&self, x: f64, y: f64, dist: f64) -> Bbox {
+        square(x, y, dist)
+    }
+}
+
+/// Minkowski distance of order `p`: `(|dx|^p + |dy|^p)^(1/p)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Minkowski {
+    p: f64,
+}
+
+impl Minkowski {
+    /// Fails unless `p >= 1` (below, the triangle inequality doesn't hold).
+    pub fn new(p: f64) -> Result<Self> {
+        if !(p >= 1.0) || p.is_infinite() {
+            return Err(format!("Invalid order for the Minkowski distance: {}", p).into());
+        }
+        Ok(Minkowski { p: p })
+    }
+
+    pub fn p(&self) -> f64 {
+        self.p
+    }
+}
+
+impl DistanceMetric for Minkowski {
+    #[inline(always)]
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        ((x1 - x2).abs().powf(self.p) + (y1 - y2).abs().powf(self.p)).powf(1.0 / self.p)
+    }
+    fn search_window(&self, x: f64, y: f64, dist: f64) -> Bbox {
+        square(x, y, dist)
+    }
+}
+
+/// Great circle distance (in metres) between geographic coordinates (longitude
+/// and latitude, in degrees) on a sphere of radius 6372.8 km, computed with
+/// the haversine formula.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Haversine;
+
+impl Haversine {
+    /// Area (in square metres) of the part of the sphere between the
+    /// longitudes and the latitudes (in degrees) of `bbox`.
+    pub fn area(&self, bbox: &Bbox) -> f64 {
+        let dlon = (bbox.max_x - bbox.min_x).min(360.0).to_radians();
+        let (lat1, lat2) = (bbox.min_y.max(-90.0).to_radians(), bbox.max_y.min(90.0).to_radians());
+        R * R * dlon * (lat2.sin() - lat1.sin())
+    }
+}
+
+impl DistanceMetric for Haversine {
+    #[inline(always)]
+    fn distance(&self, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+        let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+        let half_dlat = (lat2 - lat1) / 2.0;
+        let half_dlon = (lon2 - lon1).to_radians() / 2.0;
+        // Haversine of the central angle (clamped against rounding errors)
+        let h = half_dlat.sin().powi(2) + lat1.cos() * lat2.cos() * half_dlon.sin().powi(2);
+        2.0 * R * h.min(1.0).sqrt().asin()
+    }
+    fn search_window(&self, lon: f64, lat: f64, dist: f64) -> Bbox {
+        let angle = dist / R;
+        let dlat = angle.to_degrees();
+        let (min_lat, max_lat) = (lat - dlat, lat + dlat);
+        if min_lat <= -90.0 || max_lat >= 90.0 || angle >= f64::consts::FRAC_PI_2 {
+            return Bbox::new(-180.0, 180.0, min_lat.max(-90.0), max_lat.min(90.0));
+        }
+        // Largest longitude difference of a point at `angle` from (lon, lat)
+        let dlon = (angle.sin() / lat.to_radians().cos()).min(1.0).asin().to_degrees();
+        if lon - dlon < -180.0 || lon + dlon > 180.0 {
+            Bbox::new(-180.0, 180.0, min_lat, max_lat)
+        } else {
+            Bbox::new(lon - dlon, lon + dlon, min_lat, max_lat)
+        }
+    }
+}
+
+/// Length (in metres) of the geodesics of the WGS84 ellipsoid between
+/// geographic coordinates (longitude and latitude, in degrees).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Geodesic;
+
+impl DistanceMetric for Geodesic {
+    #[inline(always)]
+    fn distance(&self, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+        wgs84_distance(lon1, lat1, lon2, lat2)
+    }
+    fn search_window(&self, lon: f64, lat: f64, dist: f64) -> Bbox {
+        // Geodesics are never more than 0.6% shorter than the arcs of the
+        // sphere of radius R joining the same coordinates
+        Haversine.search_window(lon, lat, dist * 1.01)
+    }
+}
+
+/// The distance of the point type `T`, used when no other metric is given.
+pub struct PointMetric<T> {
+    point_type: PhantomData<fn() -> T>,
+}
+
+impl<T> PointMetric<T> {
+    pub fn new() -> Self {
+        PointMetric { point_type: PhantomData }
+    }
+}
+
+impl<T> Default for PointMetric<T> {
+    fn default() -> Self {
+        PointMetric::new()
+    }
+}
+
+impl<T> Clone for PointMetric<T> {
+    fn clone(&self) -> Self {
+        PointMetric::new()
+    }
+}
+
+impl<T> Copy for PointMetric<T> {}
+
+impl<T> ::std::fmt::Debug for PointMetric<T> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "PointMetric")
+    }
+}
+
+impl<T> DistanceMetric for PointMetric<T>
+    where T: PtValue
+{
+    #[inline(always)]
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        T::distance_between(x1, y1, x2, y2)
+    }
+    #[inline(always)]
+    fn distance_lanes(&self, xs: f64x4, ys: f64x4, x: f64, y: f64) -> f64x4 {
+        T::distance_lanes(xs, ys, x, y)
+    }
+    fn search_window(&self, x: f64, y: f64, dist: f64) -> Bbox {
+        T::search_window(x, y, dist)
+    }
+    fn is_planar(&self) -> bool {
+        T::is_planar()
+    }
+}
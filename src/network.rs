@@ -0,0 +1,383 @@
+//! Distances along a network (e.g. roads, with travel times as costs): the
+//! locations are snapped to the nearest node of the network, and their
+//! distance is the cost of the shortest path between these nodes.
+//!
+//! The network is read from files only: a GeoJSON FeatureCollection of
+//! (Multi)LineStrings, or a CSV list of edges.
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::f64;
+use std::fs::File;
+use std::io::Read;
+use csv;
+use geojson::{GeoJson, Value};
+use rayon::prelude::*;
+use serde_json;
+use bbox::Bbox;
+use errors::*;
+use index::GridIndex;
+use metric::{DistanceMetric, Euclidean};
+use utils::{CartesianPtValue, PtValue};
+
+
+/// Undirected graph whose nodes are located by their coordinates.
+#[derive(Debug, Clone)]
+pub struct Network {
+    nodes: Vec<(f64, f64)>,
+    /// Adjacency lists in compressed form: the edges of node `i` lead to
+    /// `targets[edge_start[i]..edge_start[i + 1]]`, with the matching `costs`.
+    edge_start: Vec<usize>,
+    targets: Vec<usize>,
+    costs: Vec<f64>,
+    index: GridIndex,
+    /// Initial half-side of the window searched for the nearest node.
+    search_radius: f64,
+}
+
+impl Network {
+    /// Network made of `edges`, given as (start, end, cost), whose ends
+    /// sharing the same coordinates are the same node. The edges can be
+    /// traversed both ways.
+    pub fn from_edges(edges: &[((f64, f64), (f64, f64), f64)]) -> Result<Self> {
+        if edges.is_empty() {
+            return Err("The network has no edges".into());
+        }
+        let mut nodes = Vec::new();
+        let mut node_ids = HashMap::new();
+        let mut pairs = Vec::with_capacity(edges.len());
+        for (ix, &(start, end, cost)) in edges.iter().enumerate() {
+            if !(cost >= 0.0) || cost.is_infinite() {
+                return Err(format!("Invalid cost for edge {}: {}", ix, cost).into());
+            }
+            let mut node_id = |(x, y): (f64, f64)| -> Result<usize> {
+                if !(x.is_finite() && y.is_finite()) {
+                    return Err(format!("Invalid coordinates for edge {}: ({}, {})", ix, x, y)
+                                   .into());
+                }
+                // Adding 0.0 turns -0.0 into 0.0, so that both are the same node
+                let key = ((x + 0.0).to_bits(), (y + 0.0).to_bits());
+                Ok(*node_ids
+                        .entry(key)
+                        .or_insert_with(|| {
+                                            nodes.push((x, y));
+                                            nodes.len() - 1
+                                        }))
+            };
+            let (a, b) = (node_id(start)?, node_id(end)?);
+            pairs.push((a, b, cost));
+        }
+        let mut degree = vec![0; nodes.len() + 1];
+        for &(a, b, _) in &pairs {
+            degree[a + 1] += 1;
+            degree[b + 1] += 1;
+        }
+        let mut edge_start = degree;
+        for i in 0..nodes.len() {
+            edge_start[i + 1] += edge_start[i];
+        }
+        let mut next = edge_start.clone();
+        let (mut targets, mut costs) = (vec![0; 2 * pairs.len()], vec![0.0; 2 * pairs.len()]);
+        for &(a, b, cost) in &pairs {
+            for &(from, to) in &[(a, b), (b, a)] {
+                targets[next[from]] = to;
+                costs[next[from]] = cost;
+                next[from] += 1;
+            }
+        }
+        let points = nodes
+            .iter()
+            .map(|&(x, y)| CartesianPtValue::new(x, y, 0.0))
+            .collect::<Vec<_>>();
+        let (mut min_x, mut max_x, mut min_y, mut max_y) =
+            (f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY);
+        for &(x, y) in &nodes {
+            min_x = min_x.min(x);
+            max_x = max_x.max(x);
+            min_y = min_y.min(y);
+            max_y = max_y.max(y);
+        }
+        let (width, height) = (max_x - min_x, max_y - min_y);
+        // About one node per bucket
+        let search_radius = if width > 0.0 && height > 0.0 {
+            (width * height / nodes.len() as f64).sqrt()
+        } else if width + height > 0.0 {
+            (width + height) / nodes.len() as f64
+        } else {
+            1.0
+        };
+        Ok(Network {
+               index: GridIndex::new(&points, (search_radius, search_radius)),
+               nodes: nodes,
+               edge_start: edge_start,
+               targets: targets,
+               costs: costs,
+               search_radius: search_radius,
+           })
+    }
+
+    /// Network of the (Multi)LineString features of a GeoJSON
+    /// FeatureCollection, each segment of which is an edge.
+    ///
+    /// The cost of a feature is read from its `cost_field` property and split
+    /// between its segments in proportion to their length according to
+    /// `metric`; without `cost_field`, the cost of a segment is its length.
+    pub fn from_geojson<M>(path: &str, cost_field: Option<&str>, metric: &M) -> Result<Self>
+        where M: DistanceMetric
+    {
+        let mut file = File::open(path)?;
+        let mut raw_json = String::new();
+        file.read_to_string(&mut raw_json)?;
+        let features = match raw_json.parse::<GeoJson>()? {
+            GeoJson::FeatureCollection(collection) => collection.features,
+            _ => return Err("Error: expected a FeatureCollection".into()),
+        };
+        let mut edges = Vec::new();
+        for (ix, ft) in features.iter().enumerate() {
+            let lines = match ft.geometry.as_ref().map(|g| &g.value) {
+                Some(&Value::LineString(ref line)) => vec![line.clone()],
+                Some(&Value::MultiLineString(ref lines)) => lines.clone(),
+                Some(_) => {
+                    return Err(format!("Feature {}: the network is made of LineStrings", ix)
+                                   .into())
+                }
+                None => return Err(format!("Feature {}: no geometry", ix).into()),
+            };
+            let mut segments = Vec::new();
+            for line in &lines {
+                for pair in line.windows(2) {
+                    if pair[0].len() < 2 || pair[1].len() < 2 {
+                        return Err(format!("Feature {}: invalid position", ix).into());
+                    }
+                    let (start, end) = ((pair[0][0], pair[0][1]), (pair[1][0], pair[1][1]));
+                    segments.push((start, end, metric.distance(start.0, start.1, end.0, end.1)));
+                }
+            }
+            if let Some(name) = cost_field {
+                let cost = match ft.properties.as_ref().and_then(|prop| prop.get(name)) {
+                    Some(&serde_json::Value::Number(ref val)) => val.as_f64(),
+                    Some(&serde_json::Value::String(ref val)) => val.trim().parse::<f64>().ok(),
+                    Some(_) => None,
+                    None => {
+                        return Err(format!("Feature {}: missing field \"{}\"", ix, name).into())
+                    }
+                };
+                let cost = cost.ok_or_else(|| {
+                                               format!("Feature {}: invalid value for field \"{}\"",
+                                                       ix,
+                                                       name)
+                                           })?;
+                let length = segments.iter().fold(0.0, |sum, &(_, _, len)| sum + len);
+                let nb_segments = segments.len() as f64;
+                for segment in &mut segments {
+                    segment.2 = if length > 0.0 {
+                        cost * segment.2 / length
+                    } else {
+                        cost / nb_segments
+                    };
+                }
+            }
+            edges.extend(segments);
+        }
+        Network::from_edges(&edges)
+    }
+
+    /// Network of the edges listed in a CSV file, after a header row, as
+    /// rows of five columns: the x and y coordinates of the start, those of
+    /// the end, and the cost of the edge.
+    pub fn from_csv(path: &str) -> Result<Self> {
+        let mut rdr = csv::Reader::from_file(path)?.has_headers(true);
+        let mut edges = Vec::new();
+        for (i, record) in rdr.records().enumerate() {
+            let record = record?;
+            if record.len() < 5 {
+                return Err(format!("Line {}: expected 5 columns (start x, start y, end x, \
+                                    end y, cost)",
+                                   i + 2)
+                                   .into());
+            }
+            let mut values = [0.0; 5];
+            for (v, field) in values.iter_mut().zip(&record) {
+                *v = field.trim().parse::<f64>()?;
+            }
+            edges.push(((values[0], values[1]), (values[2], values[3]), values[4]));
+        }
+        Network::from_edges(&edges)
+    }
+
+    pub fn nb_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Number of (undirected) edges.
+    pub fn nb_edges(&self) -> usize {
+        self.targets.len() / 2
+    }
+
+    /// Index of the node nearest to (x, y), in the units of the coordinates
+    /// (None if they aren't finite).
+    pub fn nearest_node(&self, x: f64, y: f64) -> Option<usize> {
+        if !(x.is_finite() && y.is_finite()) {
+            return None;
+        }
+        let mut radius = self.search_radius;
+        loop {
+            let mut best: Option<(usize, f64)> = None;
+            self.index
+                .query(&Bbox::new(x - radius, x + radius, y - radius, y + radius),
+                       |ix| {
+                           let (nx, ny) = self.nodes[ix];
+                           let dist = Euclidean.distance(x, y, nx, ny);
+                           match best {
+                               Some((b, d)) if d < dist || (d == dist && b < ix) => {}
+                               _ => best = Some((ix, dist)),
+                           }
+                       });
+            match best {
+                // A node of the window may be farther than a node outside of it
+                Some((ix, dist)) if dist <= radius => return Some(ix),
+                _ => radius *= 2.0,
+            }
+        }
+    }
+
+    /// Cost of the shortest paths from node `source` to every node (infinite
+    /// for the nodes which can't be reached), by Dijkstra's algorithm.
+    pub fn shortest_paths(&self, source: usize) -> Vec<f64> {
+        let mut costs = vec![f64::INFINITY; self.nodes.len()];
+        let mut heap = BinaryHeap::new();
+        costs[source] = 0.0;
+        heap.push(Visit {
+                      cost: 0.0,
+                      node: source,
+                  });
+        while let Some(Visit { cost, node }) = heap.pop() {
+            if cost > costs[node] {
+                continue;
+            }
+            for e in self.edge_start[node]..self.edge_start[node + 1] {
+                let (next, next_cost) = (self.targets[e], cost + self.costs[e]);
+                if next_cost < costs[next] {
+                    costs[next] = next_cost;
+                    heap.push(Visit {
+                                  cost: next_cost,
+                                  node: next,
+                              });
+                }
+            }
+        }
+        costs
+    }
+}
+
+/// Node to visit, ordered so that the `BinaryHeap` pops the cheapest first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Visit {
+    cost: f64,
+    node: usize,
+}
+
+impl Eq for Visit {}
+
+impl Ord for Visit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+
+impl PartialOrd for Visit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Cost of the shortest path along a `Network` between the nodes nearest to
+/// two locations.
+///
+/// The costs from the observation points given to `new` to every node are
+/// computed once (in parallel), and the nodes of the points and of the
+/// locations given to `with_locations` (e.g. the cells of the grid) are only
+/// searched for once. Other locations are snapped on each call, and any
+/// distance involving no observation point requires a search of its own
+/// (so this metric isn't suited to the Barnes-Hut engine, whose cells are
+/// seen from the centres of mass of groups of points).
+#[derive(Debug, Clone)]
+pub struct NetworkMetric<'a> {
+    network: &'a Network,
+    /// Node of each observation point and location, by coordinates.
+    snapped: HashMap<(u64, u64), usize>,
+    /// Costs from the node of the observation points to every node.
+    sources: HashMap<usize, Vec<f64>>,
+}
+
+impl<'a> NetworkMetric<'a> {
+    pub fn new<T>(network: &'a Network, obs_points: &[T]) -> Self
+        where T: PtValue
+    {
+        let mut snapped = HashMap::new();
+        for pt in obs_points {
+            let (x, y) = pt.get_coordinates();
+            if let Some(node) = network.nearest_node(x, y) {
+                snapped.insert(coordinates_key(x, y), node);
+            }
+        }
+        let mut nodes = snapped.values().cloned().collect::<Vec<usize>>();
+        nodes.sort();
+        nodes.dedup();
+        let sources = nodes
+            .into_par_iter()
+            .map(|node| (node, network.shortest_paths(node)))
+            .collect();
+        NetworkMetric {
+            network: network,
+            snapped: snapped,
+            sources: sources,
+        }
+    }
+
+    /// Snap `locations` (e.g. the cells of the grid, at which the distances
+    /// will be computed) to their nodes once and for all.
+    pub fn with_locations(mut self, locations: &[(f64, f64)]) -> Self {
+        let network = self.network;
+        let nodes = locations
+            .par_iter()
+            .map(|&(x, y)| (coordinates_key(x, y), network.nearest_node(x, y)))
+            .collect::<Vec<_>>();
+        for (key, node) in nodes {
+            if let Some(node) = node {
+                self.snapped.entry(key).or_insert(node);
+            }
+        }
+        self
+    }
+
+    fn node(&self, x: f64, y: f64) -> Option<usize> {
+        match self.snapped.get(&coordinates_key(x, y)) {
+            Some(&node) => Some(node),
+            None => self.network.nearest_node(x, y),
+        }
+    }
+}
+
+fn coordinates_key(x: f64, y: f64) -> (u64, u64) {
+    ((x + 0.0).to_bits(), (y + 0.0).to_bits())
+}
+
+impl<'a> DistanceMetric for NetworkMetric<'a> {
+    fn distance(&self, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        let (a, b) = match (self.node(x1, y1), self.node(x2, y2)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return f64::NAN,
+        };
+        if let Some(costs) = self.sources.get(&b) {
+            costs[a]
+        } else if let Some(costs) = self.sources.get(&a) {
+            costs[b]
+        } else {
+            self.network.shortest_paths(a)[b]
+        }
+    }
+}
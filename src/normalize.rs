@@ -0,0 +1,108 @@
+//! Rescaling of the result grids, to express their values (e.g. potentials,
+//! in "stock × weight" units) in units easier to explain.
+//!
+//! The cells are the ones of a `GridSpec`: on geographic coordinates, their
+//! areas are the ones of the sphere of the spherical distance, shrinking
+//! towards the poles.
+use std::f64;
+use bbox::Bbox;
+use errors::*;
+use grid::GridSpec;
+use metric::Haversine;
+use utils::PtValue;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    /// Rescale the values for the integral of the surface over the grid (the
+    /// sum of the values times the areas of their cells) to equal this total
+    /// (e.g. the total stock of the observation points): the values become
+    /// densities of stock per unit area.
+    Volume(f64),
+    /// Percentage of the largest value.
+    PercentOfMax,
+    /// Value divided by the area of its cell.
+    Density,
+}
+
+/// Areas of the cells of `grid`, in the order of the cells returned by the
+/// grid functions: in square units of the coordinates for the planar point
+/// types, in square metres otherwise.
+pub fn cell_areas<T>(grid: &GridSpec) -> Vec<f64>
+    where T: PtValue
+{
+    let (cell_x, cell_y) = grid.cell_size();
+    // The cells of a row all have the same area
+    let row_areas = (0..grid.reso_y)
+        .map(|j| if T::is_planar() {
+                 cell_x * cell_y
+             } else {
+                 let min_y = grid.extent.min_y + cell_y * j as f64;
+                 Haversine.area(&Bbox::new(grid.extent.min_x,
+                                           grid.extent.min_x + cell_x,
+                                           min_y,
+                                           min_y + cell_y))
+             })
+        .collect::<Vec<f64>>();
+    let mut areas = Vec::with_capacity(grid.reso_x * grid.reso_y);
+    for _ in 0..grid.reso_x {
+        areas.extend_from_slice(&row_areas);
+    }
+    areas
+}
+
+/// Rescale the values of the cells of `grid` (as returned by the grid
+/// functions) as given by `normalization`. NaN values are left as is.
+pub fn normalize<T>(cells: &mut [T], grid: &GridSpec, normalization: Normalization) -> Result<()>
+    where T: PtValue
+{
+    grid.check()?;
+    if cells.len() != grid.reso_x * grid.reso_y {
+        return Err(ErrorKind::DimensionMismatch(grid.reso_x * grid.reso_y, cells.len()).into());
+    }
+    let areas = cell_areas::<T>(grid);
+    match normalization {
+        Normalization::Volume(total) => {
+            let integral = cells
+                .iter()
+                .zip(&areas)
+                .map(|(cell, area)| cell.get_value() * area)
+                .filter(|volume| !volume.is_nan())
+                .sum::<f64>();
+            if !total.is_finite() || !integral.is_finite() || integral == 0.0 {
+                return Err(format!("Can't rescale a surface of integral {} to {}",
+                                   integral,
+                                   total)
+                                   .into());
+            }
+            let factor = total / integral;
+            for cell in cells.iter_mut() {
+                let value = cell.get_value();
+                cell.set_value(value * factor);
+            }
+        }
+        Normalization::PercentOfMax => {
+            let max = cells
+                .iter()
+                .map(|cell| cell.get_value())
+                .fold(f64::NAN, f64::max);
+            if !(max > 0.0) || max.is_infinite() {
+                return Err(format!("Can't express the values as a percentage of their \
+                                    maximum {}",
+                                   max)
+                                   .into());
+            }
+            for cell in cells.iter_mut() {
+                let value = cell.get_value();
+                cell.set_value(value * 100.0 / max);
+            }
+        }
+        Normalization::Density => {
+            for (cell, area) in cells.iter_mut().zip(&areas) {
+                let value = cell.get_value();
+                cell.set_value(value / area);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! Thread pool shared by the parallel computations.
+//!
+//! Every grid and batch evaluation of the crate runs on the global rayon
+//! pool, which is created on first use with one thread per logical CPU.
+//! Each value is computed by a single thread, in the same order as in a
+//! serial computation, so the results don't depend on the number of threads.
+use rayon;
+use rayon::prelude::*;
+use errors::*;
+use bbox::Bbox;
+use utils::PtValue;
+
+
+/// Set the number of threads of the shared pool.
+///
+/// This has to be done before the first parallel computation, and only once.
+pub fn set_num_threads(n_thread: usize) -> Result<()> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(n_thread)
+        .build_global()
+        .map_err(|e| format!("Unable to configure the thread pool ({})", e).into())
+}
+
+/// Number of threads of the shared pool.
+pub fn num_threads() -> usize {
+    rayon::current_num_threads()
+}
+
+/// Cells of a grid of `reso_x` by `reso_y` nodes over `bbox`, with a null
+/// value, stored as `[i * reso_y + j]`.
+pub fn grid_cells<T>(bbox: &Bbox, reso_x: usize, reso_y: usize) -> Vec<T>
+    where T: PtValue
+{
+    let x_step = (bbox.max_x - bbox.min_x) / reso_x as f64;
+    let y_step = (bbox.max_y - bbox.min_y) / reso_y as f64;
+    let mut cells = Vec::with_capacity(reso_x * reso_y);
+    for i in 0..reso_x {
+        for j in 0..reso_y {
+            cells.push(T::new(bbox.min_x + x_step * i as f64,
+                              bbox.min_y + y_step * j as f64,
+                              0.0));
+        }
+    }
+    cells
+}
+
+/// Set the value of each cell to `func(x, y)`, in parallel.
+pub fn fill_grid<T, F>(cells: &mut [T], func: F)
+    where T: PtValue + Send,
+          F: Fn(f64, f64) -> f64 + Sync
+{
+    cells
+        .par_iter_mut()
+        .for_each(|cell| {
+                      let (x, y) = cell.get_coordinates();
+                      cell.set_value(func(x, y));
+                  });
+}
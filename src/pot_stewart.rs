@@ -3,33 +3,108 @@ use utils::*;
 use bbox::Bbox;
 use std::f64;
 use std::marker::{Send, Sync};
-use jobsteal::{make_pool, BorrowSpliteratorMut, Spliterator};
+use rayon::prelude::*;
+use fft::grid_convolution;
+use parallel::{fill_grid, grid_cells};
+use costs::CostMatrix;
+use cutoff::{Cutoff, TruncationReport, sample_cells, truncation_report};
+use index::GridIndex;
+use quadtree::MassTree;
+use interaction::InteractionMatrix;
+use kernel::{PointColumns, fill_cells, weighted_sums};
+use metric::{DistanceMetric, PointMetric};
+use std::marker::PhantomData;
+use std::str::FromStr;
+use tiled::{compute_tiled, compute_tiled_batched, TileWriter};
+use wide::f64x4;
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub enum SmoothType {
     Exponential = 0,
     Pareto,
 }
 
+impl FromStr for SmoothType {
+    type Err = Error;
+
+    /// Parse the name of a smoothing function ("exponential" or "pareto").
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "exponential" => Ok(SmoothType::Exponential),
+            "pareto" => Ok(SmoothType::Pareto),
+            _ => Err(ErrorKind::UnknownMethod(s.to_string()).into()),
+        }
+    }
+}
+
+/// Algorithm used to compute the potentials of a `StewartPotentialGrid`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StewartEngine {
+    /// Use the FFT engine when the points have a planar distance, the span
+    /// covers at least `FFT_MIN_SPAN_CELLS` cells, `beta >= 1` (the
+    /// conditions of its tolerance) and the grid is large enough for it to
+    /// pay off (but not so large that its transforms would exceed 2^24
+    /// nodes); use the exact computation otherwise.
+    Auto,
+    /// Sum the contributions of every observation point to every cell.
+    Exact,
+    /// Spread the observation points on the nodes of the grid and convolve
+    /// them with the smoothing function using FFTs. Points lying on the
+    /// nodes give the same results as `Exact` (up to rounding errors);
+    /// otherwise, with a span of at least `FFT_MIN_SPAN_CELLS` cells and
+    /// `beta >= 1`, the results differ from `Exact` by less than 1% of the
+    /// maximum potential.
+    /// Only available for points with a planar (euclidean) distance, and for
+    /// grids whose padded transforms (about twice the grid and the points
+    /// around it, rounded up to powers of two) hold at most 2^24 nodes.
+    Fft,
+    /// Barnes–Hut approximation: the observation points are gathered in a
+    /// quadtree and each node seen from a cell under an angle below the given
+    /// opening angle (the ratio between the radius of the node and its distance
+    /// to the cell) contributes as a single mass at its center of mass.
+    /// An angle of 0 gives the exact potentials; larger angles are faster and
+    /// less accurate, the error depending on the spread of the points with
+    /// regard to the span (sparse points need smaller angles): measure it on
+    /// comparable data with `examples/barnes_hut_error.rs`. Usable with any
+    /// distance, in particular for spherical coordinates.
+    /// Never selected by `Auto`.
+    BarnesHut(f64),
+}
+
+/// Minimum span, in number of cells, for the FFT engine to be used automatically.
+pub const FFT_MIN_SPAN_CELLS: f64 = 5.0;
+/// Minimum number of (observation point, cell) pairs for the FFT engine to be used automatically.
+const FFT_MIN_PAIRS: u64 = 1 << 24;
+/// Maximum extent (in multiples of the output grid size) of the grid holding the binned points.
+const FFT_MAX_EXTENT: usize = 4;
+/// Maximum number of nodes of the (padded) transforms of the FFT engine, each
+/// of the four arrays it needs taking 8 bytes per node.
+const FFT_MAX_NODES: usize = 1 << 24;
+
 #[derive(Debug, Clone)]
 pub struct StewartPotentialGrid<'a> {
+    smooth_type: SmoothType,
     smooth_func: fn(f64, f64, f64) -> f64,
-    reso_x: u32,
-    reso_y: u32,
+    reso_x: usize,
+    reso_y: usize,
+    span: f64,
     beta: f64,
     alpha: f64,
     bbox: &'a Bbox,
     n_thread: u32,
+    engine: StewartEngine,
 }
 
 impl<'a> StewartPotentialGrid<'a> {
+    /// With `n_thread` below 2 the potentials are computed serially, otherwise on
+    /// the shared thread pool (see `parallel::set_num_threads` for its size).
     pub fn new(span: f64,
                beta: f64,
                interaction_type: SmoothType,
                bbox: &'a Bbox,
-               reso_x: u32,
-               reso_y: u32,
+               reso_x: usize,
+               reso_y: usize,
                n_thread: u32)
                -> Self {
         match interaction_type {
@@ -38,10 +113,13 @@ impl<'a> StewartPotentialGrid<'a> {
                     bbox: bbox,
                     reso_x: reso_x,
                     reso_y: reso_y,
+                    span: span,
                     beta: beta,
                     alpha: 0.69314718055994529 / (span).powf(beta),
+                    smooth_type: interaction_type,
                     smooth_func: exponential,
                     n_thread: n_thread,
+                    engine: StewartEngine::Auto,
                 }
             }
             SmoothType::Pareto => {
@@ -49,55 +127,106 @@ impl<'a> StewartPotentialGrid<'a> {
                     reso_x: reso_x,
                     reso_y: reso_y,
                     bbox: bbox,
+                    span: span,
                     beta: beta,
                     alpha: ((2.0 as f64).powf(1.0 / beta) - 1.0) / span,
+                    smooth_type: interaction_type,
                     smooth_func: pareto,
                     n_thread: n_thread,
+                    engine: StewartEngine::Auto,
                 }
             }
         }
     }
+
+    /// Select the algorithm used to compute the potentials (`StewartEngine::Auto` by default).
+    pub fn with_engine(mut self, engine: StewartEngine) -> Self {
+        self.engine = engine;
+        self
+    }
 }
 
 
 
-pub struct Stewart<'a, T: 'a> {
-    obs_points: &'a [T],
+pub struct Stewart<'a, T: 'a, M: 'a> {
+    points: PointColumns,
     // span: f64,
     beta: f64,
     alpha: f64,
-    distance_function: fn(f64, f64, f64) -> f64,
+    smooth_type: SmoothType,
+    metric: &'a M,
+    obs_points: PhantomData<&'a [T]>,
 }
 
-impl<'a, T> Stewart<'a, T>
-    where T: PtValue
+impl<'a, T, M> Stewart<'a, T, M>
+    where T: PtValue,
+          M: DistanceMetric
 {
-    pub fn new(obs_points: &'a [T], distance_function: &str, span: f64, beta: Option<f64>) -> Self {
+    pub fn new(obs_points: &'a [T],
+               distance_function: &str,
+               span: f64,
+               beta: Option<f64>,
+               metric: &'a M)
+               -> Result<Self> {
         let _beta: f64 = if beta.is_some() { beta.unwrap() } else { 2.0 };
-        let (_dist_func, alpha): (fn(f64, f64, f64) -> f64, f64) = match distance_function {
-            "exponential" => (exponential, 0.69314718055994529 / (span).powf(_beta)),
-            "pareto" => (pareto, ((2.0 as f64).powf(1.0 / _beta) - 1.0) / span),
-            &_ => panic!("Invalid function name!"),
+        let smooth_type = distance_function.parse::<SmoothType>()?;
+        let alpha = match smooth_type {
+            SmoothType::Exponential => 0.69314718055994529 / (span).powf(_beta),
+            SmoothType::Pareto => ((2.0 as f64).powf(1.0 / _beta) - 1.0) / span,
         };
-        Stewart {
-            obs_points: obs_points,
-            // span: span,
-            beta: _beta,
-            alpha: alpha,
-            distance_function: _dist_func,
-        }
+        Ok(Stewart {
+               points: PointColumns::new(obs_points),
+               // span: span,
+               beta: _beta,
+               alpha: alpha,
+               smooth_type: smooth_type,
+               metric: metric,
+               obs_points: PhantomData,
+           })
     }
 
     pub fn interp_point(&self, pt: (f64, f64)) -> f64 {
-        let func = self.distance_function;
-        let value = self.obs_points
-            .iter()
-            .fold(0.0, |mut sum, obs_pt| {
-                let val = obs_pt.get_value();
-                sum += val * func(self.alpha, self.beta, obs_pt.distance(pt.0, pt.1));
-                sum
-            });
-        value
+        let mut value = [0.0];
+        self.interp_batch(&[pt], &mut value);
+        value[0]
+    }
+
+    fn interp_batch(&self, cells: &[(f64, f64)], out: &mut [f64]) {
+        potentials(self.smooth_type,
+                   self.alpha,
+                   self.beta,
+                   self.metric,
+                   &self.points,
+                   cells,
+                   out)
+    }
+}
+
+/// Potentials of `points` at `cells`, written to `out`.
+fn potentials<M>(smooth_type: SmoothType,
+                 alpha: f64,
+                 beta: f64,
+                 metric: &M,
+                 points: &PointColumns,
+                 cells: &[(f64, f64)],
+                 out: &mut [f64])
+    where M: DistanceMetric
+{
+    match smooth_type {
+        SmoothType::Exponential => {
+            weighted_sums(cells,
+                          points,
+                          metric,
+                          &|dist| exponential_lanes(alpha, beta, dist),
+                          out)
+        }
+        SmoothType::Pareto => {
+            weighted_sums(cells,
+                          points,
+                          metric,
+                          &|dist| pareto_lanes(alpha, beta, dist),
+                          out)
+        }
     }
 }
 
@@ -111,91 +240,411 @@ fn pareto(alpha: f64, beta: f64, dist: f64) -> f64 {
     (1.0 + alpha * dist).powf(-beta)
 }
 
-pub fn stewart_interpolation<T>(reso_x: u32,
-                                reso_y: u32,
+/// `exponential` on each lane of `dist`.
+#[inline(always)]
+fn exponential_lanes(alpha: f64, beta: f64, dist: f64x4) -> f64x4 {
+    (f64x4::splat(-alpha) * dist.powf(beta)).exp()
+}
+
+/// `pareto` on each lane of `dist`.
+#[inline(always)]
+fn pareto_lanes(alpha: f64, beta: f64, dist: f64x4) -> f64x4 {
+    (f64x4::ONE + f64x4::splat(alpha) * dist).powf(-beta)
+}
+
+pub fn stewart_interpolation<T>(reso_x: usize,
+                                reso_y: usize,
                                 bbox: &Bbox,
                                 obs_points: &[T],
                                 func_name: &str,
                                 span: f64,
                                 beta: Option<f64>)
                                 -> Result<Vec<T>>
-    where T: PtValue
+    where T: PtValue + Send + Sync
 {
+    stewart_interpolation_with_metric(reso_x,
+                                      reso_y,
+                                      bbox,
+                                      obs_points,
+                                      func_name,
+                                      span,
+                                      beta,
+                                      &PointMetric::<T>::new())
+}
 
-    let x_step = (bbox.max_x - bbox.min_x) / reso_x as f64;
-    let y_step = (bbox.max_y - bbox.min_y) / reso_y as f64;
-    let mut unknown_pts = Vec::with_capacity((reso_x * reso_y) as usize);
-    let stewart = Stewart::new(obs_points, func_name, span, beta);
-    for i in 0..reso_x {
-        for j in 0..reso_y {
-            let x = bbox.min_x + x_step * i as f64;
-            let y = bbox.min_y + y_step * j as f64;
-            let value = stewart.interp_point((x, y));
-            unknown_pts.push(T::new(x, y, value));
-        }
+/// Same as `stewart_interpolation`, the distances being computed with `metric`.
+pub fn stewart_interpolation_with_metric<T, M>(reso_x: usize,
+                                               reso_y: usize,
+                                               bbox: &Bbox,
+                                               obs_points: &[T],
+                                               func_name: &str,
+                                               span: f64,
+                                               beta: Option<f64>,
+                                               metric: &M)
+                                               -> Result<Vec<T>>
+    where T: PtValue + Send + Sync,
+          M: DistanceMetric
+{
+    bbox.check_grid(reso_x, reso_y)?;
+    if obs_points.is_empty() {
+        return Err(ErrorKind::EmptyInput.into());
     }
+    let stewart = Stewart::new(obs_points, func_name, span, beta, metric)?;
+    let mut unknown_pts = grid_cells(bbox, reso_x, reso_y);
+    fill_cells(&mut unknown_pts, true, |cells, out| stewart.interp_batch(cells, out));
     Ok(unknown_pts)
 }
 
 pub fn stewart<T>(stewart_config: &StewartPotentialGrid, obs_points: &[T]) -> Result<Vec<T>>
     where T: PtValue + Send + Sync
+{
+    stewart_with_metric(stewart_config, obs_points, &PointMetric::<T>::new())
+}
+
+/// Same as `stewart`, the distances being computed with `metric` (the FFT
+/// engine is only available if it is planar).
+pub fn stewart_with_metric<T, M>(stewart_config: &StewartPotentialGrid,
+                                 obs_points: &[T],
+                                 metric: &M)
+                                 -> Result<Vec<T>>
+    where T: PtValue + Send + Sync,
+          M: DistanceMetric
 {
     let (bbox, reso_x, reso_y) =
         (stewart_config.bbox, stewart_config.reso_x, stewart_config.reso_y);
+    bbox.check_grid(reso_x, reso_y)?;
+    if obs_points.is_empty() {
+        return Err(ErrorKind::EmptyInput.into());
+    }
     let x_step = (bbox.max_x - bbox.min_x) / reso_x as f64;
     let y_step = (bbox.max_y - bbox.min_y) / reso_y as f64;
-    let mut plots = Vec::with_capacity((reso_x * reso_y) as usize);
-    for i in 0..reso_x {
-        for j in 0..reso_y {
-            plots.push(T::new(bbox.min_x + x_step * i as f64,
-                              bbox.min_y + y_step * j as f64,
-                              0.0));
+    let mut plots = grid_cells(bbox, reso_x, reso_y);
+    if let StewartEngine::BarnesHut(theta) = stewart_config.engine {
+        if !(theta >= 0.0 && theta.is_finite()) {
+            return Err(format!("Invalid opening angle: {}", theta).into());
         }
+        do_pot_tree(&mut plots, obs_points, metric, stewart_config, theta);
+        return Ok(plots);
     }
-    if stewart_config.n_thread < 2 {
-        do_pot(&mut plots, obs_points, stewart_config)
-    } else {
-        do_pot_par(&mut plots, obs_points, stewart_config)
+    let use_fft = match stewart_config.engine {
+        StewartEngine::Exact | StewartEngine::BarnesHut(_) => false,
+        StewartEngine::Fft => {
+            if !metric.is_planar() {
+                return Err("The FFT engine requires a planar distance".into());
+            }
+            true
+        }
+        StewartEngine::Auto => {
+            metric.is_planar() && stewart_config.beta >= 1.0 &&
+            stewart_config.span >= FFT_MIN_SPAN_CELLS * x_step.max(y_step) &&
+            obs_points.len() as u64 * reso_x as u64 * reso_y as u64 >= FFT_MIN_PAIRS
+        }
+    };
+    if use_fft {
+        if do_pot_fft(&mut plots, obs_points, stewart_config, (x_step, y_step)) {
+            return Ok(plots);
+        } else if stewart_config.engine == StewartEngine::Fft {
+            return Err("Observation points too far from the grid, or grid too large, for the FFT \
+                        engine"
+                               .into());
+        }
     }
+    do_pot(&mut plots, obs_points, metric, stewart_config);
     Ok(plots)
 }
 
-fn do_pot<T>(flat_grid: &mut Vec<T>, obs_points: &[T], stewart_config: &StewartPotentialGrid)
-    where T: PtValue
+/// Compute the potentials on the grid of `stewart_config` by tiles of
+/// `tile_size` cells passed to `writer` (see `tiled::compute_tiled`).
+///
+/// The FFT engine needs the whole grid, so it is rejected, and the `Auto`
+/// engine always stands for the `Exact` one: the values are the ones of
+/// `stewart` with the `Exact` or `BarnesHut` engine of `stewart_config`, but
+/// can differ (within the tolerance of the FFT engine) from the ones of
+/// `stewart` with the `Auto` engine, on the grids where it takes the FFT one.
+pub fn stewart_tiled<T, W>(stewart_config: &StewartPotentialGrid,
+                           obs_points: &[T],
+                           tile_size: usize,
+                           writer: &mut W)
+                           -> Result<()>
+    where T: PtValue + Sync,
+          W: TileWriter
+{
+    stewart_tiled_with_metric(stewart_config,
+                              obs_points,
+                              &PointMetric::<T>::new(),
+                              tile_size,
+                              writer)
+}
+
+/// Same as `stewart_tiled`, the distances being computed with `metric`.
+pub fn stewart_tiled_with_metric<T, M, W>(stewart_config: &StewartPotentialGrid,
+                                          obs_points: &[T],
+                                          metric: &M,
+                                          tile_size: usize,
+                                          writer: &mut W)
+                                          -> Result<()>
+    where T: PtValue + Sync,
+          M: DistanceMetric,
+          W: TileWriter
+{
+    if obs_points.is_empty() {
+        return Err(ErrorKind::EmptyInput.into());
+    }
+    let (bbox, reso_x, reso_y) =
+        (stewart_config.bbox, stewart_config.reso_x, stewart_config.reso_y);
+    let (beta, alpha) = (stewart_config.beta, stewart_config.alpha);
+    match stewart_config.engine {
+        StewartEngine::Fft => Err("The FFT engine can't compute the potentials by tiles".into()),
+        StewartEngine::BarnesHut(theta) => {
+            if !(theta >= 0.0 && theta.is_finite()) {
+                return Err(format!("Invalid opening angle: {}", theta).into());
+            }
+            let func = stewart_config.smooth_func;
+            let tree = MassTree::new(obs_points, metric);
+            compute_tiled(bbox,
+                          reso_x,
+                          reso_y,
+                          tile_size,
+                          |x, y| tree.sum((x, y), metric, theta, |dist| func(alpha, beta, dist)),
+                          writer)
+        }
+        StewartEngine::Auto | StewartEngine::Exact => {
+            let points = PointColumns::new(obs_points);
+            compute_tiled_batched(bbox,
+                                  reso_x,
+                                  reso_y,
+                                  tile_size,
+                                  |cells, out| {
+                                      potentials(stewart_config.smooth_type,
+                                                 alpha,
+                                                 beta,
+                                                 metric,
+                                                 &points,
+                                                 cells,
+                                                 out)
+                                  },
+                                  writer)
+        }
+    }
+}
+
+/// Interaction matrix holding the weight of each observation point in each
+/// cell of the grid of `stewart_config`, to compute the potentials of several
+/// stocks on the same points (see `InteractionMatrix::apply`).
+///
+/// The matrix is dense without `cutoff` (and gives the potentials of the `Exact`
+/// engine, up to the rounding of its vector functions); with a `cutoff` it only
+/// holds the weights of the points within the cutoff distance of each cell (as
+/// `stewart_truncated`).
+/// The values of `obs_points` are not used.
+pub fn stewart_interaction_matrix<T>(stewart_config: &StewartPotentialGrid,
+                                     obs_points: &[T],
+                                     cutoff: Option<Cutoff>)
+                                     -> Result<InteractionMatrix>
+    where T: PtValue + Sync
+{
+    stewart_interaction_matrix_with_metric(stewart_config,
+                                           obs_points,
+                                           cutoff,
+                                           &PointMetric::<T>::new())
+}
+
+/// Same as `stewart_interaction_matrix`, the distances being computed with `metric`.
+pub fn stewart_interaction_matrix_with_metric<T, M>(stewart_config: &StewartPotentialGrid,
+                                                    obs_points: &[T],
+                                                    cutoff: Option<Cutoff>,
+                                                    metric: &M)
+                                                    -> Result<InteractionMatrix>
+    where T: PtValue + Sync,
+          M: DistanceMetric
 {
+    let (bbox, reso_x, reso_y) =
+        (stewart_config.bbox, stewart_config.reso_x, stewart_config.reso_y);
+    bbox.check_grid(reso_x, reso_y)?;
+    if obs_points.is_empty() {
+        return Err(ErrorKind::EmptyInput.into());
+    }
     let (beta, alpha) = (stewart_config.beta, stewart_config.alpha);
     let func = stewart_config.smooth_func;
-    for cell in flat_grid.iter_mut() {
-        // let (cell_x, cell_y) = cell.get_coordinates();
-        let value = obs_points
-            .iter()
-            .fold(0.0, |mut sum, obs_pt| {
-                let (x, y, val) = obs_pt.get_triplet();
-                sum += val * func(alpha, beta, cell.distance(x, y));
-                sum
-            });
-        cell.set_value(value);
+    let kernel = |dist: f64| func(alpha, beta, dist);
+    let cells = grid_cells::<T>(bbox, reso_x, reso_y);
+    match cutoff {
+        Some(cutoff) => {
+            let cutoff_dist = cutoff.distance(&kernel)?;
+            Ok(InteractionMatrix::sparse(&cells, obs_points, metric, kernel, cutoff_dist))
+        }
+        None => Ok(InteractionMatrix::dense(&cells, obs_points, metric, kernel)),
+    }
+}
+
+/// Potentials of `obs_points` at `targets`, the distances being read from
+/// `costs` (from the points to the targets) instead of being computed.
+pub fn stewart_from_costs<T>(obs_points: &[T],
+                             targets: &[T],
+                             costs: &CostMatrix,
+                             smooth_type: SmoothType,
+                             span: f64,
+                             beta: f64)
+                             -> Result<Vec<T>>
+    where T: PtValue + Sync
+{
+    if obs_points.is_empty() {
+        return Err(ErrorKind::EmptyInput.into());
     }
+    costs.check_dimensions(obs_points.len(), targets.len())?;
+    let (func, alpha): (fn(f64, f64, f64) -> f64, f64) = match smooth_type {
+        SmoothType::Exponential => (exponential, 0.69314718055994529 / (span).powf(beta)),
+        SmoothType::Pareto => (pareto, ((2.0 as f64).powf(1.0 / beta) - 1.0) / span),
+    };
+    let matrix = InteractionMatrix::from_costs(targets, costs, |cost| func(alpha, beta, cost))?;
+    matrix.apply_grid(&obs_points.iter().map(|pt| pt.get_value()).collect::<Vec<f64>>())
 }
 
-fn do_pot_par<T>(flat_grid: &mut Vec<T>, obs_points: &[T], stewart_config: &StewartPotentialGrid)
+/// Compute the potentials on the grid of `stewart_config`, dropping the
+/// contributions of the observation points beyond `cutoff`.
+///
+/// The points are looked up through a spatial index, so the cost depends on
+/// the number of points within the cutoff distance of each cell rather than
+/// on the total number of points.
+pub fn stewart_truncated<T>(stewart_config: &StewartPotentialGrid,
+                            obs_points: &[T],
+                            cutoff: Cutoff)
+                            -> Result<(Vec<T>, TruncationReport)>
     where T: PtValue + Send + Sync
 {
+    stewart_truncated_with_metric(stewart_config, obs_points, cutoff, &PointMetric::<T>::new())
+}
+
+/// Same as `stewart_truncated`, the distances being computed with `metric`.
+pub fn stewart_truncated_with_metric<T, M>(stewart_config: &StewartPotentialGrid,
+                                           obs_points: &[T],
+                                           cutoff: Cutoff,
+                                           metric: &M)
+                                           -> Result<(Vec<T>, TruncationReport)>
+    where T: PtValue + Send + Sync,
+          M: DistanceMetric
+{
+    let (bbox, reso_x, reso_y) =
+        (stewart_config.bbox, stewart_config.reso_x, stewart_config.reso_y);
+    bbox.check_grid(reso_x, reso_y)?;
+    if obs_points.is_empty() {
+        return Err(ErrorKind::EmptyInput.into());
+    }
     let (beta, alpha) = (stewart_config.beta, stewart_config.alpha);
     let func = stewart_config.smooth_func;
-    let mut pool = make_pool(stewart_config.n_thread as usize).unwrap();
-    flat_grid
-        .split_iter_mut()
-        .for_each(&pool.spawner(), |cell| {
-            let value = obs_points
+    let kernel = |dist: f64| func(alpha, beta, dist);
+    let cutoff_dist = cutoff.distance(&kernel)?;
+    let window = metric.search_window((bbox.min_x + bbox.max_x) / 2.0,
+                                      (bbox.min_y + bbox.max_y) / 2.0,
+                                      cutoff_dist);
+    let index = GridIndex::new(obs_points,
+                               (window.max_x - window.min_x, window.max_y - window.min_y));
+    let mut plots = grid_cells(bbox, reso_x, reso_y);
+    fill_grid(&mut plots, |x, y| {
+        let mut value = 0.0;
+        index.within(obs_points, metric, x, y, cutoff_dist, |ix, dist| {
+            value += obs_points[ix].get_value() * kernel(dist);
+        });
+        value
+    });
+    let samples = sample_cells(plots.len())
+        .into_par_iter()
+        .map(|ix| {
+            let cell: &T = &plots[ix];
+            let (cx, cy) = cell.get_coordinates();
+            let full = obs_points
                 .iter()
-                .fold(0.0, |mut sum, obs_pt| {
+                .fold(0.0, |sum, obs_pt| {
                     let (x, y, val) = obs_pt.get_triplet();
-                    sum += val * func(alpha, beta, cell.distance(x, y));
-                    sum
+                    sum + val * kernel(metric.distance(cx, cy, x, y))
                 });
+            (full, cell.get_value())
+        })
+        .collect::<Vec<(f64, f64)>>();
+    let total = obs_points.iter().fold(0.0, |sum, pt| sum + pt.get_value().abs());
+    let report = truncation_report(cutoff_dist, kernel(cutoff_dist) * total, &samples);
+    Ok((plots, report))
+}
+
+/// Compute the potentials by summing the contributions of every point,
+/// on the shared thread pool unless `n_thread` is below 2.
+fn do_pot<T, M>(flat_grid: &mut Vec<T>,
+                obs_points: &[T],
+                metric: &M,
+                stewart_config: &StewartPotentialGrid)
+    where T: PtValue + Send + Sync,
+          M: DistanceMetric
+{
+    let points = PointColumns::new(obs_points);
+    let (beta, alpha) = (stewart_config.beta, stewart_config.alpha);
+    fill_cells(flat_grid, stewart_config.n_thread >= 2, |cells, out| {
+        potentials(stewart_config.smooth_type, alpha, beta, metric, &points, cells, out)
+    });
+}
+
+/// Compute the potentials by convolution of the binned observation points with
+/// the smoothing function, returning `false` (and leaving `flat_grid` untouched)
+/// if the points are too far from the grid or the transforms too large.
+fn do_pot_fft<T>(flat_grid: &mut Vec<T>,
+                 obs_points: &[T],
+                 stewart_config: &StewartPotentialGrid,
+                 step: (f64, f64))
+                 -> bool
+    where T: PtValue
+{
+    let (beta, alpha) = (stewart_config.beta, stewart_config.alpha);
+    let func = stewart_config.smooth_func;
+    let bbox = stewart_config.bbox;
+    let triplets = obs_points.iter().map(|pt| pt.get_triplet()).collect::<Vec<_>>();
+    let values = grid_convolution(&triplets,
+                                  (bbox.min_x, bbox.min_y),
+                                  step,
+                                  (stewart_config.reso_x, stewart_config.reso_y),
+                                  FFT_MAX_EXTENT,
+                                  FFT_MAX_NODES,
+                                  |dist| func(alpha, beta, dist));
+    match values {
+        Some(values) => {
+            for (cell, value) in flat_grid.iter_mut().zip(values) {
+                cell.set_value(value);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Approximate the potentials with the Barnes–Hut algorithm (see `StewartEngine::BarnesHut`).
+fn do_pot_tree<T, M>(flat_grid: &mut Vec<T>,
+                     obs_points: &[T],
+                     metric: &M,
+                     stewart_config: &StewartPotentialGrid,
+                     theta: f64)
+    where T: PtValue + Send + Sync,
+          M: DistanceMetric
+{
+    let (beta, alpha) = (stewart_config.beta, stewart_config.alpha);
+    let func = stewart_config.smooth_func;
+    let tree = MassTree::new(obs_points, metric);
+    let potential = |cell: &T| {
+        tree.sum(cell.get_coordinates(),
+                 metric,
+                 theta,
+                 |dist| func(alpha, beta, dist))
+    };
+    if stewart_config.n_thread < 2 {
+        for cell in flat_grid.iter_mut() {
+            let value = potential(cell);
             cell.set_value(value);
-        });
+        }
+    } else {
+        flat_grid
+            .par_iter_mut()
+            .for_each(|cell| {
+                          let value = potential(cell);
+                          cell.set_value(value);
+                      });
+    }
 }
 
 //
@@ -0,0 +1,584 @@
+//! Map projections of geographic (longitude, latitude) coordinates, and the
+//! coordinate reference systems (CRS) built on them, identified by EPSG code.
+//!
+//! The formulas are those of the EPSG Guidance Note 7-2 (the transverse
+//! Mercator one being the Krüger series to the third order, accurate to the
+//! millimetre within 3000 km of the central meridian). Datum shifts are not
+//! handled: WGS84, ETRS89 and NAD83 coordinates are taken as equal (they
+//! differ by less than a metre or two).
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+use rayon::prelude::*;
+use gdal::spatial_ref::SpatialRef;
+use errors::*;
+use utils::PtValue;
+
+
+/// Reference ellipsoid, given by its semi-major axis (in metres) and flattening.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ellipsoid {
+    pub a: f64,
+    pub f: f64,
+}
+
+pub const WGS84: Ellipsoid = Ellipsoid {
+    a: 6378137.0,
+    f: 1.0 / 298.257223563,
+};
+
+pub const GRS80: Ellipsoid = Ellipsoid {
+    a: 6378137.0,
+    f: 1.0 / 298.257222101,
+};
+
+impl Ellipsoid {
+    /// First eccentricity.
+    pub fn e(&self) -> f64 {
+        (self.f * (2.0 - self.f)).sqrt()
+    }
+}
+
+/// Projection of the coordinates of a CRS. Angles are in degrees, false
+/// eastings and northings in metres.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Longitude and latitude, not projected.
+    Geographic,
+    /// Spherical ("Pseudo") Mercator of web maps, on the semi-major axis.
+    WebMercator,
+    TransverseMercator {
+        lon0: f64,
+        lat0: f64,
+        k0: f64,
+        false_easting: f64,
+        false_northing: f64,
+    },
+    /// Lambert conformal conic with two standard parallels.
+    LambertConformalConic {
+        lon0: f64,
+        lat0: f64,
+        lat1: f64,
+        lat2: f64,
+        false_easting: f64,
+        false_northing: f64,
+    },
+    AlbersEqualArea {
+        lon0: f64,
+        lat0: f64,
+        lat1: f64,
+        lat2: f64,
+        false_easting: f64,
+        false_northing: f64,
+    },
+    LambertAzimuthalEqualArea {
+        lon0: f64,
+        lat0: f64,
+        false_easting: f64,
+        false_northing: f64,
+    },
+}
+
+/// Coordinate reference system: a projection on an ellipsoid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crs {
+    epsg: Option<u32>,
+    ellipsoid: Ellipsoid,
+    projection: Projection,
+}
+
+impl Crs {
+    /// CRS without EPSG code.
+    pub fn new(ellipsoid: Ellipsoid, projection: Projection) -> Self {
+        Crs {
+            epsg: None,
+            ellipsoid: ellipsoid,
+            projection: projection,
+        }
+    }
+
+    /// CRS of an EPSG code, among:
+    /// - 4326 (WGS84) and 4258 (ETRS89), geographic,
+    /// - 3857, Web Mercator,
+    /// - 32601 to 32660 and 32701 to 32760, WGS84 / UTM north and south zones,
+    /// - 25828 to 25838, ETRS89 / UTM zones,
+    /// - 2154, RGF93 / Lambert-93,
+    /// - 3034, ETRS89 / LCC Europe,
+    /// - 3035, ETRS89 / LAEA Europe,
+    /// - 5070, NAD83 / Conus Albers,
+    /// - 3577, GDA94 / Australian Albers.
+    pub fn from_epsg(code: u32) -> Result<Self> {
+        let utm = |zone: u32, south: bool| {
+            Projection::TransverseMercator {
+                lon0: zone as f64 * 6.0 - 183.0,
+                lat0: 0.0,
+                k0: 0.9996,
+                false_easting: 500000.0,
+                false_northing: if south { 10000000.0 } else { 0.0 },
+            }
+        };
+        let (ellipsoid, projection) = match code {
+            4326 => (WGS84, Projection::Geographic),
+            4258 => (GRS80, Projection::Geographic),
+            3857 => (WGS84, Projection::WebMercator),
+            32601..=32660 => (WGS84, utm(code - 32600, false)),
+            32701..=32760 => (WGS84, utm(code - 32700, true)),
+            25828..=25838 => (GRS80, utm(code - 25800, false)),
+            2154 => {
+                (GRS80,
+                 Projection::LambertConformalConic {
+                     lon0: 3.0,
+                     lat0: 46.5,
+                     lat1: 49.0,
+                     lat2: 44.0,
+                     false_easting: 700000.0,
+                     false_northing: 6600000.0,
+                 })
+            }
+            3034 => {
+                (GRS80,
+                 Projection::LambertConformalConic {
+                     lon0: 10.0,
+                     lat0: 52.0,
+                     lat1: 35.0,
+                     lat2: 65.0,
+                     false_easting: 4000000.0,
+                     false_northing: 2800000.0,
+                 })
+            }
+            3035 => {
+                (GRS80,
+                 Projection::LambertAzimuthalEqualArea {
+                     lon0: 10.0,
+                     lat0: 52.0,
+                     false_easting: 4321000.0,
+                     false_northing: 3210000.0,
+                 })
+            }
+            5070 => {
+                (GRS80,
+                 Projection::AlbersEqualArea {
+                     lon0: -96.0,
+                     lat0: 23.0,
+                     lat1: 29.5,
+                     lat2: 45.5,
+                     false_easting: 0.0,
+                     false_northing: 0.0,
+                 })
+            }
+            3577 => {
+                (GRS80,
+                 Projection::AlbersEqualArea {
+                     lon0: 132.0,
+                     lat0: 0.0,
+                     lat1: -18.0,
+                     lat2: -36.0,
+                     false_easting: 0.0,
+                     false_northing: 0.0,
+                 })
+            }
+            _ => return Err(format!("Unsupported EPSG code: {}", code).into()),
+        };
+        Ok(Crs {
+               epsg: Some(code),
+               ellipsoid: ellipsoid,
+               projection: projection,
+           })
+    }
+
+    pub fn epsg(&self) -> Option<u32> {
+        self.epsg
+    }
+
+    pub fn ellipsoid(&self) -> Ellipsoid {
+        self.ellipsoid
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// Whether the coordinates are longitudes and latitudes.
+    pub fn is_geographic(&self) -> bool {
+        self.projection == Projection::Geographic
+    }
+
+    /// OGC URN of the CRS (e.g. "urn:ogc:def:crs:EPSG::2154"), if it has an EPSG code.
+    pub fn urn(&self) -> Option<String> {
+        self.epsg.map(|code| format!("urn:ogc:def:crs:EPSG::{}", code))
+    }
+
+    /// WKT definition of the CRS (as found in GeoTIFF files), from its EPSG code.
+    pub fn to_wkt(&self) -> Result<String> {
+        let code = self.epsg
+            .ok_or_else(|| format!("The CRS {} has no EPSG code", self))?;
+        SpatialRef::from_epsg(code)
+            .and_then(|srs| srs.to_wkt())
+            .map_err(|e| format!("Unable to get the definition of EPSG:{}: {:?}", code, e).into())
+    }
+
+    /// Projected coordinates of (lon, lat).
+    pub fn forward(&self, lon: f64, lat: f64) -> (f64, f64) {
+        let ell = &self.ellipsoid;
+        match self.projection {
+            Projection::Geographic => (lon, lat),
+            Projection::WebMercator => {
+                (ell.a * lon.to_radians(), ell.a * (FRAC_PI_4 + lat.to_radians() / 2.0).tan().ln())
+            }
+            Projection::TransverseMercator { lon0, lat0, k0, false_easting, false_northing } => {
+                let tm = Krueger::new(ell);
+                let (xi, eta) = tm.forward((lon - lon0).to_radians(), lat.to_radians());
+                let xi0 = tm.forward(0.0, lat0.to_radians()).0;
+                (false_easting + k0 * tm.a * eta, false_northing + k0 * tm.a * (xi - xi0))
+            }
+            Projection::LambertConformalConic { lon0, lat0, lat1, lat2, false_easting,
+                                                false_northing } => {
+                let lcc = Lcc::new(ell, lat0, lat1, lat2);
+                let (rho, theta) = (lcc.rho(lat.to_radians()), lcc.n * (lon - lon0).to_radians());
+                (false_easting + rho * theta.sin(), false_northing + lcc.rho0 - rho * theta.cos())
+            }
+            Projection::AlbersEqualArea { lon0, lat0, lat1, lat2, false_easting, false_northing } => {
+                let aea = Albers::new(ell, lat0, lat1, lat2);
+                let (rho, theta) = (aea.rho(lat.to_radians()), aea.n * (lon - lon0).to_radians());
+                (false_easting + rho * theta.sin(), false_northing + aea.rho0 - rho * theta.cos())
+            }
+            Projection::LambertAzimuthalEqualArea { lon0, lat0, false_easting, false_northing } => {
+                let laea = Laea::new(ell, lat0);
+                let beta = laea.beta(lat.to_radians());
+                let dlon = (lon - lon0).to_radians();
+                let b = laea.rq *
+                        (2.0 /
+                         (1.0 + laea.beta0.sin() * beta.sin() +
+                          laea.beta0.cos() * beta.cos() * dlon.cos()))
+                                .sqrt();
+                (false_easting + b * laea.d * beta.cos() * dlon.sin(),
+                 false_northing +
+                 b / laea.d *
+                 (laea.beta0.cos() * beta.sin() - laea.beta0.sin() * beta.cos() * dlon.cos()))
+            }
+        }
+    }
+
+    /// (lon, lat) of projected coordinates.
+    pub fn inverse(&self, x: f64, y: f64) -> (f64, f64) {
+        let ell = &self.ellipsoid;
+        match self.projection {
+            Projection::Geographic => (x, y),
+            Projection::WebMercator => {
+                ((x / ell.a).to_degrees(), (2.0 * (y / ell.a).exp().atan() - FRAC_PI_2).to_degrees())
+            }
+            Projection::TransverseMercator { lon0, lat0, k0, false_easting, false_northing } => {
+                let tm = Krueger::new(ell);
+                let xi0 = tm.forward(0.0, lat0.to_radians()).0;
+                let (dlon, lat) = tm.inverse((y - false_northing) / (k0 * tm.a) + xi0,
+                                             (x - false_easting) / (k0 * tm.a));
+                (lon0 + dlon.to_degrees(), lat.to_degrees())
+            }
+            Projection::LambertConformalConic { lon0, lat0, lat1, lat2, false_easting,
+                                                false_northing } => {
+                let lcc = Lcc::new(ell, lat0, lat1, lat2);
+                let (rho, theta) = polar(lcc.n, lcc.rho0, x - false_easting, y - false_northing);
+                let t = (rho / (ell.a * lcc.f)).powf(1.0 / lcc.n);
+                (lon0 + (theta / lcc.n).to_degrees(), lat_from_t(ell.e(), t).to_degrees())
+            }
+            Projection::AlbersEqualArea { lon0, lat0, lat1, lat2, false_easting, false_northing } => {
+                let aea = Albers::new(ell, lat0, lat1, lat2);
+                let (rho, theta) = polar(aea.n, aea.rho0, x - false_easting, y - false_northing);
+                let q = (aea.c - (rho * aea.n / ell.a).powi(2)) / aea.n;
+                (lon0 + (theta / aea.n).to_degrees(), aea.lat_from_q(q).to_degrees())
+            }
+            Projection::LambertAzimuthalEqualArea { lon0, lat0, false_easting, false_northing } => {
+                let laea = Laea::new(ell, lat0);
+                let (dx, dy) = (x - false_easting, y - false_northing);
+                let rho = ((dx / laea.d).powi(2) + (laea.d * dy).powi(2)).sqrt();
+                if rho == 0.0 {
+                    return (lon0, lat0);
+                }
+                let c = 2.0 * (rho / (2.0 * laea.rq)).min(1.0).asin();
+                let beta = (c.cos() * laea.beta0.sin() +
+                            laea.d * dy * c.sin() * laea.beta0.cos() / rho)
+                        .asin();
+                let dlon = (dx * c.sin())
+                    .atan2(laea.d * rho * laea.beta0.cos() * c.cos() -
+                           laea.d * laea.d * dy * laea.beta0.sin() * c.sin());
+                (lon0 + dlon.to_degrees(), laea.lat_from_beta(beta).to_degrees())
+            }
+        }
+    }
+
+    /// Coordinates in `target` of the coordinates (x, y) of this CRS.
+    pub fn transform(&self, target: &Crs, x: f64, y: f64) -> (f64, f64) {
+        if self == target {
+            return (x, y);
+        }
+        let (lon, lat) = self.inverse(x, y);
+        target.forward(lon, lat)
+    }
+}
+
+impl ::std::fmt::Display for Crs {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self.epsg {
+            Some(code) => write!(f, "EPSG:{}", code),
+            None => write!(f, "{:?}", self.projection),
+        }
+    }
+}
+
+impl ::std::str::FromStr for Crs {
+    type Err = Error;
+
+    /// Parse an EPSG code, given as "2154" or "EPSG:2154".
+    fn from_str(s: &str) -> Result<Self> {
+        let code = s.trim();
+        let code = if code.to_uppercase().starts_with("EPSG:") {
+            &code[5..]
+        } else {
+            code
+        };
+        match code.parse::<u32>() {
+            Ok(code) => Crs::from_epsg(code),
+            Err(_) => Err(format!("Invalid CRS: '{}' (expected an EPSG code)", s).into()),
+        }
+    }
+}
+
+/// Points (or grid cells) of `from`, with their coordinates in `to`, computed in parallel.
+pub fn reproject<T, U>(points: &[T], from: &Crs, to: &Crs) -> Vec<U>
+    where T: PtValue + Sync,
+          U: PtValue + Send
+{
+    points
+        .par_iter()
+        .map(|pt| {
+                 let (x, y, val) = pt.get_triplet();
+                 let (x, y) = from.transform(to, x, y);
+                 U::new(x, y, val)
+             })
+        .collect()
+}
+
+/// Polar coordinates (radius, angle) of a point about the apex of a cone of
+/// constant `n`, whose radius at the origin of the projection is `rho0`.
+fn polar(n: f64, rho0: f64, dx: f64, dy: f64) -> (f64, f64) {
+    let sign = n.signum();
+    (sign * (dx * dx + (rho0 - dy).powi(2)).sqrt(), (sign * dx).atan2(sign * (rho0 - dy)))
+}
+
+fn m(e: f64, lat: f64) -> f64 {
+    lat.cos() / (1.0 - (e * lat.sin()).powi(2)).sqrt()
+}
+
+fn t(e: f64, lat: f64) -> f64 {
+    let es = e * lat.sin();
+    (FRAC_PI_4 - lat / 2.0).tan() / ((1.0 - es) / (1.0 + es)).powf(e / 2.0)
+}
+
+fn q(e: f64, lat: f64) -> f64 {
+    let (sin, es) = (lat.sin(), e * lat.sin());
+    (1.0 - e * e) * (sin / (1.0 - es * es) - ((1.0 - es) / (1.0 + es)).ln() / (2.0 * e))
+}
+
+/// Latitude (in radians) of the isometric latitude function `t`.
+fn lat_from_t(e: f64, t: f64) -> f64 {
+    let mut lat = FRAC_PI_2 - 2.0 * t.atan();
+    for _ in 0..15 {
+        let es = e * lat.sin();
+        let next = FRAC_PI_2 - 2.0 * (t * ((1.0 - es) / (1.0 + es)).powf(e / 2.0)).atan();
+        if (next - lat).abs() < 1e-14 {
+            return next;
+        }
+        lat = next;
+    }
+    lat
+}
+
+/// Krüger series of the transverse Mercator projection, in units of the
+/// rectifying radius `a`.
+struct Krueger {
+    a: f64,
+    e: f64,
+    n: f64,
+    alpha: [f64; 3],
+    beta: [f64; 3],
+    delta: [f64; 3],
+}
+
+impl Krueger {
+    fn new(ell: &Ellipsoid) -> Self {
+        let n = ell.f / (2.0 - ell.f);
+        let (n2, n3) = (n * n, n * n * n);
+        Krueger {
+            a: ell.a / (1.0 + n) * (1.0 + n2 / 4.0 + n2 * n2 / 64.0),
+            e: ell.e(),
+            n: n,
+            alpha: [n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0,
+                    13.0 * n2 / 48.0 - 3.0 * n3 / 5.0,
+                    61.0 * n3 / 240.0],
+            beta: [n / 2.0 - 2.0 * n2 / 3.0 + 37.0 * n3 / 96.0,
+                   n2 / 48.0 + n3 / 15.0,
+                   17.0 * n3 / 480.0],
+            delta: [2.0 * n - 2.0 * n2 / 3.0 - 2.0 * n3,
+                    7.0 * n2 / 3.0 - 8.0 * n3 / 5.0,
+                    56.0 * n3 / 15.0],
+        }
+    }
+
+    /// (xi, eta) of a point at `dlon` from the central meridian (in radians).
+    fn forward(&self, dlon: f64, lat: f64) -> (f64, f64) {
+        let c = 2.0 * self.n.sqrt() / (1.0 + self.n);
+        debug_assert!((c - self.e).abs() < 1e-12);
+        let t = (lat.sin().atanh() - c * (c * lat.sin()).atanh()).sinh();
+        let xi_p = t.atan2(dlon.cos());
+        let eta_p = (dlon.sin() / (1.0 + t * t).sqrt()).atanh();
+        let (mut xi, mut eta) = (xi_p, eta_p);
+        for (j, alpha) in self.alpha.iter().enumerate() {
+            let k = 2.0 * (j + 1) as f64;
+            xi += alpha * (k * xi_p).sin() * (k * eta_p).cosh();
+            eta += alpha * (k * xi_p).cos() * (k * eta_p).sinh();
+        }
+        (xi, eta)
+    }
+
+    /// (dlon, lat), in radians, of (xi, eta).
+    fn inverse(&self, xi: f64, eta: f64) -> (f64, f64) {
+        let (mut xi_p, mut eta_p) = (xi, eta);
+        for (j, beta) in self.beta.iter().enumerate() {
+            let k = 2.0 * (j + 1) as f64;
+            xi_p -= beta * (k * xi).sin() * (k * eta).cosh();
+            eta_p -= beta * (k * xi).cos() * (k * eta).sinh();
+        }
+        let chi = (xi_p.sin() / eta_p.cosh()).asin();
+        let mut lat = chi;
+        for (j, delta) in self.delta.iter().enumerate() {
+            lat += delta * (2.0 * (j + 1) as f64 * chi).sin();
+        }
+        (eta_p.sinh().atan2(xi_p.cos()), lat)
+    }
+}
+
+/// Constants of a Lambert conformal conic projection.
+struct Lcc {
+    a: f64,
+    e: f64,
+    n: f64,
+    f: f64,
+    rho0: f64,
+}
+
+impl Lcc {
+    fn new(ell: &Ellipsoid, lat0: f64, lat1: f64, lat2: f64) -> Self {
+        let e = ell.e();
+        let (lat0, lat1, lat2) = (lat0.to_radians(), lat1.to_radians(), lat2.to_radians());
+        let (m1, m2, t1, t2) = (m(e, lat1), m(e, lat2), t(e, lat1), t(e, lat2));
+        let n = if (lat1 - lat2).abs() < 1e-12 {
+            lat1.sin()
+        } else {
+            (m1.ln() - m2.ln()) / (t1.ln() - t2.ln())
+        };
+        let f = m1 / (n * t1.powf(n));
+        Lcc {
+            a: ell.a,
+            e: e,
+            n: n,
+            f: f,
+            rho0: ell.a * f * t(e, lat0).powf(n),
+        }
+    }
+
+    fn rho(&self, lat: f64) -> f64 {
+        self.a * self.f * t(self.e, lat).powf(self.n)
+    }
+}
+
+/// Constants of an Albers equal-area conic projection.
+struct Albers {
+    a: f64,
+    e: f64,
+    n: f64,
+    c: f64,
+    rho0: f64,
+}
+
+impl Albers {
+    fn new(ell: &Ellipsoid, lat0: f64, lat1: f64, lat2: f64) -> Self {
+        let e = ell.e();
+        let (lat0, lat1, lat2) = (lat0.to_radians(), lat1.to_radians(), lat2.to_radians());
+        let (m1, m2, q1, q2) = (m(e, lat1), m(e, lat2), q(e, lat1), q(e, lat2));
+        let n = if (lat1 - lat2).abs() < 1e-12 {
+            lat1.sin()
+        } else {
+            (m1 * m1 - m2 * m2) / (q2 - q1)
+        };
+        let c = m1 * m1 + n * q1;
+        Albers {
+            a: ell.a,
+            e: e,
+            n: n,
+            c: c,
+            rho0: ell.a * (c - n * q(e, lat0)).sqrt() / n,
+        }
+    }
+
+    fn rho(&self, lat: f64) -> f64 {
+        self.a * (self.c - self.n * q(self.e, lat)).sqrt() / self.n
+    }
+
+    /// Latitude (in radians) of the authalic function `q`, by Newton iterations.
+    fn lat_from_q(&self, q_value: f64) -> f64 {
+        let e = self.e;
+        let mut lat = (q_value / 2.0).max(-1.0).min(1.0).asin();
+        for _ in 0..15 {
+            let (sin, es) = (lat.sin(), e * lat.sin());
+            let one_es2 = 1.0 - es * es;
+            let next = lat +
+                       one_es2 * one_es2 / (2.0 * lat.cos()) *
+                       (q_value / (1.0 - e * e) - sin / one_es2 +
+                        ((1.0 - es) / (1.0 + es)).ln() / (2.0 * e));
+            if !next.is_finite() {
+                return lat;
+            }
+            if (next - lat).abs() < 1e-14 {
+                return next;
+            }
+            lat = next;
+        }
+        lat
+    }
+}
+
+/// Constants of an oblique Lambert azimuthal equal-area projection.
+struct Laea {
+    e: f64,
+    qp: f64,
+    rq: f64,
+    beta0: f64,
+    d: f64,
+}
+
+impl Laea {
+    fn new(ell: &Ellipsoid, lat0: f64) -> Self {
+        let e = ell.e();
+        let lat0 = lat0.to_radians();
+        let qp = q(e, FRAC_PI_2);
+        let rq = ell.a * (qp / 2.0).sqrt();
+        let beta0 = (q(e, lat0) / qp).asin();
+        Laea {
+            e: e,
+            qp: qp,
+            rq: rq,
+            beta0: beta0,
+            d: ell.a * m(e, lat0) / (rq * beta0.cos()),
+        }
+    }
+
+    /// Authalic latitude (in radians) of `lat`.
+    fn beta(&self, lat: f64) -> f64 {
+        (q(self.e, lat) / self.qp).max(-1.0).min(1.0).asin()
+    }
+
+    fn lat_from_beta(&self, beta: f64) -> f64 {
+        let (e2, e4, e6) = (self.e.powi(2), self.e.powi(4), self.e.powi(6));
+        beta + (e2 / 3.0 + 31.0 * e4 / 180.0 + 517.0 * e6 / 5040.0) * (2.0 * beta).sin() +
+        (23.0 * e4 / 360.0 + 251.0 * e6 / 3780.0) * (4.0 * beta).sin() +
+        761.0 * e6 / 45360.0 * (6.0 * beta).sin()
+    }
+}
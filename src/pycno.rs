@@ -0,0 +1,437 @@
+//! Pycnophylactic interpolation (Tobler, 1979): counts given for zones (e.g.
+//! the population of administrative units) spread on a grid as a smooth
+//! surface whose sum over the cells of each zone remains its count.
+//!
+//! Each cell belongs to the zone containing its position (its centre or its
+//! lower-left corner, depending on the `GridSpec`); cells outside of the
+//! zones are left as NaN. An ancillary weight raster (e.g. built-up areas)
+//! refines the result (dasymetric mapping): the smoothed surface is then a
+//! density, the value of each cell being its density times its weight.
+use std::cmp::Ordering;
+use std::f64;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use gdal::raster::Dataset;
+use geojson::{GeoJson, Value};
+use rayon::prelude::*;
+use serde_json;
+use bbox::Bbox;
+use errors::*;
+use grid::GridSpec;
+use parallel::grid_cells;
+use utils::PtValue;
+
+
+/// Largest offset, as a fraction of a cell, between the edges of a weight
+/// raster and the ones of the grid.
+const ALIGNMENT_TOLERANCE: f64 = 1e-6;
+
+/// A zone and the count (e.g. of inhabitants) to spread on its cells.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    /// Polygons of the zone, each one an exterior ring followed by its holes.
+    pub polygons: Vec<Vec<Vec<(f64, f64)>>>,
+    pub count: f64,
+}
+
+impl Zone {
+    pub fn new(polygons: Vec<Vec<Vec<(f64, f64)>>>, count: f64) -> Self {
+        Zone {
+            polygons: polygons,
+            count: count,
+        }
+    }
+
+    pub fn bbox(&self) -> Bbox {
+        let mut bbox = Bbox::new(f64::INFINITY,
+                                 f64::NEG_INFINITY,
+                                 f64::INFINITY,
+                                 f64::NEG_INFINITY);
+        for ring in self.polygons.iter().flat_map(|rings| rings.iter()) {
+            for &(x, y) in ring {
+                bbox = bbox.union(&Bbox::new(x, x, y, y));
+            }
+        }
+        bbox
+    }
+
+    /// Whether (x, y) is inside the zone (out of its holes).
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        let mut inside = false;
+        for ring in self.polygons.iter().flat_map(|rings| rings.iter()) {
+            for (ix, &(x1, y1)) in ring.iter().enumerate() {
+                let (x2, y2) = ring[(ix + 1) % ring.len()];
+                // Half-open rule, so a vertex lying on the line is only counted once
+                if (y1 > y) != (y2 > y) && x < x1 + (y - y1) * (x2 - x1) / (y2 - y1) {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+
+    fn vertices_mean(&self) -> Option<(f64, f64)> {
+        let (mut sx, mut sy, mut n) = (0.0, 0.0, 0);
+        for ring in self.polygons.iter().flat_map(|rings| rings.iter()) {
+            for &(x, y) in ring {
+                sx += x;
+                sy += y;
+                n += 1;
+            }
+        }
+        if n == 0 {
+            None
+        } else {
+            Some((sx / n as f64, sy / n as f64))
+        }
+    }
+}
+
+/// Read the zones from the (Multi)Polygon features of a GeoJSON
+/// FeatureCollection, their counts from the `field_name` property.
+pub fn parse_geojson_zones(path: &str, field_name: &str) -> Result<Vec<Zone>> {
+    let mut file = File::open(path)?;
+    let mut raw_json = String::new();
+    file.read_to_string(&mut raw_json)?;
+    let features = match raw_json.parse::<GeoJson>()? {
+        GeoJson::FeatureCollection(collection) => collection.features,
+        _ => return Err("Error: expected a FeatureCollection".into()),
+    };
+    let mut zones = Vec::with_capacity(features.len());
+    for (ix, ft) in features.iter().enumerate() {
+        let count = match ft.properties.as_ref().and_then(|prop| prop.get(field_name)) {
+            Some(&serde_json::Value::Number(ref val)) => val.as_f64(),
+            Some(&serde_json::Value::String(ref val)) => val.trim().parse::<f64>().ok(),
+            Some(_) => None,
+            None => {
+                return Err(format!("Feature {}: missing field \"{}\"", ix, field_name).into())
+            }
+        };
+        let count = count
+            .ok_or_else(|| format!("Feature {}: invalid value for field \"{}\"", ix, field_name))?;
+        let polygons = match ft.geometry.as_ref().map(|g| &g.value) {
+            Some(&Value::Polygon(ref rings)) => vec![rings.clone()],
+            Some(&Value::MultiPolygon(ref polygons)) => polygons.clone(),
+            Some(_) => return Err(format!("Feature {}: zones are polygons", ix).into()),
+            None => return Err(format!("Feature {}: no geometry", ix).into()),
+        };
+        let mut zone = Vec::with_capacity(polygons.len());
+        for rings in polygons {
+            let mut polygon = Vec::with_capacity(rings.len());
+            for ring in rings {
+                if ring.iter().any(|pos| pos.len() < 2) {
+                    return Err(format!("Feature {}: invalid position", ix).into());
+                }
+                polygon.push(ring.iter().map(|pos| (pos[0], pos[1])).collect());
+            }
+            zone.push(polygon);
+        }
+        zones.push(Zone::new(zone, count));
+    }
+    Ok(zones)
+}
+
+/// Values of the first band of the raster at `path`, which must have the
+/// cells of `grid` (e.g. the grid of `GridSpec::from_template(path)`), in
+/// the order of the cells returned by the grid functions. The rows of the
+/// raster are read from the top or from the bottom as given by the sign of
+/// its pixel height.
+pub fn read_weight_raster(path: &str, grid: &GridSpec) -> Result<Vec<f64>> {
+    let dataset = Dataset::open(Path::new(path))
+        .map_err(|e| Error::from(format!("Unable to open {}: {:?}", path, e)))?;
+    let (width, height) = dataset.size();
+    if (width, height) != (grid.reso_x, grid.reso_y) {
+        return Err(format!("The raster {} has {}x{} pixels, the grid {}x{} cells",
+                           path,
+                           width,
+                           height,
+                           grid.reso_x,
+                           grid.reso_y)
+                           .into());
+    }
+    let transform = dataset
+        .geo_transform()
+        .map_err(|e| Error::from(format!("No geotransform in {}: {:?}", path, e)))?;
+    if transform[2] != 0.0 || transform[4] != 0.0 || !(transform[1] > 0.0) ||
+       transform[5] == 0.0 || transform[5].is_nan() {
+        return Err(format!("Unsupported geotransform in {}: {:?} (expected no rotation and a \
+                            positive pixel width)",
+                           path,
+                           transform)
+                           .into());
+    }
+    let top_down = transform[5] < 0.0;
+    let (min_y, max_y) = if top_down {
+        (transform[3] + transform[5] * height as f64, transform[3])
+    } else {
+        (transform[3], transform[3] + transform[5] * height as f64)
+    };
+    let extent = Bbox::new(transform[0], transform[0] + transform[1] * width as f64, min_y, max_y);
+    let (cell_x, cell_y) = grid.cell_size();
+    if (extent.min_x - grid.extent.min_x).abs() > ALIGNMENT_TOLERANCE * cell_x ||
+       (extent.max_x - grid.extent.max_x).abs() > ALIGNMENT_TOLERANCE * cell_x ||
+       (extent.min_y - grid.extent.min_y).abs() > ALIGNMENT_TOLERANCE * cell_y ||
+       (extent.max_y - grid.extent.max_y).abs() > ALIGNMENT_TOLERANCE * cell_y {
+        return Err(format!("The raster {} covers {}, the grid {}", path, extent, grid.extent)
+                       .into());
+    }
+    let buffer = dataset
+        .read_full_raster_as::<f64>(1)
+        .map_err(|e| Error::from(format!("Unable to read {}: {:?}", path, e)))?;
+    let mut weights = Vec::with_capacity(width * height);
+    for i in 0..width {
+        for j in 0..height {
+            let row = if top_down { height - 1 - j } else { j };
+            weights.push(buffer.data[row * width + i]);
+        }
+    }
+    Ok(weights)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PycnoConfig {
+    /// Maximum number of smoothing iterations.
+    pub max_iterations: usize,
+    /// Stop once no cell changes by more than this fraction of the largest value.
+    pub tolerance: f64,
+    /// Share (between 0 and 1) of the mean of its neighbours in the new
+    /// density of a cell at each iteration.
+    pub relaxation: f64,
+}
+
+impl Default for PycnoConfig {
+    fn default() -> Self {
+        PycnoConfig {
+            max_iterations: 1000,
+            tolerance: 1e-4,
+            relaxation: 0.5,
+        }
+    }
+}
+
+/// Outcome of a pycnophylactic interpolation.
+#[derive(Debug, Clone, Default)]
+pub struct PycnoReport {
+    pub nb_iterations: usize,
+    /// Whether the tolerance was reached before the maximum number of iterations.
+    pub converged: bool,
+    /// Zones left without any cell (e.g. lying outside of the grid extent),
+    /// whose counts are missing from the grid.
+    pub unassigned_zones: Vec<usize>,
+    /// Zones too small to hold the position of a cell, whose counts were added
+    /// to the nearest cell of another zone.
+    pub shared_zones: Vec<usize>,
+}
+
+/// Spread the counts of `zones` on the cells of `grid`, optionally weighted
+/// by the (non-negative) `weights` of the cells, given in the order of the
+/// cells returned by the grid functions (NaN weights count as 0).
+///
+/// Starting from the count of each zone spread in proportion to the weights
+/// of its cells, the density of each cell is repeatedly moved towards the
+/// mean of the neighbouring ones, then rescaled for the sum over each zone
+/// to remain its count. Zones within the grid extent but too small to hold
+/// the position of a cell are given the cell nearest to them or, if it
+/// belongs to another zone, their counts are added to its value once smoothed
+/// (the cell is shared, the other zone keeping its count); zones outside the
+/// extent (e.g. cropped by a window) are reported as unassigned; zones whose
+/// cells all have a null weight are spread evenly.
+pub fn pycnophylactic<T>(zones: &[Zone],
+                         grid: &GridSpec,
+                         weights: Option<&[f64]>,
+                         config: &PycnoConfig)
+                         -> Result<(Vec<T>, PycnoReport)>
+    where T: PtValue + Send + Sync
+{
+    grid.check()?;
+    if !(config.relaxation > 0.0 && config.relaxation <= 1.0) {
+        return Err(format!("Invalid relaxation: {} (expected between 0 and 1)",
+                           config.relaxation)
+                           .into());
+    }
+    if !(config.tolerance >= 0.0) {
+        return Err(format!("Invalid tolerance: {}", config.tolerance).into());
+    }
+    for (ix, zone) in zones.iter().enumerate() {
+        if !(zone.count >= 0.0) || zone.count.is_infinite() {
+            return Err(format!("Zone {}: invalid count {}", ix, zone.count).into());
+        }
+    }
+    let (reso_x, reso_y) = (grid.reso_x, grid.reso_y);
+    let mut cells = grid_cells::<T>(&grid.sampling_bbox(), reso_x, reso_y);
+    let n = cells.len();
+    let mut weight = match weights {
+        Some(weights) => {
+            if weights.len() != n {
+                return Err(ErrorKind::DimensionMismatch(n, weights.len()).into());
+            }
+            if let Some(w) = weights.iter().find(|w| **w < 0.0 || w.is_infinite()) {
+                return Err(format!("Invalid weight: {}", w).into());
+            }
+            weights
+                .iter()
+                .map(|&w| if w.is_nan() { 0.0 } else { w })
+                .collect::<Vec<f64>>()
+        }
+        None => vec![1.0; n],
+    };
+
+    let (zone_of, shared_cell) = assign_cells(zones, grid, &cells);
+    let mut report = PycnoReport::default();
+    let (mut zone_weight, mut zone_cells) = (vec![0.0; zones.len()], vec![0; zones.len()]);
+    for k in 0..n {
+        if let Some(z) = zone_of[k] {
+            zone_weight[z] += weight[k];
+            zone_cells[z] += 1;
+        }
+    }
+    report.unassigned_zones = (0..zones.len())
+        .filter(|&z| zone_cells[z] == 0 && shared_cell[z].is_none())
+        .collect();
+    report.shared_zones = (0..zones.len()).filter(|&z| shared_cell[z].is_some()).collect();
+    if zone_weight.iter().any(|&total| total == 0.0) {
+        for k in 0..n {
+            if let Some(z) = zone_of[k] {
+                if zone_weight[z] == 0.0 {
+                    weight[k] = 1.0;
+                }
+            }
+        }
+        zone_weight = vec![0.0; zones.len()];
+        for k in 0..n {
+            if let Some(z) = zone_of[k] {
+                zone_weight[z] += weight[k];
+            }
+        }
+    }
+
+    let relaxation = config.relaxation;
+    let mut density = (0..n)
+        .map(|k| zone_of[k].map_or(0.0, |z| zones[z].count / zone_weight[z]))
+        .collect::<Vec<f64>>();
+    for iteration in 0..config.max_iterations {
+        let smoothed = (0..n)
+            .into_par_iter()
+            .map(|k| {
+                if zone_of[k].is_none() {
+                    return 0.0;
+                }
+                let (i, j) = (k / reso_y, k % reso_y);
+                let neighbours = [(i > 0, k.wrapping_sub(reso_y)),
+                                  (i + 1 < reso_x, k + reso_y),
+                                  (j > 0, k.wrapping_sub(1)),
+                                  (j + 1 < reso_y, k + 1)];
+                let (mut sum, mut nb) = (0.0, 0);
+                for &(exists, nb_k) in &neighbours {
+                    if exists && zone_of[nb_k].is_some() {
+                        sum += density[nb_k];
+                        nb += 1;
+                    }
+                }
+                if nb == 0 {
+                    density[k]
+                } else {
+                    (1.0 - relaxation) * density[k] + relaxation * sum / nb as f64
+                }
+            })
+            .collect::<Vec<f64>>();
+        let mut zone_sum = vec![0.0; zones.len()];
+        for k in 0..n {
+            if let Some(z) = zone_of[k] {
+                zone_sum[z] += smoothed[k] * weight[k];
+            }
+        }
+        let (mut max_change, mut max_value) = (0.0f64, 0.0f64);
+        for k in 0..n {
+            if let Some(z) = zone_of[k] {
+                let new_density = if zone_sum[z] > 0.0 {
+                    smoothed[k] * zones[z].count / zone_sum[z]
+                } else {
+                    zones[z].count / zone_weight[z]
+                };
+                max_change = max_change.max((new_density - density[k]).abs() * weight[k]);
+                max_value = max_value.max(new_density * weight[k]);
+                density[k] = new_density;
+            }
+        }
+        report.nb_iterations = iteration + 1;
+        if max_change <= config.tolerance * max_value {
+            report.converged = true;
+            break;
+        }
+    }
+    for (k, cell) in cells.iter_mut().enumerate() {
+        cell.set_value(zone_of[k].map_or(f64::NAN, |_| density[k] * weight[k]));
+    }
+    for (z, zone) in zones.iter().enumerate() {
+        if let Some(k) = shared_cell[z] {
+            let value = cells[k].get_value();
+            cells[k].set_value(value + zone.count);
+        }
+    }
+    Ok((cells, report))
+}
+
+/// Zone of each cell: the first one containing its position or, for the zones
+/// containing none but overlapping the grid extent, the cell nearest to the
+/// mean of their vertices if no zone has it yet. Also returns, for each zone,
+/// the cell it shares with another zone if that nearest cell was already given
+/// to one.
+fn assign_cells<T>(zones: &[Zone],
+                   grid: &GridSpec,
+                   cells: &[T])
+                   -> (Vec<Option<usize>>, Vec<Option<usize>>)
+    where T: PtValue
+{
+    let (reso_x, reso_y) = (grid.reso_x, grid.reso_y);
+    let sampling = grid.sampling_bbox();
+    let (step_x, step_y) = grid.cell_size();
+    let range = |min: f64, max: f64, origin: f64, step: f64, reso: usize| {
+        let first = ((min - origin) / step).floor().max(0.0);
+        let last = ((max - origin) / step).ceil().min(reso as f64 - 1.0);
+        (first as usize, last as usize, first <= last)
+    };
+    let mut zone_of = vec![None; cells.len()];
+    let mut has_cell = vec![false; zones.len()];
+    let mut shared_cell = vec![None; zones.len()];
+    for (z, zone) in zones.iter().enumerate() {
+        let bbox = zone.bbox();
+        let (i_min, i_max, cols) = range(bbox.min_x, bbox.max_x, sampling.min_x, step_x, reso_x);
+        let (j_min, j_max, rows) = range(bbox.min_y, bbox.max_y, sampling.min_y, step_y, reso_y);
+        if !(cols && rows) {
+            continue;
+        }
+        for i in i_min..i_max + 1 {
+            for j in j_min..j_max + 1 {
+                let k = i * reso_y + j;
+                let (x, y) = cells[k].get_coordinates();
+                if zone_of[k].is_none() && zone.contains(x, y) {
+                    zone_of[k] = Some(z);
+                    has_cell[z] = true;
+                }
+            }
+        }
+    }
+    for (z, zone) in zones.iter().enumerate() {
+        if has_cell[z] || !zone.bbox().intersects(&grid.extent) {
+            continue;
+        }
+        let (x, y) = match zone.vertices_mean() {
+            Some(pos) => pos,
+            None => continue,
+        };
+        let dist = |k: usize| {
+            let (cx, cy) = cells[k].get_coordinates();
+            (cx - x).powi(2) + (cy - y).powi(2)
+        };
+        let nearest = (0..cells.len())
+            .min_by(|&a, &b| dist(a).partial_cmp(&dist(b)).unwrap_or(Ordering::Equal));
+        match nearest {
+            Some(k) if zone_of[k].is_none() => zone_of[k] = Some(z),
+            Some(k) => shared_cell[z] = Some(k),
+            None => (),
+        }
+    }
+    (zone_of, shared_cell)
+}
@@ -0,0 +1,140 @@
+//! Quadtree of the observation points aggregating the masses (values) of
+//! the points of each node, to approximate sums of distance kernels
+//! (Barnes–Hut algorithm).
+use std::f64::{INFINITY, NEG_INFINITY};
+use metric::DistanceMetric;
+use utils::PtValue;
+
+
+/// Maximum number of points of a leaf.
+const LEAF_SIZE: usize = 16;
+/// Maximum depth of the tree (reached only with many duplicated points).
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug)]
+struct Node {
+    /// Center of mass, weighted by the absolute values of the points.
+    center: (f64, f64),
+    /// Sum of the values of the points.
+    mass: f64,
+    /// Distance from the center of mass to the farthest point of the node.
+    radius: f64,
+    start: usize,
+    end: usize,
+    children: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct MassTree {
+    nodes: Vec<Node>,
+    /// (x, y, value) of the points, ordered so that each node holds a range of them.
+    points: Vec<(f64, f64, f64)>,
+}
+
+impl MassTree {
+    /// Tree of `obs_points`, the radius of the nodes being measured with `metric`.
+    pub fn new<T, M>(obs_points: &[T], metric: &M) -> Self
+        where T: PtValue,
+              M: DistanceMetric
+    {
+        let mut points = obs_points.iter().map(|pt| pt.get_triplet()).collect::<Vec<_>>();
+        let mut nodes = Vec::new();
+        if !points.is_empty() {
+            build(&mut points, metric, 0, 0, &mut nodes);
+        }
+        MassTree {
+            nodes: nodes,
+            points: points,
+        }
+    }
+
+    /// Sum of `value * kernel(distance)` over the points, where the nodes
+    /// whose radius is below `theta` times their distance to `cell` are
+    /// replaced by their mass at their center of mass.
+    /// With `theta == 0` the sum is exact (up to the summation order).
+    pub fn sum<M, K>(&self, cell: (f64, f64), metric: &M, theta: f64, kernel: K) -> f64
+        where M: DistanceMetric,
+              K: Fn(f64) -> f64
+    {
+        let (cx, cy) = cell;
+        let mut sum = 0.0;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(ix) = stack.pop() {
+            let node = &self.nodes[ix];
+            let dist = metric.distance(cx, cy, node.center.0, node.center.1);
+            if node.radius < theta * dist {
+                sum += node.mass * kernel(dist);
+            } else if node.children.is_empty() {
+                for &(x, y, val) in &self.points[node.start..node.end] {
+                    sum += val * kernel(metric.distance(cx, cy, x, y));
+                }
+            } else {
+                stack.extend(node.children.iter().cloned());
+            }
+        }
+        sum
+    }
+}
+
+fn build<M>(points: &mut [(f64, f64, f64)],
+            metric: &M,
+            offset: usize,
+            depth: usize,
+            nodes: &mut Vec<Node>)
+            -> usize
+    where M: DistanceMetric
+{
+    let (mut sum_x, mut sum_y, mut sum_w, mut mass) = (0.0, 0.0, 0.0, 0.0);
+    let (mut min_x, mut max_x, mut min_y, mut max_y) =
+        (INFINITY, NEG_INFINITY, INFINITY, NEG_INFINITY);
+    for &(x, y, val) in points.iter() {
+        sum_x += x * val.abs();
+        sum_y += y * val.abs();
+        sum_w += val.abs();
+        mass += val;
+        min_x = min_x.min(x);
+        max_x = max_x.max(x);
+        min_y = min_y.min(y);
+        max_y = max_y.max(y);
+    }
+    let center = if sum_w > 0.0 {
+        (sum_x / sum_w, sum_y / sum_w)
+    } else {
+        ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0)
+    };
+    let radius = points
+        .iter()
+        .fold(0.0f64, |r, &(x, y, _)| r.max(metric.distance(center.0, center.1, x, y)));
+    let ix = nodes.len();
+    nodes.push(Node {
+                   center: center,
+                   mass: mass,
+                   radius: radius,
+                   start: offset,
+                   end: offset + points.len(),
+                   children: Vec::new(),
+               });
+    if points.len() > LEAF_SIZE && depth < MAX_DEPTH && (max_x > min_x || max_y > min_y) {
+        let (mid_x, mid_y) = ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+        let quadrant = |&(x, y, _): &(f64, f64, f64)| (x > mid_x) as usize + 2 * (y > mid_y) as usize;
+        points.sort_by_key(|pt| quadrant(pt));
+        let mut children = Vec::with_capacity(4);
+        let mut start = 0;
+        for q in 0..4 {
+            let end = start + points[start..].iter().take_while(|pt| quadrant(pt) == q).count();
+            if end > start {
+                children.push(build(&mut points[start..end],
+                                    metric,
+                                    offset + start,
+                                    depth + 1,
+                                    nodes));
+            }
+            start = end;
+        }
+        nodes[ix].children = children;
+    }
+    ix
+}
@@ -1,104 +1,840 @@
 use errors::*;
+use metric::{DistanceMetric, Euclidean, PointMetric};
 use utils::PtValue;
 use bbox::Bbox;
 use std::f64;
 use rulinalg::matrix::Matrix;
 use rulinalg::vector::Vector;
+use parallel::grid_cells;
+use rayon::prelude::*;
+use tiled::{compute_tiled_batched, TileWriter};
+use kernel::{PointColumns, fill_cells, map_coordinates, weighted_sums};
+use index::GridIndex;
+use sparse::{CsrMatrix, conjugate_gradient};
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use wide::{f64x4, CmpEq};
 
 
+#[derive(Debug, Clone, Copy)]
+enum RbfFunction {
+    Linear,
+    Cubic,
+    ThinPlate,
+    Quintic,
+    Gaussian,
+    Multiquadratic,
+    InverseMultiquadratic,
+    InverseQuadratic,
+    WendlandC0,
+    WendlandC2,
+    WendlandC4,
+}
+
+impl RbfFunction {
+    fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "linear" => Ok(RbfFunction::Linear),
+            "cubic" => Ok(RbfFunction::Cubic),
+            "thin_plate" | "thin_plate_spline" => Ok(RbfFunction::ThinPlate),
+            "quintic" => Ok(RbfFunction::Quintic),
+            "gaussian" => Ok(RbfFunction::Gaussian),
+            "multiquadratic" | "multiquadric" => Ok(RbfFunction::Multiquadratic),
+            "inverse_multiquadratic" |
+            "inverse_multiquadric" => Ok(RbfFunction::InverseMultiquadratic),
+            "inverse_quadratic" => Ok(RbfFunction::InverseQuadratic),
+            "wendland_c0" => Ok(RbfFunction::WendlandC0),
+            "wendland_c2" => Ok(RbfFunction::WendlandC2),
+            "wendland_c4" => Ok(RbfFunction::WendlandC4),
+            &_ => Err(ErrorKind::UnknownMethod(name.to_string()).into()),
+        }
+    }
+
+    fn function(self) -> fn(f64, f64) -> f64 {
+        match self {
+            RbfFunction::Linear => distance_linear,
+            RbfFunction::Cubic => distance_cubic,
+            RbfFunction::ThinPlate => distance_thin_plate,
+            RbfFunction::Quintic => distance_quintic,
+            RbfFunction::Gaussian => distance_gaussian,
+            RbfFunction::Multiquadratic => distance_multiquadratic,
+            RbfFunction::InverseMultiquadratic => distance_inverse_multiquadratic,
+            RbfFunction::InverseQuadratic => distance_inverse_quadratic,
+            RbfFunction::WendlandC0 => distance_wendland_c0,
+            RbfFunction::WendlandC2 => distance_wendland_c2,
+            RbfFunction::WendlandC4 => distance_wendland_c4,
+        }
+    }
+
+    /// Whether the interpolant doesn't depend on epsilon (without smoothing).
+    fn is_scale_invariant(self) -> bool {
+        match self {
+            RbfFunction::Linear |
+            RbfFunction::Cubic |
+            RbfFunction::ThinPlate |
+            RbfFunction::Quintic => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the function is zero beyond a distance of epsilon.
+    fn is_compact(self) -> bool {
+        match self {
+            RbfFunction::WendlandC0 |
+            RbfFunction::WendlandC2 |
+            RbfFunction::WendlandC4 => true,
+            _ => false,
+        }
+    }
+
+    /// Sign of the smoothing added to the diagonal of the system, negative
+    /// for the functions scipy's `RBFInterpolator` uses with the opposite sign
+    /// (so that they are conditionally positive definite).
+    fn smoothing_sign(self) -> f64 {
+        match self {
+            RbfFunction::Linear |
+            RbfFunction::Quintic |
+            RbfFunction::Multiquadratic => -1.0,
+            _ => 1.0,
+        }
+    }
+}
+
+/// Polynomial added to the radial basis functions, whose coefficients are
+/// solved for together with their weights (the `degree` of scipy's
+/// `RBFInterpolator`: -1, 0, 1 or 2).
+///
+/// The cubic and thin plate functions need at least a linear polynomial, the
+/// quintic function a quadratic one, to define a unique interpolant.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Polynomial {
+    None,
+    Constant,
+    Linear,
+    Quadratic,
+}
+
+impl Polynomial {
+    fn nb_terms(self) -> usize {
+        match self {
+            Polynomial::None => 0,
+            Polynomial::Constant => 1,
+            Polynomial::Linear => 3,
+            Polynomial::Quadratic => 6,
+        }
+    }
+
+    /// Polynomial of the degree below (`None` for `None`).
+    fn lower(self) -> Self {
+        match self {
+            Polynomial::None | Polynomial::Constant => Polynomial::None,
+            Polynomial::Linear => Polynomial::Constant,
+            Polynomial::Quadratic => Polynomial::Linear,
+        }
+    }
+}
+
+/// Polynomial part of an interpolator, in coordinates shifted and scaled to
+/// [-1, 1] over the observation points (as scipy does for conditioning).
 #[derive(Debug, Clone)]
-pub struct Rbf<'a, T: 'a> {
-    obs_points: &'a [T],
-    weights: Vector<f64>,
-    distance_function: fn(f64, f64) -> f64,
+struct Tail {
+    shift: (f64, f64),
+    scale: (f64, f64),
+    coefs: Vec<f64>,
+}
+
+impl Tail {
+    fn new<T>(obs_points: &[T]) -> Self
+        where T: PtValue
+    {
+        let (mut min_x, mut max_x, mut min_y, mut max_y) =
+            (f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY);
+        for pt in obs_points {
+            let (x, y) = pt.get_coordinates();
+            min_x = min_x.min(x);
+            max_x = max_x.max(x);
+            min_y = min_y.min(y);
+            max_y = max_y.max(y);
+        }
+        let half_range = |min: f64, max: f64| if max > min { (max - min) / 2.0 } else { 1.0 };
+        Tail {
+            shift: ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0),
+            scale: (half_range(min_x, max_x), half_range(min_y, max_y)),
+            coefs: Vec::new(),
+        }
+    }
+
+    /// Values of the monomials (1, x, y, x^2, xy, y^2) at (x, y), of which the
+    /// polynomial uses as many as it has coefficients.
+    fn monomials(&self, x: f64, y: f64) -> [f64; 6] {
+        let (x, y) = ((x - self.shift.0) / self.scale.0, (y - self.shift.1) / self.scale.1);
+        [1.0, x, y, x * x, x * y, y * y]
+    }
+
+    fn value(&self, x: f64, y: f64) -> f64 {
+        self.coefs
+            .iter()
+            .zip(&self.monomials(x, y))
+            .fold(0.0, |sum, (c, m)| sum + c * m)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rbf<'a, T: 'a, M = PointMetric<T>> {
+    /// Observation points, with their weight as value.
+    centers: PointColumns,
+    function: RbfFunction,
     epsilon: f64,
+    /// Index of the centers, for compactly supported functions.
+    support: Option<GridIndex>,
+    tail: Option<Tail>,
+    metric: M,
+    obs_points: PhantomData<&'a [T]>,
 }
 
 impl<'a, T> Rbf<'a, T>
-    where T: PtValue
+    where T: PtValue + Sync
 {
-    pub fn new(obs_points: &'a [T], distance_function: &str, epsilon: Option<f64>) -> Self {
-        let distance_func = match distance_function {
-            "linear" => distance_linear,
-            "cubic" => distance_cubic,
-            "thin_plate" => distance_thin_plate,
-            "quintic" => distance_quintic,
-            "gaussian" => distance_gaussian,
-            "multiquadratic" => distance_multiquadratic,
-            "inverse_multiquadratic" => distance_inverse_multiquadratic,
-            &_ => panic!("Invalid function name!"),
-        };
+    /// Interpolator through `obs_points` with the given function (see
+    /// `augmented` for the functions and `epsilon`).
+    ///
+    /// The compactly supported Wendland functions ("wendland_c0", "wendland_c2"
+    /// and "wendland_c4") take `epsilon` as their (required) support radius:
+    /// their system only involves the pairs of points closer than it, and is
+    /// solved as a sparse system, which allows many more points than the other
+    /// functions, whose dense system grows with the square of their number.
+    pub fn new(obs_points: &'a [T], distance_function: &str, epsilon: Option<f64>) -> Result<Self> {
+        Rbf::augmented(obs_points, distance_function, epsilon, Polynomial::None, 0.0)
+    }
+
+    /// Same as `new`, with a `polynomial` term and a `smooth` parameter,
+    /// following scipy's `RBFInterpolator`: `smooth` is added to the diagonal
+    /// of the system (0 interpolates the values exactly, larger values give a
+    /// smoother surface, approaching the least squares fit of the polynomial).
+    ///
+    /// The functions are the ones of `RBFInterpolator`, of `epsilon * r` for a
+    /// distance `r`: "linear" (`r`), "thin_plate_spline" (`r^2 ln(r)`),
+    /// "cubic" (`r^3`), "quintic" (`r^5`), "multiquadric" (`sqrt(1 + r^2)`),
+    /// "inverse_multiquadric" (`1 / sqrt(1 + r^2)`), "inverse_quadratic"
+    /// (`1 / (1 + r^2)`) and "gaussian" (`exp(-r^2)`). scipy negates the
+    /// linear, quintic and multiquadric functions; the smoothing is subtracted
+    /// for them instead, which gives the same interpolant. The values are the
+    /// ones of `RBFInterpolator(points, values, kernel, epsilon, smoothing,
+    /// degree)`, with these differences:
+    ///
+    /// - "thin_plate", "multiquadratic" and "inverse_multiquadratic" are also
+    ///   accepted as the names of the thin plate spline and multiquadric functions;
+    /// - without `epsilon`, the functions whose interpolant depends on it
+    ///   (which scipy requires it for) use the inverse of the mean distance
+    ///   between the points, the others 1, as scipy;
+    /// - the `polynomial` (the `degree`) doesn't default to the minimum degree
+    ///   of the function, and can be below it (scipy warns), at the risk of a
+    ///   singular system;
+    /// - `smooth` is the same for every point, and there is no `neighbors`
+    ///   parameter (see `LocalRbf` for large numbers of points);
+    /// - the Wendland functions (not in scipy) take `epsilon` as a radius.
+    ///
+    /// The compactly supported functions only take a smoothing, their sparse
+    /// system being solved without polynomial.
+    pub fn augmented(obs_points: &'a [T],
+                     distance_function: &str,
+                     epsilon: Option<f64>,
+                     polynomial: Polynomial,
+                     smooth: f64)
+                     -> Result<Self> {
+        Rbf::with_metric(obs_points,
+                         distance_function,
+                         epsilon,
+                         polynomial,
+                         smooth,
+                         PointMetric::new())
+    }
+}
+
+impl<'a, T, M> Rbf<'a, T, M>
+    where T: PtValue + Sync,
+          M: DistanceMetric
+{
+    /// Same as `augmented`, the distances between the points being computed
+    /// with `metric` (the support radius of the compactly supported functions,
+    /// like `epsilon`, is then in its units).
+    pub fn with_metric(obs_points: &'a [T],
+                       distance_function: &str,
+                       epsilon: Option<f64>,
+                       polynomial: Polynomial,
+                       smooth: f64,
+                       metric: M)
+                       -> Result<Self> {
+        Rbf::build(obs_points, distance_function, epsilon, polynomial, smooth, metric)
+    }
+
+    /// Same as `with_metric`, for points which don't outlive the interpolator
+    /// (it only keeps a copy of their coordinates).
+    fn build(obs_points: &[T],
+             distance_function: &str,
+             epsilon: Option<f64>,
+             polynomial: Polynomial,
+             smooth: f64,
+             metric: M)
+             -> Result<Self> {
+        let function = RbfFunction::from_name(distance_function)?;
+        let distance_func = function.function();
         let nb_pts = obs_points.len();
-        let mut mat = vec![0.0; nb_pts * nb_pts];
-        for j in 0..nb_pts {
-            for i in 0..nb_pts {
-                mat[j * nb_pts + i] = _norm::<T>(&obs_points[i], &obs_points[j]);
+        if nb_pts == 0 {
+            return Err(ErrorKind::EmptyInput.into());
+        }
+        if !(smooth >= 0.0) || smooth.is_infinite() {
+            return Err(format!("Invalid smoothing parameter: {}", smooth).into());
+        }
+        let nb_terms = polynomial.nb_terms();
+        if nb_pts < nb_terms {
+            return Err(format!("At least {} observation points are required by a {:?} \
+                                polynomial",
+                               nb_terms,
+                               polynomial)
+                               .into());
+        }
+        if function.is_compact() {
+            if polynomial != Polynomial::None {
+                return Err(format!("The '{}' function can't be used with a polynomial",
+                                   distance_function)
+                                   .into());
             }
+            return match epsilon {
+                       Some(radius) if radius > 0.0 && radius.is_finite() => {
+                           Rbf::new_compact(obs_points, function, radius, smooth, metric)
+                       }
+                       _ => {
+                           Err(format!("The '{}' function requires a positive support radius \
+                                        (epsilon)",
+                                       distance_function)
+                                       .into())
+                       }
+                   };
         }
-        let eps = if epsilon.is_some() {
-            epsilon.unwrap()
-        } else {
-            sum_all(&mat) / ((nb_pts as f64).powi(2) - nb_pts as f64)
+        let mut mat = vec![0.0; nb_pts * nb_pts];
+        mat.par_chunks_mut(nb_pts)
+            .enumerate()
+            .for_each(|(j, row)| {
+                let (xj, yj) = obs_points[j].get_coordinates();
+                for i in 0..nb_pts {
+                    let (xi, yi) = obs_points[i].get_coordinates();
+                    row[i] = metric.distance(xi, yi, xj, yj);
+                }
+            });
+        let eps = match epsilon {
+            Some(eps) => eps,
+            None if function.is_scale_invariant() => 1.0,
+            // Inverse of the mean distance between the points
+            None => ((nb_pts as f64).powi(2) - nb_pts as f64) / sum_all(&mat),
         };
         // for j in 0..nb_pts {
         //     for i in 0..nb_pts {
         //         mat[j * nb_pts + i] = distance_func(mat[j * nb_pts + i], eps);
         //     }
         // }
-        for ix in 0..(nb_pts * nb_pts) {
-            mat[ix] = distance_func(mat[ix], eps);
+        mat.par_iter_mut().for_each(|r| *r = distance_func(*r, eps));
+        for i in 0..nb_pts {
+            mat[i * nb_pts + i] += function.smoothing_sign() * smooth;
         }
-        let mut values: Vec<f64> = Vec::with_capacity(nb_pts);
+        let mut values: Vec<f64> = Vec::with_capacity(nb_pts + nb_terms);
         for i in 0..nb_pts {
             values.push(obs_points[i].get_value());
         }
-        let mat = Matrix::new(nb_pts, nb_pts, mat);
+        let mut tail = None;
+        if nb_terms > 0 {
+            // [[K, P], [P^T, 0]] * [weights, coefs] = [values, 0]
+            let t = Tail::new(obs_points);
+            let size = nb_pts + nb_terms;
+            let mut full = vec![0.0; size * size];
+            for j in 0..nb_pts {
+                full[j * size..j * size + nb_pts].copy_from_slice(&mat[j * nb_pts..(j + 1) * nb_pts]);
+                let (x, y) = obs_points[j].get_coordinates();
+                let monomials = t.monomials(x, y);
+                for (k, &m) in monomials[..nb_terms].iter().enumerate() {
+                    full[j * size + nb_pts + k] = m;
+                    full[(nb_pts + k) * size + j] = m;
+                }
+            }
+            values.extend((0..nb_terms).map(|_| 0.0));
+            mat = full;
+            tail = Some(t);
+        }
+        let size = nb_pts + nb_terms;
+        let mat = Matrix::new(size, size, mat);
         let vec = Vector::new(values);
         // let weights = mat.solve(vec).unwrap().into_iter().collect::<Vec<f64>>();
-        let weights = mat.solve(vec).unwrap();
-        Rbf {
-            obs_points: obs_points,
-            distance_function: distance_func,
-            epsilon: eps,
-            weights: weights,
+        let mut weights = mat.solve(vec)
+            .map_err(|_| Error::from(ErrorKind::SingularSystem))?
+            .into_vec();
+        if let Some(ref mut t) = tail {
+            t.coefs = weights.split_off(nb_pts);
         }
+        Ok(Rbf {
+               centers: PointColumns::with_values(obs_points, weights),
+               function: function,
+               epsilon: eps,
+               support: None,
+               tail: tail,
+               metric: metric,
+               obs_points: PhantomData,
+           })
+    }
+
+    fn new_compact(obs_points: &[T],
+                   function: RbfFunction,
+                   radius: f64,
+                   smooth: f64,
+                   metric: M)
+                   -> Result<Self> {
+        let distance_func = function.function();
+        // Buckets sized after the search window at the middle of the points
+        let (mut min_x, mut max_x, mut min_y, mut max_y) =
+            (f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY);
+        for pt in obs_points {
+            let (x, y) = pt.get_coordinates();
+            min_x = min_x.min(x);
+            max_x = max_x.max(x);
+            min_y = min_y.min(y);
+            max_y = max_y.max(y);
+        }
+        let window = metric.search_window((min_x + max_x) / 2.0, (min_y + max_y) / 2.0, radius);
+        let index = GridIndex::new(obs_points,
+                                   ((window.max_x - window.min_x) / 2.0,
+                                    (window.max_y - window.min_y) / 2.0));
+        let rows = (0..obs_points.len())
+            .into_par_iter()
+            .map(|i| {
+                let mut row = Vec::new();
+                let (x, y) = obs_points[i].get_coordinates();
+                index.query(&metric.search_window(x, y, radius), |j| {
+                                let (xj, yj) = obs_points[j].get_coordinates();
+                                let r = metric.distance(x, y, xj, yj);
+                                if j == i {
+                                    row.push((j, distance_func(r, radius) + smooth));
+                                } else if r < radius {
+                                    row.push((j, distance_func(r, radius)));
+                                }
+                            });
+                row.sort_by_key(|&(j, _)| j);
+                row
+            })
+            .collect::<Vec<Vec<(usize, f64)>>>();
+        let mat = CsrMatrix::from_rows(rows);
+        let values = obs_points.iter().map(|pt| pt.get_value()).collect::<Vec<f64>>();
+        let weights = conjugate_gradient(&mat, &values, 1e-10, obs_points.len().max(1000))?;
+        Ok(Rbf {
+               centers: PointColumns::with_values(obs_points, weights),
+               function: function,
+               epsilon: radius,
+               support: Some(index),
+               tail: None,
+               metric: metric,
+               obs_points: PhantomData,
+           })
     }
 
     pub fn interp_point(&self, pt: (f64, f64)) -> f64 {
-        let _pt = T::new(pt.0, pt.1, 0.0);
-        let mut distances: Vec<f64> = Vec::with_capacity(self.obs_points.len());
-        for point in self.obs_points {
-            let a = _norm(&_pt, point);
-            distances.push((self.distance_function)(a, self.epsilon));
+        let mut value = [0.0];
+        self.interp_batch(&[pt], &mut value);
+        value[0]
+    }
+
+    /// Interpolated values at each of the given points, computed in parallel.
+    pub fn interp_points(&self, pts: &[(f64, f64)]) -> Vec<f64> {
+        map_coordinates(pts, |cells, out| self.interp_batch(cells, out))
+    }
+
+    /// Interpolated values on a grid, computed in parallel.
+    pub fn interp_grid(&self, bbox: &Bbox, reso_x: usize, reso_y: usize) -> Result<Vec<T>>
+        where T: Send
+    {
+        bbox.check_grid(reso_x, reso_y)?;
+        let mut plots = grid_cells(bbox, reso_x, reso_y);
+        fill_cells(&mut plots, true, |cells, out| self.interp_batch(cells, out));
+        Ok(plots)
+    }
+
+    fn interp_batch(&self, cells: &[(f64, f64)], out: &mut [f64]) {
+        if let Some(ref index) = self.support {
+            self.interp_batch_compact(index, cells, out);
+        } else {
+            self.interp_batch_dense(cells, out);
+        }
+        if let Some(ref tail) = self.tail {
+            for (&(x, y), o) in cells.iter().zip(out.iter_mut()) {
+                *o += tail.value(x, y);
+            }
+        }
+    }
+
+    fn interp_batch_dense(&self, cells: &[(f64, f64)], out: &mut [f64]) {
+        let (centers, metric, eps) = (&self.centers, &self.metric, self.epsilon);
+        match self.function {
+            RbfFunction::Linear => {
+                weighted_sums(cells, centers, metric, &|r| r * f64x4::splat(eps), out)
+            }
+            RbfFunction::Cubic => {
+                weighted_sums(cells, centers, metric, &|r| cubic_lanes(r, eps), out)
+            }
+            RbfFunction::ThinPlate => {
+                weighted_sums(cells, centers, metric, &|r| thin_plate_lanes(r, eps), out)
+            }
+            RbfFunction::Quintic => {
+                weighted_sums(cells, centers, metric, &|r| quintic_lanes(r, eps), out)
+            }
+            RbfFunction::Gaussian => {
+                weighted_sums(cells, centers, metric, &|r| gaussian_lanes(r, eps), out)
+            }
+            RbfFunction::Multiquadratic => {
+                weighted_sums(cells, centers, metric, &|r| multiquadratic_lanes(r, eps), out)
+            }
+            RbfFunction::InverseMultiquadratic => {
+                weighted_sums(cells,
+                              centers,
+                              metric,
+                              &|r| f64x4::ONE / multiquadratic_lanes(r, eps),
+                              out)
+            }
+            RbfFunction::InverseQuadratic => {
+                weighted_sums(cells, centers, metric, &|r| inverse_quadratic_lanes(r, eps), out)
+            }
+            RbfFunction::WendlandC0 => {
+                weighted_sums(cells, centers, metric, &|r| wendland_c0_lanes(r, eps), out)
+            }
+            RbfFunction::WendlandC2 => {
+                weighted_sums(cells, centers, metric, &|r| wendland_c2_lanes(r, eps), out)
+            }
+            RbfFunction::WendlandC4 => {
+                weighted_sums(cells, centers, metric, &|r| wendland_c4_lanes(r, eps), out)
+            }
+        }
+    }
+
+    /// Sum over the centers within the support radius of each cell only.
+    fn interp_batch_compact(&self, index: &GridIndex, cells: &[(f64, f64)], out: &mut [f64]) {
+        let (distance_func, radius) = (self.function.function(), self.epsilon);
+        for (&(x, y), o) in cells.iter().zip(out.iter_mut()) {
+            let mut sum = 0.0;
+            index.query(&self.metric.search_window(x, y, radius), |ix| {
+                            let (cx, cy, w) = self.centers.get(ix);
+                            let r = self.metric.distance(x, y, cx, cy);
+                            if r < radius {
+                                sum += w * distance_func(r, radius);
+                            }
+                        });
+            *o = sum;
         }
-        let dist = Vector::new(distances);
-        let r = &dist.elemul(&self.weights);
-        r.sum()
     }
 }
 
-pub fn rbf_interpolation<T>(reso_x: u32,
-                            reso_y: u32,
+pub fn rbf_interpolation<T>(reso_x: usize,
+                            reso_y: usize,
                             bbox: &Bbox,
                             obs_points: &[T],
                             func_name: &str,
                             epsilon: Option<f64>)
                             -> Result<Vec<T>>
-    where T: PtValue
+    where T: PtValue + Send + Sync
 {
-    let x_step = (bbox.max_x - bbox.min_x) / reso_x as f64;
-    let y_step = (bbox.max_y - bbox.min_y) / reso_y as f64;
-    let mut plots = Vec::with_capacity((reso_x * reso_y) as usize);
-    let rbf = Rbf::new(obs_points, func_name, epsilon);
-    for i in 0..reso_x {
-        for j in 0..reso_y {
-            let x = bbox.min_x + x_step * i as f64;
-            let y = bbox.min_y + y_step * j as f64;
-            let value = rbf.interp_point((x, y));
-            plots.push(T::new(x, y, value));
+    bbox.check_grid(reso_x, reso_y)?;
+    Rbf::new(obs_points, func_name, epsilon)?.interp_grid(bbox, reso_x, reso_y)
+}
+
+/// Same as `rbf_interpolation`, the distances being computed with `metric`.
+pub fn rbf_interpolation_with_metric<T, M>(reso_x: usize,
+                                           reso_y: usize,
+                                           bbox: &Bbox,
+                                           obs_points: &[T],
+                                           func_name: &str,
+                                           epsilon: Option<f64>,
+                                           metric: &M)
+                                           -> Result<Vec<T>>
+    where T: PtValue + Send + Sync,
+          M: DistanceMetric + Clone
+{
+    bbox.check_grid(reso_x, reso_y)?;
+    Rbf::with_metric(obs_points, func_name, epsilon, Polynomial::None, 0.0, metric.clone())?
+        .interp_grid(bbox, reso_x, reso_y)
+}
+
+/// Radial basis function interpolation on a grid, computed by tiles of
+/// `tile_size` cells passed to `writer` (see `tiled::compute_tiled`).
+pub fn rbf_tiled<T, W>(reso_x: usize,
+                       reso_y: usize,
+                       bbox: &Bbox,
+                       obs_points: &[T],
+                       func_name: &str,
+                       epsilon: Option<f64>,
+                       tile_size: usize,
+                       writer: &mut W)
+                       -> Result<()>
+    where T: PtValue + Sync,
+          W: TileWriter
+{
+    rbf_tiled_with_metric(reso_x,
+                          reso_y,
+                          bbox,
+                          obs_points,
+                          func_name,
+                          epsilon,
+                          &PointMetric::<T>::new(),
+                          tile_size,
+                          writer)
+}
+
+/// Same as `rbf_tiled`, the distances being computed with `metric`.
+pub fn rbf_tiled_with_metric<T, M, W>(reso_x: usize,
+                                      reso_y: usize,
+                                      bbox: &Bbox,
+                                      obs_points: &[T],
+                                      func_name: &str,
+                                      epsilon: Option<f64>,
+                                      metric: &M,
+                                      tile_size: usize,
+                                      writer: &mut W)
+                                      -> Result<()>
+    where T: PtValue + Sync,
+          M: DistanceMetric + Clone,
+          W: TileWriter
+{
+    let rbf = Rbf::with_metric(obs_points,
+                               func_name,
+                               epsilon,
+                               Polynomial::None,
+                               0.0,
+                               metric.clone())?;
+    compute_tiled_batched(bbox,
+                          reso_x,
+                          reso_y,
+                          tile_size,
+                          |cells, out| rbf.interp_batch(cells, out),
+                          writer)
+}
+
+/// Regular grid of overlapping discs ("patches") covering the observation points.
+#[derive(Debug, Clone)]
+struct PatchGrid {
+    origin: (f64, f64),
+    patch_size: f64,
+    radius: f64,
+    nx: usize,
+    ny: usize,
+}
+
+impl PatchGrid {
+    /// Call `f` with the index of, and the distance to, every patch whose
+    /// center is closer than the patch radius to (x, y).
+    fn around<F>(&self, x: f64, y: f64, mut f: F)
+        where F: FnMut(usize, f64)
+    {
+        let range = |v: f64, origin: f64, n: usize| {
+            let lo = ((v - self.radius - origin) / self.patch_size - 0.5).ceil().max(0.0);
+            let hi = ((v + self.radius - origin) / self.patch_size - 0.5)
+                .floor()
+                .min(n as f64 - 1.0);
+            if hi >= lo { lo as usize..hi as usize + 1 } else { 0..0 }
+        };
+        for i in range(x, self.origin.0, self.nx) {
+            let cx = self.origin.0 + (i as f64 + 0.5) * self.patch_size;
+            for j in range(y, self.origin.1, self.ny) {
+                let cy = self.origin.1 + (j as f64 + 0.5) * self.patch_size;
+                let dist = Euclidean.distance(x, y, cx, cy);
+                if dist < self.radius {
+                    f(i * self.ny + j, dist);
+                }
+            }
+        }
+    }
+}
+
+/// Partition of unity interpolation: the domain is split into square patches
+/// of `patch_size` (in the units of the coordinates), each extended to the
+/// disc of radius `(1 + overlap) * patch_size / sqrt(2)` around its center.
+/// An `Rbf` is solved on the points of each patch (in parallel), and the
+/// interpolated value is the mean of the values of the patches covering it,
+/// weighted by a Wendland C2 function of the distance to their center.
+///
+/// Each system only involves the points of one patch, so that the cost grows
+/// linearly with the number of points for a given density. When `epsilon` is
+/// `None`, each patch uses its own default value. The patches with fewer than
+/// two points (or than the terms of the polynomial) are left out, and the
+/// value of the places no other patch covers is NaN. A patch whose system is
+/// singular (e.g. its points are aligned, which a linear polynomial can't
+/// be solved for) is solved again with polynomials of lower degrees, and
+/// left out if it is still singular without any.
+///
+/// The patches are laid out in the units of the coordinates whatever the
+/// metric, which only applies to the interpolators of the patches.
+#[derive(Debug, Clone)]
+pub struct LocalRbf<'a, T: 'a, M = PointMetric<T>> {
+    grid: PatchGrid,
+    patches: Vec<Option<Rbf<'a, T, M>>>,
+}
+
+impl<'a, T> LocalRbf<'a, T>
+    where T: PtValue + Sync
+{
+    pub fn new(obs_points: &'a [T],
+               distance_function: &str,
+               epsilon: Option<f64>,
+               polynomial: Polynomial,
+               smooth: f64,
+               patch_size: f64,
+               overlap: f64)
+               -> Result<Self> {
+        LocalRbf::with_metric(obs_points,
+                              distance_function,
+                              epsilon,
+                              polynomial,
+                              smooth,
+                              patch_size,
+                              overlap,
+                              PointMetric::new())
+    }
+}
+
+impl<'a, T, M> LocalRbf<'a, T, M>
+    where T: PtValue + Sync,
+          M: DistanceMetric + Clone
+{
+    /// Same as `new`, the interpolators of the patches computing their
+    /// distances with `metric`.
+    pub fn with_metric(obs_points: &'a [T],
+                       distance_function: &str,
+                       epsilon: Option<f64>,
+                       polynomial: Polynomial,
+                       smooth: f64,
+                       patch_size: f64,
+                       overlap: f64,
+                       metric: M)
+                       -> Result<Self> {
+        if obs_points.is_empty() {
+            return Err(ErrorKind::EmptyInput.into());
+        }
+        if !(patch_size > 0.0) || patch_size.is_infinite() {
+            return Err(format!("Invalid patch size: {}", patch_size).into());
+        }
+        if !(overlap > 0.0) || overlap.is_infinite() {
+            return Err(format!("Invalid patch overlap: {} (must be positive)", overlap).into());
+        }
+        let (mut min_x, mut max_x, mut min_y, mut max_y) =
+            (f64::INFINITY, f64::NEG_INFINITY, f64::INFINITY, f64::NEG_INFINITY);
+        for pt in obs_points {
+            let (x, y) = pt.get_coordinates();
+            min_x = min_x.min(x);
+            max_x = max_x.max(x);
+            min_y = min_y.min(y);
+            max_y = max_y.max(y);
+        }
+        let count = |width: f64| ((width / patch_size).ceil() as usize).max(1);
+        let grid = PatchGrid {
+            origin: (min_x, min_y),
+            patch_size: patch_size,
+            radius: (1.0 + overlap) * patch_size / 2f64.sqrt(),
+            nx: count(max_x - min_x),
+            ny: count(max_y - min_y),
+        };
+        let nb_patches = grid.nx
+            .checked_mul(grid.ny)
+            .ok_or_else(|| format!("Too many patches of size {}", patch_size))?;
+        let mut members = vec![Vec::new(); nb_patches];
+        for (ix, pt) in obs_points.iter().enumerate() {
+            let (x, y) = pt.get_coordinates();
+            grid.around(x, y, |patch, _| members[patch].push(ix));
+        }
+        let min_points = polynomial.nb_terms().max(2);
+        let patches = members
+            .into_par_iter()
+            .map(|ixs| {
+                if ixs.len() < min_points {
+                    return Ok(None);
+                }
+                let pts = ixs.iter()
+                    .map(|&ix| {
+                             let (x, y, val) = obs_points[ix].get_triplet();
+                             T::new(x, y, val)
+                         })
+                    .collect::<Vec<T>>();
+                let mut polynomial = polynomial;
+                loop {
+                    match Rbf::build(&pts,
+                                     distance_function,
+                                     epsilon,
+                                     polynomial,
+                                     smooth,
+                                     metric.clone()) {
+                        Err(Error(ErrorKind::SingularSystem, _)) => {
+                            if polynomial == Polynomial::None {
+                                return Ok(None);
+                            }
+                            polynomial = polynomial.lower();
+                        }
+                        res => return res.map(Some),
+                    }
+                }
+            })
+            .collect::<Result<Vec<Option<Rbf<T, M>>>>>()?;
+        Ok(LocalRbf {
+               grid: grid,
+               patches: patches,
+           })
+    }
+
+    /// Number of patches holding enough points to be solved.
+    pub fn nb_patches(&self) -> usize {
+        self.patches.iter().filter(|patch| patch.is_some()).count()
+    }
+
+    pub fn interp_point(&self, pt: (f64, f64)) -> f64 {
+        let mut value = [0.0];
+        self.interp_batch(&[pt], &mut value);
+        value[0]
+    }
+
+    /// Interpolated values at each of the given points, computed in parallel.
+    pub fn interp_points(&self, pts: &[(f64, f64)]) -> Vec<f64> {
+        map_coordinates(pts, |cells, out| self.interp_batch(cells, out))
+    }
+
+    /// Interpolated values on a grid, computed in parallel.
+    pub fn interp_grid(&self, bbox: &Bbox, reso_x: usize, reso_y: usize) -> Result<Vec<T>>
+        where T: Send
+    {
+        bbox.check_grid(reso_x, reso_y)?;
+        let mut plots = grid_cells(bbox, reso_x, reso_y);
+        fill_cells(&mut plots, true, |cells, out| self.interp_batch(cells, out));
+        Ok(plots)
+    }
+
+    fn interp_batch(&self, cells: &[(f64, f64)], out: &mut [f64]) {
+        // Cells (and their weight) covered by each patch, the patches being
+        // evaluated in the same order for any batch
+        let mut covered = BTreeMap::new();
+        for (c, &(x, y)) in cells.iter().enumerate() {
+            self.grid
+                .around(x, y, |patch, dist| if self.patches[patch].is_some() {
+                    covered
+                        .entry(patch)
+                        .or_insert_with(Vec::new)
+                        .push((c, distance_wendland_c2(dist, self.grid.radius)));
+                });
+        }
+        let (mut sums, mut sum_weights) = (vec![0.0; cells.len()], vec![0.0; cells.len()]);
+        for (patch, members) in covered {
+            let rbf = self.patches[patch].as_ref().unwrap();
+            let coords = members.iter().map(|&(c, _)| cells[c]).collect::<Vec<_>>();
+            let mut values = vec![0.0; coords.len()];
+            rbf.interp_batch(&coords, &mut values);
+            for (&(c, w), val) in members.iter().zip(values) {
+                sums[c] += w * val;
+                sum_weights[c] += w;
+            }
+        }
+        for (o, (sum, sw)) in out.iter_mut().zip(sums.into_iter().zip(sum_weights)) {
+            *o = if sw > 0.0 { sum / sw } else { f64::NAN };
         }
     }
-    Ok(plots)
 }
 
 fn sum_all(mat: &Vec<f64>) -> f64 {
@@ -110,49 +846,140 @@ fn sum_all(mat: &Vec<f64>) -> f64 {
 }
 
 
-fn _norm<T>(pa: &T, pb: &T) -> f64
-    where T: PtValue
-{
-    let ca = pa.get_coordinates();
-    let cb = pb.get_coordinates();
-    ((ca.0 - cb.0).powi(2) + (ca.1 - cb.1).powi(2)).sqrt()
-}
+// Functions of scipy's `RBFInterpolator`, of `epsilon * r` (see `Rbf::augmented`).
 
 #[inline(always)]
-#[allow(unused_variables)]
 fn distance_linear(r: f64, epsilon: f64) -> f64 {
-    r
+    epsilon * r
 }
 
 #[inline(always)]
-#[allow(unused_variables)]
 fn distance_cubic(r: f64, epsilon: f64) -> f64 {
-    r.powi(3)
+    (epsilon * r).powi(3)
 }
 
 #[inline(always)]
-#[allow(unused_variables)]
 fn distance_quintic(r: f64, epsilon: f64) -> f64 {
-    r.powi(5)
+    (epsilon * r).powi(5)
 }
 
 #[inline(always)]
-#[allow(unused_variables)]
 fn distance_thin_plate(r: f64, epsilon: f64) -> f64 {
+    let r = epsilon * r;
     if r == 0.0 { 0.0 } else { r.powi(2) * r.ln() }
 }
 
 #[inline(always)]
 fn distance_gaussian(r: f64, epsilon: f64) -> f64 {
-    1.0 / ((r / epsilon).powi(2) + 1.0).exp()
+    (-(epsilon * r).powi(2)).exp()
 }
 
 #[inline(always)]
 fn distance_inverse_multiquadratic(r: f64, epsilon: f64) -> f64 {
-    1.0 / ((r / epsilon).powi(2) + 1.0).sqrt()
+    1.0 / ((epsilon * r).powi(2) + 1.0).sqrt()
 }
 
 #[inline(always)]
 fn distance_multiquadratic(r: f64, epsilon: f64) -> f64 {
-    ((r / epsilon).powi(2) + 1.0).sqrt()
+    ((epsilon * r).powi(2) + 1.0).sqrt()
+}
+
+#[inline(always)]
+fn distance_inverse_quadratic(r: f64, epsilon: f64) -> f64 {
+    1.0 / ((epsilon * r).powi(2) + 1.0)
+}
+
+/// Wendland functions of smoothness C0, C2 and C4 (positive definite in
+/// two dimensions), equal to 1 at the center and 0 beyond `epsilon`.
+#[inline(always)]
+fn distance_wendland_c0(r: f64, epsilon: f64) -> f64 {
+    let q = r / epsilon;
+    if q >= 1.0 { 0.0 } else { (1.0 - q).powi(2) }
+}
+
+#[inline(always)]
+fn distance_wendland_c2(r: f64, epsilon: f64) -> f64 {
+    let q = r / epsilon;
+    if q >= 1.0 {
+        0.0
+    } else {
+        (1.0 - q).powi(4) * (4.0 * q + 1.0)
+    }
+}
+
+#[inline(always)]
+fn distance_wendland_c4(r: f64, epsilon: f64) -> f64 {
+    let q = r / epsilon;
+    if q >= 1.0 {
+        0.0
+    } else {
+        (1.0 - q).powi(6) * (35.0 * q * q + 18.0 * q + 3.0) / 3.0
+    }
+}
+
+// The same functions on each lane of `r`, for the batched evaluation.
+
+#[inline(always)]
+fn cubic_lanes(r: f64x4, epsilon: f64) -> f64x4 {
+    let r = r * f64x4::splat(epsilon);
+    r * r * r
+}
+
+#[inline(always)]
+fn quintic_lanes(r: f64x4, epsilon: f64) -> f64x4 {
+    let r = r * f64x4::splat(epsilon);
+    let r2 = r * r;
+    r2 * r2 * r
+}
+
+#[inline(always)]
+fn thin_plate_lanes(r: f64x4, epsilon: f64) -> f64x4 {
+    let r = r * f64x4::splat(epsilon);
+    r.cmp_eq(f64x4::ZERO).blend(f64x4::ZERO, r * r * r.ln())
+}
+
+#[inline(always)]
+fn gaussian_lanes(r: f64x4, epsilon: f64) -> f64x4 {
+    let r = r * f64x4::splat(epsilon);
+    (f64x4::ZERO - r * r).exp()
+}
+
+#[inline(always)]
+fn multiquadratic_lanes(r: f64x4, epsilon: f64) -> f64x4 {
+    let r = r * f64x4::splat(epsilon);
+    (r * r + f64x4::ONE).sqrt()
+}
+
+#[inline(always)]
+fn inverse_quadratic_lanes(r: f64x4, epsilon: f64) -> f64x4 {
+    let r = r * f64x4::splat(epsilon);
+    f64x4::ONE / (r * r + f64x4::ONE)
+}
+
+/// `r / epsilon` and `1 - r / epsilon`, the latter being 0 beyond `epsilon`.
+#[inline(always)]
+fn support_lanes(r: f64x4, epsilon: f64) -> (f64x4, f64x4) {
+    let q = r / f64x4::splat(epsilon);
+    (q, (f64x4::ONE - q).max(f64x4::ZERO))
+}
+
+#[inline(always)]
+fn wendland_c0_lanes(r: f64x4, epsilon: f64) -> f64x4 {
+    let (_, t) = support_lanes(r, epsilon);
+    t * t
+}
+
+#[inline(always)]
+fn wendland_c2_lanes(r: f64x4, epsilon: f64) -> f64x4 {
+    let (q, t) = support_lanes(r, epsilon);
+    let t2 = t * t;
+    t2 * t2 * (f64x4::splat(4.0) * q + f64x4::ONE)
+}
+
+#[inline(always)]
+fn wendland_c4_lanes(r: f64x4, epsilon: f64) -> f64x4 {
+    let (q, t) = support_lanes(r, epsilon);
+    let poly = f64x4::splat(35.0) * q * q + f64x4::splat(18.0) * q + f64x4::splat(3.0);
+    let t2 = t * t;
+    t2 * t2 * t2 * poly / f64x4::splat(3.0)
 }
@@ -0,0 +1,118 @@
+//! Sparse symmetric matrices and conjugate gradient solver.
+use rayon::prelude::*;
+use errors::*;
+
+
+/// Square matrix in compressed sparse row format.
+#[derive(Debug, Clone)]
+pub struct CsrMatrix {
+    row_start: Vec<usize>,
+    cols: Vec<usize>,
+    values: Vec<f64>,
+}
+
+impl CsrMatrix {
+    /// Matrix whose row `i` holds the (column, value) pairs of `rows[i]`.
+    pub fn from_rows(rows: Vec<Vec<(usize, f64)>>) -> Self {
+        let nnz = rows.iter().map(|row| row.len()).sum();
+        let mut row_start = Vec::with_capacity(rows.len() + 1);
+        let (mut cols, mut values) = (Vec::with_capacity(nnz), Vec::with_capacity(nnz));
+        row_start.push(0);
+        for row in rows {
+            for (col, val) in row {
+                cols.push(col);
+                values.push(val);
+            }
+            row_start.push(cols.len());
+        }
+        CsrMatrix {
+            row_start: row_start,
+            cols: cols,
+            values: values,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.row_start.len() - 1
+    }
+
+    /// Number of stored values.
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    fn diagonal(&self) -> Vec<f64> {
+        (0..self.size())
+            .map(|i| {
+                     (self.row_start[i]..self.row_start[i + 1])
+                         .filter(|&k| self.cols[k] == i)
+                         .map(|k| self.values[k])
+                         .sum()
+                 })
+            .collect()
+    }
+
+    /// `out = self * x`
+    pub fn mul_vec(&self, x: &[f64], out: &mut [f64]) {
+        out.par_iter_mut()
+            .enumerate()
+            .for_each(|(i, o)| {
+                          *o = (self.row_start[i]..self.row_start[i + 1])
+                              .fold(0.0, |sum, k| sum + self.values[k] * x[self.cols[k]]);
+                      });
+    }
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).fold(0.0, |sum, (x, y)| sum + x * y)
+}
+
+/// Solve `mat * x = b` for a symmetric positive definite `mat`, using the
+/// conjugate gradient method with a diagonal (Jacobi) preconditioner, until
+/// the norm of the residual is below `tolerance` times the norm of `b`.
+pub fn conjugate_gradient(mat: &CsrMatrix,
+                          b: &[f64],
+                          tolerance: f64,
+                          max_iterations: usize)
+                          -> Result<Vec<f64>> {
+    let n = mat.size();
+    let inv_diag = mat.diagonal()
+        .into_iter()
+        .map(|d| if d > 0.0 { 1.0 / d } else { 1.0 })
+        .collect::<Vec<f64>>();
+    let mut x = vec![0.0; n];
+    let mut r = b.to_vec();
+    let b_norm = dot(b, b).sqrt();
+    if b_norm == 0.0 {
+        return Ok(x);
+    }
+    let mut z = r.iter().zip(&inv_diag).map(|(r, d)| r * d).collect::<Vec<f64>>();
+    let mut p = z.clone();
+    let mut ap = vec![0.0; n];
+    let mut rz = dot(&r, &z);
+    for _ in 0..max_iterations {
+        mat.mul_vec(&p, &mut ap);
+        let p_ap = dot(&p, &ap);
+        if !(p_ap > 0.0) {
+            return Err(ErrorKind::SingularSystem.into());
+        }
+        let alpha = rz / p_ap;
+        for i in 0..n {
+            x[i] += alpha * p[i];
+            r[i] -= alpha * ap[i];
+        }
+        if dot(&r, &r).sqrt() <= tolerance * b_norm {
+            return Ok(x);
+        }
+        for i in 0..n {
+            z[i] = r[i] * inv_diag[i];
+        }
+        let rz_next = dot(&r, &z);
+        let beta = rz_next / rz;
+        rz = rz_next;
+        for i in 0..n {
+            p[i] = z[i] + beta * p[i];
+        }
+    }
+    Err(ErrorKind::NoConvergence(max_iterations).into())
+}
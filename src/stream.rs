@@ -0,0 +1,352 @@
+//! Incremental readers and writers for large inputs.
+//!
+//! The readers of this module never hold the whole document in memory:
+//! GeoJSON FeatureCollections and JSON arrays of points are deserialized
+//! one element at a time, and newline-delimited GeoJSON (GeoJSONSeq) is read
+//! line by line.
+use serde::de::{Deserializer, DeserializeOwned, DeserializeSeed, IgnoredAny, MapAccess,
+                SeqAccess, Visitor};
+use serde::de::Error as DeError;
+use serde_json;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::marker::PhantomData;
+use errors::*;
+use geojson::Value as GeoValue;
+use geometry::feature_observations;
+use utils::{GeoJsonOptions, GeoJsonReport, PtValue};
+
+
+#[derive(Deserialize)]
+struct StreamedFeature {
+    geometry: Option<StreamedGeometry>,
+    properties: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize)]
+struct StreamedGeometry {
+    #[serde(rename = "type")]
+    geom_type: String,
+    coordinates: Option<serde_json::Value>,
+}
+
+/// Visit the elements of the array found at the top level of the document,
+/// or under the `key` member if the top level is an object, calling
+/// `on_element` for each of them.
+struct ElementsSeed<'f, E, F: 'f> {
+    key: &'static str,
+    on_element: &'f mut F,
+    element: PhantomData<E>,
+}
+
+impl<'de, 'f, E, F> DeserializeSeed<'de> for ElementsSeed<'f, E, F>
+    where E: DeserializeOwned,
+          F: FnMut(E) -> Result<()>
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> ::std::result::Result<(), D::Error>
+        where D: Deserializer<'de>
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'f, E, F> Visitor<'de> for ElementsSeed<'f, E, F>
+    where E: DeserializeOwned,
+          F: FnMut(E) -> Result<()>
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "an array or an object with a `{}` array", self.key)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> ::std::result::Result<(), A::Error>
+        where A: SeqAccess<'de>
+    {
+        let on_element = self.on_element;
+        while let Some(elem) = seq.next_element::<E>()? {
+            on_element(elem).map_err(|e| A::Error::custom(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn visit_map<A>(self, mut map: A) -> ::std::result::Result<(), A::Error>
+        where A: MapAccess<'de>
+    {
+        let (key, on_element) = (self.key, self.on_element);
+        let mut found = false;
+        while let Some(k) = map.next_key::<String>()? {
+            if k == key {
+                found = true;
+                map.next_value_seed(ElementsSeed {
+                                        key: key,
+                                        on_element: &mut *on_element,
+                                        element: PhantomData::<E>,
+                                    })?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        if found {
+            Ok(())
+        } else {
+            Err(A::Error::custom(format!("missing `{}` member", key)))
+        }
+    }
+}
+
+fn stream_elements<E, F>(path: &str, key: &'static str, mut on_element: F) -> Result<()>
+    where E: DeserializeOwned,
+          F: FnMut(E) -> Result<()>
+{
+    let file = File::open(path)?;
+    let mut de = serde_json::Deserializer::from_reader(BufReader::new(file));
+    (&mut de)
+        .deserialize_any(ElementsSeed {
+                             key: key,
+                             on_element: &mut on_element,
+                             element: PhantomData::<E>,
+                         })?;
+    de.end()?;
+    Ok(())
+}
+
+fn json_number(value: &serde_json::Value) -> Result<f64> {
+    match *value {
+        serde_json::Value::Number(ref val) => {
+            val.as_f64().ok_or_else(|| "Invalid number".into())
+        }
+        serde_json::Value::String(ref val) => Ok(val.trim().parse::<f64>()?),
+        _ => Err("Invalid datastructure".into()),
+    }
+}
+
+impl StreamedGeometry {
+    fn into_value(self) -> ::std::result::Result<GeoValue, String> {
+        let StreamedGeometry { geom_type, coordinates } = self;
+        let coordinates = coordinates.unwrap_or(serde_json::Value::Null);
+        let value = match geom_type.as_str() {
+            "Point" => serde_json::from_value(coordinates).map(GeoValue::Point),
+            "MultiPoint" => serde_json::from_value(coordinates).map(GeoValue::MultiPoint),
+            "Polygon" => serde_json::from_value(coordinates).map(GeoValue::Polygon),
+            "MultiPolygon" => serde_json::from_value(coordinates).map(GeoValue::MultiPolygon),
+            other => return Err(format!("unsupported geometry type {}", other)),
+        };
+        value.map_err(|e| format!("invalid {} coordinates ({})", geom_type, e))
+    }
+}
+
+fn streamed_observations(feature: StreamedFeature,
+                         field_name: &str,
+                         options: &GeoJsonOptions)
+                         -> ::std::result::Result<Vec<(f64, f64, f64)>, String> {
+    let geometry = match feature.geometry {
+        Some(geometry) => Some(geometry.into_value()?),
+        None => None,
+    };
+    feature_observations(geometry.as_ref(),
+                         feature.properties.as_ref(),
+                         field_name,
+                         options.polygon_representation,
+                         options.split_multipoint_value)
+}
+
+/// Read the features of a GeoJSON FeatureCollection one at a time,
+/// passing the resulting points to `callback`, and return the number of
+/// points read.
+pub fn stream_geojson_points<T, F>(path: &str, field_name: &str, callback: F) -> Result<usize>
+    where T: PtValue,
+          F: FnMut(T)
+{
+    let report = stream_geojson_points_with(path, field_name, &GeoJsonOptions::default(), callback)?;
+    Ok(report.nb_points)
+}
+
+/// Read the features of a GeoJSON FeatureCollection one at a time,
+/// passing the resulting points to `callback` (see `utils::parse_geojson_points_with`
+/// regarding how features are converted and skipped).
+pub fn stream_geojson_points_with<T, F>(path: &str,
+                                        field_name: &str,
+                                        options: &GeoJsonOptions,
+                                        mut callback: F)
+                                        -> Result<GeoJsonReport>
+    where T: PtValue,
+          F: FnMut(T)
+{
+    let mut report = GeoJsonReport::default();
+    let mut ix = 0;
+    stream_elements(path, "features", |feature: StreamedFeature| {
+        match streamed_observations(feature, field_name, options) {
+            Ok(triplets) => {
+                for (x, y, val) in triplets {
+                    callback(T::new(x, y, val));
+                    report.nb_points += 1;
+                }
+            }
+            Err(reason) => report.skipped_features.push((ix, reason)),
+        }
+        ix += 1;
+        Ok(())
+    })?;
+    Ok(report)
+}
+
+/// Read the points of a JSON file (an array of `{"lon", "lat", "value"}`
+/// objects, possibly under a `values` member) one at a time, passing each of
+/// them to `callback`, and return the number of points read.
+pub fn stream_json_points<T, F>(path: &str, mut callback: F) -> Result<usize>
+    where T: PtValue,
+          F: FnMut(T)
+{
+    let mut count = 0;
+    stream_elements(path, "values", |elem: serde_json::Value| {
+        let x = json_number(&elem["lon"])?;
+        let y = json_number(&elem["lat"])?;
+        let value = json_number(&elem["value"])?;
+        callback(T::new(x, y, value));
+        count += 1;
+        Ok(())
+    })?;
+    Ok(count)
+}
+
+/// Iterator over the points of a newline-delimited GeoJSON file
+/// (GeoJSONSeq, with or without the leading record separators of RFC 8142).
+///
+/// Features which can't be used are skipped and listed in `report()`.
+#[derive(Debug)]
+pub struct GeoJsonSeqReader<T> {
+    lines: Lines<BufReader<File>>,
+    field_name: String,
+    options: GeoJsonOptions,
+    line_nb: usize,
+    nb_features: usize,
+    pending: Vec<T>,
+    report: GeoJsonReport,
+}
+
+impl<T> GeoJsonSeqReader<T>
+    where T: PtValue
+{
+    pub fn open(path: &str, field_name: &str) -> Result<Self> {
+        GeoJsonSeqReader::open_with(path, field_name, &GeoJsonOptions::default())
+    }
+
+    pub fn open_with(path: &str, field_name: &str, options: &GeoJsonOptions) -> Result<Self> {
+        let file = File::open(path)?;
+        Ok(GeoJsonSeqReader {
+               lines: BufReader::new(file).lines(),
+               field_name: field_name.to_string(),
+               options: options.clone(),
+               line_nb: 0,
+               nb_features: 0,
+               pending: Vec::new(),
+               report: GeoJsonReport::default(),
+           })
+    }
+
+    /// Points read and features skipped so far.
+    pub fn report(&self) -> &GeoJsonReport {
+        &self.report
+    }
+
+    fn read_line(&mut self, line: &str) -> Result<()> {
+        let feature: StreamedFeature = serde_json::from_str(line)
+            .chain_err(|| format!("Line {}", self.line_nb))?;
+        match streamed_observations(feature, &self.field_name, &self.options) {
+            Ok(triplets) => {
+                for &(x, y, val) in triplets.iter().rev() {
+                    self.pending.push(T::new(x, y, val));
+                }
+            }
+            Err(reason) => self.report.skipped_features.push((self.nb_features, reason)),
+        }
+        self.nb_features += 1;
+        Ok(())
+    }
+}
+
+impl<T> Iterator for GeoJsonSeqReader<T>
+    where T: PtValue
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        loop {
+            if let Some(pt) = self.pending.pop() {
+                self.report.nb_points += 1;
+                return Some(Ok(pt));
+            }
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(err)) => return Some(Err(err.into())),
+                None => return None,
+            };
+            self.line_nb += 1;
+            let text = line.trim_matches(|c: char| c == '\x1e' || c.is_whitespace());
+            if text.is_empty() {
+                continue;
+            }
+            if let Err(err) = self.read_line(text) {
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+/// Read all the points of a GeoJSONSeq file.
+pub fn parse_geojsonseq_points<T>(path: &str, field_name: &str) -> Result<Vec<T>>
+    where T: PtValue
+{
+    GeoJsonSeqReader::<T>::open(path, field_name)?.collect()
+}
+
+/// Writer of result points as a GeoJSONSeq file, one Point feature per line.
+#[derive(Debug)]
+pub struct GeoJsonSeqWriter {
+    writer: BufWriter<File>,
+}
+
+impl GeoJsonSeqWriter {
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(GeoJsonSeqWriter { writer: BufWriter::new(file) })
+    }
+
+    pub fn write_point<T>(&mut self, pt: &T) -> Result<()>
+        where T: PtValue
+    {
+        let (x, y, value) = pt.get_triplet();
+        let mut geometry = serde_json::Map::new();
+        geometry.insert(String::from("type"), serde_json::Value::String("Point".into()));
+        geometry.insert(String::from("coordinates"), serde_json::to_value(vec![x, y])?);
+        let mut prop = serde_json::Map::new();
+        prop.insert(String::from("value"), serde_json::to_value(value)?);
+        let mut feature = serde_json::Map::new();
+        feature.insert(String::from("type"), serde_json::Value::String("Feature".into()));
+        feature.insert(String::from("geometry"), serde_json::Value::Object(geometry));
+        feature.insert(String::from("properties"), serde_json::Value::Object(prop));
+        serde_json::to_writer(&mut self.writer, &feature)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Write the result points as a GeoJSONSeq file.
+pub fn save_geojsonseq_points<T>(path: &str, result_points: &[T]) -> Result<()>
+    where T: PtValue
+{
+    let mut writer = GeoJsonSeqWriter::create(path)?;
+    for pt in result_points {
+        writer.write_point(pt)?;
+    }
+    writer.finish()
+}
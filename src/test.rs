@@ -15,8 +15,8 @@ mod test {
         let obs_points = utils::parse_json_points::<utils::SphericalPtValue>("examples/ra.json")
             .unwrap();
         let bbox = bbox::Bbox::new(1.0, 4.0, 32.0, 35.0);
-        let reso_lat: u32 = 80;
-        let reso_lon: u32 = 80;
+        let reso_lat: usize = 80;
+        let reso_lon: usize = 80;
         let conf1 = StewartPotentialGrid::new(15000.0,
                                               2.0,
                                               SmoothType::Exponential,
@@ -43,13 +43,42 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_parallel_grids_equal_serial() {
+        let obs_pts = vec![Pt::new(0.0, 0.0, 0.0),
+                           Pt::new(0.0, 100.0, 6.0),
+                           Pt::new(75.0, 25.0, 3.1),
+                           Pt::new(100.0, 75.0, 7.4)];
+        let bbox = bbox::Bbox::new(0.0, 100.0, 0.0, 100.0);
+        let res_idw = idw_interpolation(23, 17, &bbox, &obs_pts, 2.0).unwrap();
+        let res_rbf = rbf_interpolation(23, 17, &bbox, &obs_pts, "gaussian", None).unwrap();
+        let res_stewart = stewart_interpolation(23, 17, &bbox, &obs_pts, "pareto", 30.0, None)
+            .unwrap();
+        let metric = PointMetric::<Pt>::new();
+        let idw = idw::Idw::new(&obs_pts, 2.0, &metric);
+        let rbf = Rbf::new(&obs_pts, "gaussian", None).unwrap();
+        let stewart = pot_stewart::Stewart::new(&obs_pts, "pareto", 30.0, None, &metric)
+            .unwrap();
+        let coords = res_idw.iter().map(|pt| pt.get_coordinates()).collect::<Vec<_>>();
+        assert_eq!(res_idw.len(), 23 * 17);
+        // The first cell lies on the first point and takes its value
+        assert_eq!(res_idw[0].get_triplet(), (0.0, 0.0, 0.0));
+        assert_eq!(rbf.interp_points(&coords),
+                   coords.iter().map(|&pt| rbf.interp_point(pt)).collect::<Vec<f64>>());
+        for (i, &pt) in coords.iter().enumerate() {
+            assert_eq!(res_idw[i].get_value(), idw.interp_point(pt));
+            assert_eq!(res_rbf[i].get_triplet(), (pt.0, pt.1, rbf.interp_point(pt)));
+            assert_eq!(res_stewart[i].get_triplet(), (pt.0, pt.1, stewart.interp_point(pt)));
+        }
+    }
+
     #[test]
     fn test_radial_basis_func_linear() {
         let obs_pts = vec![Pt::new(0.0, 0.0, 0.0),
                            Pt::new(0.0, 100.0, 6.0),
                            Pt::new(75.0, 25.0, 3.1),
                            Pt::new(100.0, 75.0, 7.4)];
-        let rbf = Rbf::new(&obs_pts, "linear", None);
+        let rbf = Rbf::new(&obs_pts, "linear", None).unwrap();
         assert_eq!(true,
                    utils::almost_equal(2.843937337, rbf.interp_point((0.0, 50.0)), 0.0000001));
         assert_eq!(true,
@@ -62,7 +91,7 @@ mod test {
                            Pt::new(0.0, 100.0, 6.0),
                            Pt::new(75.0, 25.0, 3.1),
                            Pt::new(100.0, 75.0, 7.4)];
-        let rbf = Rbf::new(&obs_pts, "cubic", None);
+        let rbf = Rbf::new(&obs_pts, "cubic", None).unwrap();
         assert_eq!(true,
                    utils::almost_equal(0.554789362, rbf.interp_point((0.0, 50.0)), 0.0000001));
         assert_eq!(true,
@@ -75,10 +104,1243 @@ mod test {
                            Pt::new(0.0, 100.0, 6.0),
                            Pt::new(75.0, 25.0, 3.1),
                            Pt::new(100.0, 75.0, 7.4)];
-        let rbf = Rbf::new(&obs_pts, "gaussian", None);
+        let rbf = Rbf::new(&obs_pts, "gaussian", None).unwrap();
         assert_eq!(true,
                    utils::almost_equal(3.494929342, rbf.interp_point((0.0, 50.0)), 0.0000001));
         assert_eq!(true,
                    utils::almost_equal(0.777143813, rbf.interp_point((12.0, 12.0)), 0.0000001));
     }
+
+    #[test]
+    fn test_radial_basis_func_wendland() {
+        let obs_pts = (0..400)
+            .map(|i| {
+                     let (x, y) = ((i % 20) as f64 * 10.0, (i / 20) as f64 * 10.0);
+                     Pt::new(x + (i * 7 % 11) as f64 / 4.0,
+                             y + (i * 5 % 13) as f64 / 4.0,
+                             (x / 40.0).sin() + y / 100.0)
+                 })
+            .collect::<Vec<_>>();
+        let rbf = Rbf::new(&obs_pts, "wendland_c2", Some(25.0)).unwrap();
+        for pt in &obs_pts {
+            let (x, y, val) = pt.get_triplet();
+            assert!((rbf.interp_point((x, y)) - val).abs() < 1e-6);
+        }
+        // No center within the support radius
+        assert_eq!(0.0, rbf.interp_point((-100.0, -100.0)));
+        assert!(Rbf::new(&obs_pts, "wendland_c4", None).is_err());
+    }
+
+    #[test]
+    fn test_radial_basis_func_polynomial_and_smoothing() {
+        let plane = |x: f64, y: f64| 2.0 + 0.3 * x - 0.1 * y;
+        let obs_pts = (0..30)
+            .map(|i| {
+                     let (x, y) = ((i * 7 % 30) as f64 * 3.0, (i * 11 % 30) as f64 * 3.0);
+                     Pt::new(x, y, plane(x, y))
+                 })
+            .collect::<Vec<_>>();
+        // A thin plate spline with a linear tail reproduces a plane
+        let rbf = Rbf::augmented(&obs_pts, "thin_plate", None, Polynomial::Linear, 0.0).unwrap();
+        for &(x, y) in &[(12.5, 40.0), (80.0, 3.0), (45.0, 45.0)] {
+            assert!((rbf.interp_point((x, y)) - plane(x, y)).abs() < 1e-8);
+        }
+        // Smoothing noisy values brings the surface back towards the plane
+        let noisy = obs_pts
+            .iter()
+            .enumerate()
+            .map(|(i, pt)| {
+                     let (x, y, val) = pt.get_triplet();
+                     Pt::new(x, y, val + if i % 2 == 0 { 1.0 } else { -1.0 })
+                 })
+            .collect::<Vec<_>>();
+        let exact = Rbf::augmented(&noisy, "thin_plate", None, Polynomial::Linear, 0.0).unwrap();
+        let smooth = Rbf::augmented(&noisy, "thin_plate", None, Polynomial::Linear, 1e4).unwrap();
+        let error = |rbf: &Rbf<Pt>| {
+            obs_pts
+                .iter()
+                .map(|pt| {
+                         let (x, y, val) = pt.get_triplet();
+                         (rbf.interp_point((x, y)) - val).abs()
+                     })
+                .sum::<f64>()
+        };
+        assert!((error(&exact) - 30.0).abs() < 1e-6);
+        assert!(error(&smooth) < 5.0);
+        assert!(Rbf::augmented(&obs_pts[..2], "cubic", None, Polynomial::Linear, 0.0).is_err());
+        assert!(Rbf::augmented(&obs_pts, "linear", None, Polynomial::None, -1.0).is_err());
+    }
+
+    #[test]
+    fn test_radial_basis_func_scipy() {
+        // Values of scipy's RBFInterpolator(xy, values, kernel=..., epsilon=...,
+        // degree=..., smoothing=...) at (12, 12) and (50, 50)
+        let obs_pts = [(0.0, 0.0, 0.0), (0.0, 100.0, 6.0), (75.0, 25.0, 3.1), (100.0, 75.0, 7.4),
+                       (30.0, 60.0, 2.2), (60.0, 10.0, 1.5), (90.0, 40.0, 5.0), (20.0, 90.0, 4.4)]
+            .iter()
+            .map(|&(x, y, val)| Pt::new(x, y, val))
+            .collect::<Vec<_>>();
+        let cases = [("thin_plate_spline", None, Polynomial::Linear, 0.0, 0.4123929403,
+                      2.6179534052),
+                     ("thin_plate_spline", Some(0.1), Polynomial::Linear, 5.0, 0.4146521270,
+                      2.7515958041),
+                     ("gaussian", Some(0.02), Polynomial::Constant, 0.1, 0.1198125582,
+                      2.5401692481),
+                     ("multiquadric", Some(0.05), Polynomial::Linear, 0.5, 0.4933932090,
+                      2.9913214134),
+                     ("cubic", Some(0.01), Polynomial::Linear, 1.0, 0.3836683938, 3.4895746735),
+                     ("linear", Some(0.5), Polynomial::Constant, 2.0, 0.5927635429,
+                      2.9712047446),
+                     ("quintic", Some(0.02), Polynomial::Quadratic, 0.3, 0.0689027925,
+                      2.1976367423),
+                     ("inverse_multiquadric", Some(0.03), Polynomial::None, 0.0, 0.3502909522,
+                      2.7346672477),
+                     ("inverse_quadratic", Some(0.03), Polynomial::Constant, 0.2, 1.0052612510,
+                      2.9150147896)];
+        for &(name, epsilon, polynomial, smooth, v1, v2) in &cases {
+            let rbf = Rbf::augmented(&obs_pts, name, epsilon, polynomial, smooth).unwrap();
+            assert!((rbf.interp_point((12.0, 12.0)) - v1).abs() < 1e-8, "{}", name);
+            assert!((rbf.interp_point((50.0, 50.0)) - v2).abs() < 1e-8, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_local_rbf() {
+        let obs_pts = (0..900)
+            .map(|i| {
+                     let (x, y) = ((i % 30) as f64 * 10.0, (i / 30) as f64 * 10.0);
+                     Pt::new(x + (i * 7 % 11) as f64 / 2.0,
+                             y + (i * 5 % 13) as f64 / 2.0,
+                             (x / 50.0).sin() * (y / 70.0).cos())
+                 })
+            .collect::<Vec<_>>();
+        let local = LocalRbf::new(&obs_pts, "thin_plate", None, Polynomial::Linear, 0.0, 60.0, 0.25)
+            .unwrap();
+        assert_eq!(local.nb_patches(), 25);
+        // Each patch interpolates its points, and so does their weighted mean
+        for pt in &obs_pts {
+            let (x, y, val) = pt.get_triplet();
+            assert!((local.interp_point((x, y)) - val).abs() < 1e-6);
+        }
+        let bbox = bbox::Bbox::new(0.0, 290.0, 0.0, 290.0);
+        let grid = local.interp_grid(&bbox, 31, 29).unwrap();
+        let coords = grid.iter().map(|pt| pt.get_coordinates()).collect::<Vec<_>>();
+        assert_eq!(local.interp_points(&coords),
+                   grid.iter().map(|pt| pt.get_value()).collect::<Vec<f64>>());
+        assert!(local.interp_point((1000.0, 1000.0)).is_nan());
+        assert!(LocalRbf::new(&obs_pts, "linear", None, Polynomial::None, 0.0, 60.0, 0.0).is_err());
+
+        // The patch of aligned points can't be solved with a linear polynomial: it
+        // falls back to a constant one instead of failing the whole grid
+        let mut pts = (0..10)
+            .map(|i| Pt::new(i as f64 * 5.0, 0.0, i as f64))
+            .collect::<Vec<_>>();
+        assert!(Rbf::augmented(&pts, "linear", None, Polynomial::Linear, 0.0).is_err());
+        pts.extend((0..16).map(|i| Pt::new(200.0 + (i % 4) as f64 * 10.0,
+                                           200.0 + (i / 4) as f64 * 10.0,
+                                           i as f64)));
+        let local = LocalRbf::new(&pts, "linear", None, Polynomial::Linear, 0.0, 100.0, 0.25)
+            .unwrap();
+        assert!(local.nb_patches() >= 2);
+        for pt in &pts {
+            let (x, y, val) = pt.get_triplet();
+            assert!((local.interp_point((x, y)) - val).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_parse_csv_points_with_options() {
+        let options = utils::CsvOptions {
+            x_column: utils::CsvColumn::from("lon"),
+            y_column: utils::CsvColumn::from("lat"),
+            value_column: utils::CsvColumn::from("pop"),
+            delimiter: b';',
+            decimal_separator: ',',
+            skip_missing: true,
+            ..Default::default()
+        };
+        let (pts, report) =
+            utils::parse_csv_points_with::<Pt>("examples/stocks_semicolon.csv", &options).unwrap();
+        assert_eq!(2, pts.len());
+        assert_eq!((5.0, 2.5, 42.5), pts[1].get_triplet());
+        assert_eq!(1, report.nb_skipped());
+        assert_eq!(3, report.skipped_rows[0].0);
+
+        // Rows are reported by the line they start on, past quoted line breaks,
+        // and invalid numbers are skipped along with the missing values
+        let path = ::std::env::temp_dir().join("smoomars_test_multiline.csv");
+        let path = path.to_str().unwrap();
+        {
+            use std::io::Write;
+            let mut file = ::std::fs::File::create(path).unwrap();
+            file.write_all(b"x,y,value,note\n1,1,5,\"first\nsecond\"\n2,two,6,c\n3,3,,d\n4,4,7,e\n")
+                .unwrap();
+        }
+        let options = utils::CsvOptions { skip_missing: true, ..Default::default() };
+        let (pts, report) = utils::parse_csv_points_with::<Pt>(path, &options).unwrap();
+        assert_eq!(vec![(1.0, 1.0, 5.0), (4.0, 4.0, 7.0)],
+                   pts.iter().map(|pt| pt.get_triplet()).collect::<Vec<_>>());
+        assert_eq!(vec![4, 5],
+                   report.skipped_rows.iter().map(|row| row.0).collect::<Vec<usize>>());
+        assert!(report.skipped_rows[0].1.contains("invalid y value 'two'"));
+        let err = utils::parse_csv_points_with::<Pt>(path, &utils::CsvOptions::default())
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Line 4: invalid y value"));
+    }
+
+    #[test]
+    fn test_save_csv_points_roundtrip() {
+        let pts = vec![Pt::new(0.0, 0.0, 1.25), Pt::new(1.0, 0.0, f64::NAN), Pt::new(0.0, 1.0, 3.5)];
+        let path = ::std::env::temp_dir().join("smoomars_test_points.csv");
+        let path = path.to_str().unwrap();
+        let options = utils::CsvWriteOptions {
+            precision: Some(2),
+            include_nodata: false,
+            ..Default::default()
+        };
+        utils::save_csv_points(path, &pts, &options).unwrap();
+        let read_pts = utils::parse_csv_points::<Pt>(path).unwrap();
+        assert_eq!(2, read_pts.len());
+        assert_eq!((0.0, 1.0, 3.5), read_pts[1].get_triplet());
+
+        // The precision only applies to the values, and `nodata` is left out too
+        let pts = vec![Pt::new(0.123456789, 45.987654321, 1.23456),
+                       Pt::new(1.5, 2.5, -9999.0)];
+        let options = utils::CsvWriteOptions {
+            precision: Some(2),
+            include_nodata: false,
+            nodata: Some(-9999.0),
+            ..Default::default()
+        };
+        utils::save_csv_points(path, &pts, &options).unwrap();
+        let read_pts = utils::parse_csv_points::<Pt>(path).unwrap();
+        assert_eq!(1, read_pts.len());
+        assert_eq!((0.123456789, 45.987654321, 1.23), read_pts[0].get_triplet());
+        let grid = vec![Pt::new(0.125, 0.5, 1.0 / 3.0), Pt::new(0.125, 1.5, -9999.0)];
+        utils::save_csv_grid(path, &grid, (1, 2), &options).unwrap();
+        let mut content = String::new();
+        {
+            use std::io::Read;
+            ::std::fs::File::open(path).unwrap().read_to_string(&mut content).unwrap();
+        }
+        assert_eq!("y/x,0.125\n1.5,\n0.5,0.33\n", content.replace("\r\n", "\n"));
+    }
+
+    #[test]
+    fn test_geojsonseq_roundtrip() {
+        let pts = vec![utils::SphericalPtValue::new(2.35, 48.85, 10.0),
+                       utils::SphericalPtValue::new(4.83, 45.76, 5.0)];
+        let path = ::std::env::temp_dir().join("smoomars_test_points.geojsonl");
+        let path = path.to_str().unwrap();
+        stream::save_geojsonseq_points(path, &pts).unwrap();
+        let read_pts = stream::GeoJsonSeqReader::<utils::SphericalPtValue>::open(path, "value")
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(2, read_pts.len());
+        assert_eq!((4.83, 45.76, 5.0), read_pts[1].get_triplet());
+    }
+
+    #[test]
+    fn test_stream_geojson_points() {
+        // Same points and report as the reader parsing the whole document
+        let path = "examples/input_ra.geojson";
+        let options = utils::GeoJsonOptions::default();
+        let (pts, report) = utils::parse_geojson_points_with::<Pt>(path, "value", &options)
+            .unwrap();
+        let mut streamed = Vec::new();
+        let streamed_report =
+            stream::stream_geojson_points_with(path, "value", &options, |pt: Pt| {
+                streamed.push(pt.get_triplet())
+            })
+                    .unwrap();
+        assert!(pts.len() > 0);
+        assert_eq!(pts.iter().map(|pt| pt.get_triplet()).collect::<Vec<_>>(), streamed);
+        assert_eq!(report.nb_points, streamed_report.nb_points);
+        assert_eq!(report.skipped_features, streamed_report.skipped_features);
+
+        // A truncated collection is an error, not a shorter list of points
+        let content = {
+            use std::io::Read;
+            let mut content = String::new();
+            ::std::fs::File::open(path).unwrap().read_to_string(&mut content).unwrap();
+            content
+        };
+        let truncated = ::std::env::temp_dir().join("smoomars_test_truncated.geojson");
+        let truncated = truncated.to_str().unwrap();
+        {
+            use std::io::Write;
+            let mut file = ::std::fs::File::create(truncated).unwrap();
+            file.write_all(content[..content.len() / 2].as_bytes()).unwrap();
+        }
+        assert!(stream::stream_geojson_points(truncated, "value", |_: Pt| ()).is_err());
+        assert!(utils::parse_geojson_points::<Pt>(truncated, "value").is_err());
+    }
+
+    #[test]
+    fn test_polygon_centroid_and_point_on_surface() {
+        let square = vec![vec![vec![0.0, 0.0], vec![2.0, 0.0], vec![2.0, 2.0], vec![0.0, 2.0],
+                               vec![0.0, 0.0]]];
+        let (x, y, area) = geometry::polygon_centroid(&square).unwrap();
+        assert_eq!((1.0, 1.0, 4.0), (x, y, area));
+        // U-shaped polygon, whose centroid lies outside of it
+        let u_shape = vec![vec![vec![0.0, 0.0], vec![3.0, 0.0], vec![3.0, 3.0], vec![2.0, 3.0],
+                                vec![2.0, 1.0], vec![1.0, 1.0], vec![1.0, 3.0], vec![0.0, 3.0],
+                                vec![0.0, 0.0]]];
+        let (x, y) = geometry::polygon_point_on_surface(&u_shape).unwrap();
+        assert!((x < 1.0 || x > 2.0) && y > 1.0 && y < 3.0);
+
+        // Polygons with a position lacking a coordinate are skipped and reported
+        let path = ::std::env::temp_dir().join("smoomars_test_invalid_positions.geojson");
+        let path = path.to_str().unwrap();
+        {
+            use std::io::Write;
+            let mut file = ::std::fs::File::create(path).unwrap();
+            file.write_all(br#"{"type": "FeatureCollection", "features": [
+                {"type": "Feature", "properties": {"pop": 1}, "geometry": {"type": "Polygon",
+                 "coordinates": [[[0, 0], [2, 0], [2], [0, 2], [0, 0]]]}},
+                {"type": "Feature", "properties": {"pop": 2}, "geometry": {"type": "Point",
+                 "coordinates": [1, 1]}},
+                {"type": "Feature", "properties": {"pop": 3}, "geometry": {"type": "MultiPolygon",
+                 "coordinates": [[[[0, 0], [2, 0], [2, 2], [0, 0]]], [[[5]]]]}}]}"#)
+                .unwrap();
+        }
+        for &repr in &[geometry::PolygonRepresentation::Centroid,
+                       geometry::PolygonRepresentation::PointOnSurface] {
+            let options = utils::GeoJsonOptions {
+                polygon_representation: repr,
+                ..Default::default()
+            };
+            let (pts, report) = utils::parse_geojson_points_with::<Pt>(path, "pop", &options)
+                .unwrap();
+            assert_eq!(vec![(1.0, 1.0, 2.0)],
+                       pts.iter().map(|pt| pt.get_triplet()).collect::<Vec<_>>());
+            assert_eq!(report.skipped_features,
+                       vec![(0, "invalid position".to_string()),
+                            (2, "invalid position".to_string())]);
+        }
+    }
+
+    #[test]
+    fn test_errors_instead_of_panics() {
+        let obs_pts = vec![Pt::new(0.0, 0.0, 0.0), Pt::new(0.0, 100.0, 6.0)];
+        match Rbf::new(&obs_pts, "unknown", None) {
+            Err(Error(ErrorKind::UnknownMethod(ref name), _)) => assert_eq!("unknown", name),
+            _ => panic!("expected an UnknownMethod error"),
+        }
+        let duplicated = vec![Pt::new(0.0, 0.0, 1.0), Pt::new(0.0, 0.0, 1.0)];
+        match Rbf::new(&duplicated, "linear", Some(1.0)) {
+            Err(Error(ErrorKind::SingularSystem, _)) => (),
+            _ => panic!("expected a SingularSystem error"),
+        }
+        let bbox = bbox::Bbox::new(1.0, 1.0, 32.0, 35.0);
+        match idw_interpolation(10, 10, &bbox, &obs_pts, 2.0) {
+            Err(Error(ErrorKind::InvalidBbox(..), _)) => (),
+            _ => panic!("expected an InvalidBbox error"),
+        }
+        let empty: Vec<Pt> = Vec::new();
+        let bbox = bbox::Bbox::new(1.0, 4.0, 32.0, 35.0);
+        match stewart_interpolation(10, 10, &bbox, &empty, "pareto", 10.0, None) {
+            Err(Error(ErrorKind::EmptyInput, _)) => (),
+            _ => panic!("expected an EmptyInput error"),
+        }
+    }
+
+    #[test]
+    fn test_stewart_fft_engine() {
+        let bbox = bbox::Bbox::new(0.0, 200.0, 0.0, 100.0);
+        let (reso_x, reso_y) = (40, 20);
+        let on_nodes = vec![Pt::new(0.0, 0.0, 10.0),
+                            Pt::new(50.0, 25.0, 30.0),
+                            Pt::new(-20.0, 60.0, 5.0),
+                            Pt::new(150.0, 95.0, 12.0)];
+        let off_nodes = vec![Pt::new(1.3, 2.2, 10.0),
+                             Pt::new(51.7, 23.9, 30.0),
+                             Pt::new(-18.4, 61.1, 5.0),
+                             Pt::new(148.2, 97.5, 12.0)];
+        for &(ref obs_points, tolerance) in &[(on_nodes, 1e-9), (off_nodes, 1e-2)] {
+            let conf = StewartPotentialGrid::new(40.0, 2.0, SmoothType::Exponential, &bbox,
+                                                 reso_x, reso_y, 1);
+            let exact = stewart(&conf.clone().with_engine(StewartEngine::Exact), obs_points)
+                .unwrap();
+            let fft = stewart(&conf.with_engine(StewartEngine::Fft), obs_points).unwrap();
+            let max = exact.iter().fold(0.0, |m: f64, pt| m.max(pt.get_value()));
+            for (a, b) in exact.iter().zip(fft.iter()) {
+                assert_eq!(a.get_coordinates(), b.get_coordinates());
+                assert!((a.get_value() - b.get_value()).abs() <= tolerance * max);
+            }
+        }
+        let spherical = vec![utils::SphericalPtValue::new(1.0, 1.0, 1.0)];
+        let conf = StewartPotentialGrid::new(40.0, 2.0, SmoothType::Exponential, &bbox, 4, 4, 1)
+            .with_engine(StewartEngine::Fft);
+        assert!(stewart(&conf, &spherical).is_err());
+
+        // Auto only takes the FFT engine within its tolerance, with beta >= 1
+        let obs_points = (0..420)
+            .map(|i| Pt::new((i * 37 % 419) as f64 * 0.47 + 0.3, (i * 53 % 211) as f64 * 0.46, 1.0))
+            .collect::<Vec<_>>();
+        let (reso_x, reso_y) = (200, 200);
+        let bbox = bbox::Bbox::new(0.0, 200.0, 0.0, 100.0);
+        let conf = |beta, engine| {
+            StewartPotentialGrid::new(10.0, beta, SmoothType::Pareto, &bbox, reso_x, reso_y, 2)
+                .with_engine(engine)
+        };
+        let fft = stewart(&conf(2.0, StewartEngine::Fft), &obs_points).unwrap();
+        let auto = stewart(&conf(2.0, StewartEngine::Auto), &obs_points).unwrap();
+        assert!(fft.iter().zip(auto.iter()).all(|(a, b)| a.get_value() == b.get_value()));
+        let exact = stewart(&conf(0.5, StewartEngine::Exact), &obs_points).unwrap();
+        let auto = stewart(&conf(0.5, StewartEngine::Auto), &obs_points).unwrap();
+        for (a, b) in exact.iter().zip(auto.iter()) {
+            assert_eq!(a.get_triplet(), b.get_triplet());
+        }
+    }
+
+    #[test]
+    fn test_stewart_truncated() {
+        let obs_points = vec![Pt::new(3.0, 4.0, 10.0),
+                              Pt::new(52.0, 21.0, 30.0),
+                              Pt::new(-18.0, 61.0, 5.0),
+                              Pt::new(148.0, 97.0, 12.0)];
+        let bbox = bbox::Bbox::new(0.0, 200.0, 0.0, 100.0);
+        let conf = StewartPotentialGrid::new(20.0, 2.0, SmoothType::Exponential, &bbox, 40, 20, 1)
+            .with_engine(StewartEngine::Exact);
+        let exact = stewart(&conf, &obs_points).unwrap();
+        let (full, report) = stewart_truncated(&conf, &obs_points, Cutoff::Distance(1e6)).unwrap();
+        assert_eq!(0.0, report.sampled_max_dropped);
+        for (a, b) in exact.iter().zip(full.iter()) {
+            assert!(utils::almost_equal(a.get_value(), b.get_value(), 1e-9));
+        }
+        let (truncated, report) = stewart_truncated(&conf, &obs_points, Cutoff::Weight(1e-3))
+            .unwrap();
+        assert!(utils::almost_equal(20.0 * (1e3f64.ln() / 2f64.ln()).sqrt(),
+                                    report.cutoff_distance,
+                                    1e-9));
+        for (a, b) in exact.iter().zip(truncated.iter()) {
+            assert!(b.get_value() <= a.get_value() * (1.0 + 1e-12));
+            assert!(a.get_value() - b.get_value() <= report.dropped_bound);
+        }
+    }
+
+    #[test]
+    fn test_tiled_equal_in_memory() {
+        let obs_pts = vec![Pt::new(0.0, 0.0, 0.0),
+                           Pt::new(0.0, 100.0, 6.0),
+                           Pt::new(75.0, 25.0, 3.1),
+                           Pt::new(100.0, 75.0, 7.4)];
+        let bbox = bbox::Bbox::new(0.0, 100.0, 0.0, 100.0);
+        let (reso_x, reso_y) = (11, 7);
+        let grid = idw_interpolation(reso_x, reso_y, &bbox, &obs_pts, 2.0).unwrap();
+        let mut nb_cells = 0;
+        idw_tiled(reso_x,
+                  reso_y,
+                  &bbox,
+                  &obs_pts,
+                  2.0,
+                  4,
+                  &mut |tile: tiled::Tile| {
+            assert!(tile.width <= 4 && tile.height <= 4);
+            for (k, &value) in tile.values.iter().enumerate() {
+                let i = tile.col_off + k % tile.width;
+                let j = reso_y - 1 - (tile.row_off + k / tile.width);
+                assert_eq!(grid[i * reso_y + j].get_value(), value);
+                nb_cells += 1;
+            }
+            Ok(())
+        })
+                .unwrap();
+        assert_eq!(reso_x * reso_y, nb_cells);
+
+        // Stewart potentials, by the engines able to compute them by tiles
+        let compare = |grid: &[Pt], tile: tiled::Tile| -> Result<()> {
+            for (k, &value) in tile.values.iter().enumerate() {
+                let i = tile.col_off + k % tile.width;
+                let j = reso_y - 1 - (tile.row_off + k / tile.width);
+                assert_eq!(grid[i * reso_y + j].get_value(), value);
+            }
+            Ok(())
+        };
+        let conf = StewartPotentialGrid::new(30.0, 2.0, SmoothType::Exponential, &bbox, reso_x,
+                                             reso_y, 2);
+        let tree = StewartEngine::BarnesHut(0.5);
+        for &(tiled_engine, engine) in &[(StewartEngine::Auto, StewartEngine::Exact),
+                                         (StewartEngine::Exact, StewartEngine::Exact),
+                                         (tree, tree)] {
+            let expected = stewart(&conf.clone().with_engine(engine), &obs_pts).unwrap();
+            stewart_tiled(&conf.clone().with_engine(tiled_engine),
+                          &obs_pts,
+                          3,
+                          &mut |tile: tiled::Tile| compare(&expected, tile))
+                    .unwrap();
+        }
+        let mut no_tile = |tile: tiled::Tile| compare(&[], tile);
+        let conf = conf.with_engine(StewartEngine::Fft);
+        assert!(stewart_tiled(&conf, &obs_pts, 3, &mut no_tile).is_err());
+    }
+
+    #[test]
+    fn test_raster_round_trip() {
+        use gdal::raster::Dataset;
+        let obs_pts = vec![Pt::new(0.0, 0.0, 0.0),
+                           Pt::new(0.0, 100.0, 6.0),
+                           Pt::new(75.0, 25.0, 3.1),
+                           Pt::new(100.0, 75.0, 7.4)];
+        let bbox = bbox::Bbox::new(0.0, 100.0, 0.0, 70.0);
+        let (reso_x, reso_y) = (10, 7);
+        let grid = idw_interpolation(reso_x, reso_y, &bbox, &obs_pts, 2.0).unwrap();
+        let dir = ::std::env::temp_dir();
+        let path = dir.join("smoomars_test_in_memory.geotiff");
+        let path = path.to_str().unwrap();
+        let tiled_path = dir.join("smoomars_test_tiled.geotiff");
+        let tiled_path = tiled_path.to_str().unwrap();
+        utils::write_to_raster(grid.clone(), &bbox, (reso_x, reso_y), path).unwrap();
+        {
+            let mut writer = tiled::RasterTileWriter::create(tiled_path, &bbox, reso_x, reso_y)
+                .unwrap();
+            idw_tiled(reso_x, reso_y, &bbox, &obs_pts, 2.0, 3, &mut writer).unwrap();
+        }
+        let read = |path: &str| {
+            let dataset = Dataset::open(::std::path::Path::new(path)).unwrap();
+            assert_eq!(dataset.size(), (reso_x, reso_y));
+            assert_eq!(dataset.geo_transform().unwrap(),
+                       [0.0, 10.0, 0.0, 70.0, 0.0, -10.0]);
+            dataset.read_full_raster_as::<f64>(1).unwrap().data
+        };
+        let (values, tiled_values) = (read(path), read(tiled_path));
+        assert_eq!(values, tiled_values);
+        // Row by row from the top, the lower-left cell lying on the first point
+        for row in 0..reso_y {
+            for i in 0..reso_x {
+                assert_eq!(values[row * reso_x + i],
+                           grid[i * reso_y + reso_y - 1 - row].get_value());
+            }
+        }
+        assert_eq!(values[(reso_y - 1) * reso_x], 0.0);
+
+        let spec = GridSpec::from_template(path).unwrap();
+        assert_eq!(spec.extent, bbox);
+        assert_eq!((spec.reso_x, spec.reso_y), (reso_x, reso_y));
+    }
+
+    #[test]
+    fn test_stewart_barnes_hut() {
+        let mut seed: u64 = 7;
+        let mut rnd = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let obs_points = (0..500)
+            .map(|_| utils::SphericalPtValue::new(2.0 + 6.0 * rnd(), 43.0 + 5.0 * rnd(), rnd()))
+            .collect::<Vec<_>>();
+        let bbox = bbox::Bbox::new(2.0, 8.0, 43.0, 48.0);
+        let conf = StewartPotentialGrid::new(50000.0, 2.0, SmoothType::Exponential, &bbox, 20, 20, 1);
+        let exact = stewart(&conf.clone().with_engine(StewartEngine::Exact), &obs_points).unwrap();
+        let tree_exact = stewart(&conf.clone().with_engine(StewartEngine::BarnesHut(0.0)),
+                                 &obs_points)
+                .unwrap();
+        let approx = stewart(&conf.with_engine(StewartEngine::BarnesHut(0.2)), &obs_points)
+            .unwrap();
+        let max_pot = exact.iter().fold(0.0f64, |m, pt| m.max(pt.get_value()));
+        for i in 0..exact.len() {
+            assert!((exact[i].get_value() - tree_exact[i].get_value()).abs() <= 1e-12 * max_pot);
+            assert!((exact[i].get_value() - approx[i].get_value()).abs() <= 0.01 * max_pot);
+        }
+        let bad_conf = StewartPotentialGrid::new(50000.0, 2.0, SmoothType::Exponential, &bbox, 20, 20, 1)
+            .with_engine(StewartEngine::BarnesHut(-1.0));
+        assert!(stewart(&bad_conf, &obs_points).is_err());
+    }
+
+    #[test]
+    fn test_interaction_matrix() {
+        let obs_pts = (0..50)
+            .map(|i| Pt::new((i * 7 % 50) as f64, (i * 13 % 50) as f64, i as f64))
+            .collect::<Vec<_>>();
+        let bbox = bbox::Bbox::new(0.0, 50.0, 0.0, 50.0);
+        let conf = StewartPotentialGrid::new(5.0, 2.0, SmoothType::Exponential, &bbox, 15, 12, 1)
+            .with_engine(StewartEngine::Exact);
+        let matrix = stewart_interaction_matrix(&conf, &obs_pts, None).unwrap();
+        let values = obs_pts.iter().map(|pt| pt.get_value()).collect::<Vec<f64>>();
+        let res: Vec<Pt> = matrix.apply_grid(&values).unwrap();
+        let exact = stewart(&conf, &obs_pts).unwrap();
+        for (a, b) in exact.iter().zip(res.iter()) {
+            assert_eq!(a.get_coordinates(), b.get_coordinates());
+            assert!((a.get_value() - b.get_value()).abs() <= 1e-12 * a.get_value().abs());
+        }
+        let path = ::std::env::temp_dir().join("smoomars_test_matrix.bin");
+        let path = path.to_str().unwrap();
+        matrix.save(path).unwrap();
+        let loaded = InteractionMatrix::load(path).unwrap();
+        assert_eq!(matrix, loaded);
+        let doubled = values.iter().map(|v| 2.0 * v).collect::<Vec<f64>>();
+        let res2 = loaded.apply(&doubled).unwrap();
+        for (a, b) in res.iter().zip(res2.iter()) {
+            assert_eq!(2.0 * a.get_value(), *b);
+        }
+        assert!(matrix.apply(&values[1..]).is_err());
+
+        let cutoff = Cutoff::Distance(10.0);
+        let sparse = stewart_interaction_matrix(&conf, &obs_pts, Some(cutoff)).unwrap();
+        assert!(sparse.nb_weights() < matrix.nb_weights());
+        let (truncated, _) = stewart_truncated(&conf, &obs_pts, cutoff).unwrap();
+        let res3 = sparse.apply(&values).unwrap();
+        for (a, b) in truncated.iter().zip(res3.iter()) {
+            assert!((a.get_value() - b).abs() <= 1e-12 * a.get_value().abs().max(1.0));
+        }
+        sparse.save(path).unwrap();
+        assert_eq!(sparse, InteractionMatrix::load(path).unwrap());
+
+        // Sizes read from a truncated or corrupted file are rejected, not allocated
+        let bytes = {
+            use std::io::Read;
+            let mut bytes = Vec::new();
+            ::std::fs::File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+            bytes
+        };
+        let write = |bytes: &[u8]| {
+            use std::io::Write;
+            ::std::fs::File::create(path).unwrap().write_all(bytes).unwrap();
+        };
+        write(&bytes[..bytes.len() - 8]);
+        assert!(InteractionMatrix::load(path).is_err());
+        let mut huge = bytes.clone();
+        huge[8..16].copy_from_slice(&(1u64 << 60).to_le_bytes());
+        write(&huge);
+        assert!(InteractionMatrix::load(path).is_err());
+        write(&bytes);
+        assert_eq!(sparse, InteractionMatrix::load(path).unwrap());
+    }
+
+    #[test]
+    fn test_batched_kernels_equal_scalar() {
+        let obs_points = (0..37)
+            .map(|i| {
+                     utils::SphericalPtValue::new(2.0 + (i * 7 % 37) as f64 / 10.0,
+                                                  45.0 + (i * 11 % 37) as f64 / 10.0,
+                                                  i as f64)
+                 })
+            .collect::<Vec<_>>();
+        let bbox = bbox::Bbox::new(2.0, 6.0, 45.0, 49.0);
+        let conf = StewartPotentialGrid::new(80000.0, 2.0, SmoothType::Pareto, &bbox, 7, 5, 1)
+            .with_engine(StewartEngine::Exact);
+        let res = stewart(&conf, &obs_points).unwrap();
+        let alpha = (2f64.powf(1.0 / 2.0) - 1.0) / 80000.0;
+        for cell in &res {
+            let expected = obs_points
+                .iter()
+                .fold(0.0, |sum, pt| {
+                    let (x, y, val) = pt.get_triplet();
+                    sum + val * (1.0 + alpha * cell.distance(x, y)).powf(-2.0)
+                });
+            assert!((expected - cell.get_value()).abs() <= 1e-12 * expected);
+        }
+
+        // Vector euclidean distances, on a number of cells which isn't a
+        // multiple of the number of lanes (none of them lying on a point)
+        let obs_pts = obs_points
+            .iter()
+            .map(|pt| Pt::new(pt.lon * 1000.0, pt.lat * 1000.0, pt.value))
+            .collect::<Vec<_>>();
+        let bbox = bbox::Bbox::new(2010.0, 6010.0, 45010.0, 49010.0);
+        let conf = StewartPotentialGrid::new(500.0, 1.5, SmoothType::Exponential, &bbox, 7, 5, 1)
+            .with_engine(StewartEngine::Exact);
+        let res = stewart(&conf, &obs_pts).unwrap();
+        let idw = idw_interpolation(7, 5, &bbox, &obs_pts, 1.5).unwrap();
+        let alpha = 0.69314718055994529 / 500f64.powf(1.5);
+        for (cell, idw_cell) in res.iter().zip(idw.iter()) {
+            let (mut expected, mut zw, mut sw) = (0.0, 0.0, 0.0);
+            for pt in &obs_pts {
+                let (x, y, val) = pt.get_triplet();
+                let dist = cell.distance(x, y);
+                expected += val * (-alpha * dist.powf(1.5)).exp();
+                zw += val / dist.powf(1.5);
+                sw += 1.0 / dist.powf(1.5);
+            }
+            assert!((expected - cell.get_value()).abs() <= 1e-12 * expected);
+            assert!((zw / sw - idw_cell.get_value()).abs() <= 1e-12 * zw / sw);
+        }
+    }
+
+    #[test]
+    fn test_projections() {
+        // Examples of the EPSG Guidance Note 7-2, and origin of Lambert-93
+        let references = [(3857, -100.333333333333, 24.381787, -11169055.58, 2800000.00),
+                          (3035, 5.0, 50.0, 3962799.45, 2999718.85),
+                          (2154, 3.0, 46.5, 700000.0, 6600000.0),
+                          (32631, 3.0, 0.0, 500000.0, 0.0)];
+        for &(code, lon, lat, x, y) in &references {
+            let crs = projection::Crs::from_epsg(code).unwrap();
+            let (px, py) = crs.forward(lon, lat);
+            assert!((px - x).abs() < 0.05 && (py - y).abs() < 0.05);
+        }
+        for code in &["EPSG:2154", "3034", "3035", "5070", "32633", "32733", "3857"] {
+            let crs = code.parse::<Crs>().unwrap();
+            let (x, y) = crs.forward(12.5, 41.9);
+            let (lon, lat) = crs.inverse(x, y);
+            assert!((lon - 12.5).abs() < 1e-7 && (lat - 41.9).abs() < 1e-7);
+        }
+        let pts = vec![utils::SphericalPtValue::new(2.3522, 48.8566, 7.0)];
+        let projected: Vec<Pt> = projection::reproject(&pts,
+                                                       &Crs::from_epsg(4326).unwrap(),
+                                                       &Crs::from_epsg(2154).unwrap());
+        let (x, y, value) = projected[0].get_triplet();
+        assert!((x - 652469.0).abs() < 1.0 && (y - 6862035.0).abs() < 1.0 && value == 7.0);
+        assert!(Crs::from_epsg(1234).is_err());
+
+        // CSV outputs get their CRS in a .prj file next to them
+        let path = ::std::env::temp_dir().join("smoomars_test_crs.csv");
+        let path = path.to_str().unwrap();
+        utils::save_csv_points(path, &projected, &utils::CsvWriteOptions::default()).unwrap();
+        utils::save_prj(path, &Crs::from_epsg(2154).unwrap()).unwrap();
+        assert_eq!(utils::prj_path(path), ::std::env::temp_dir().join("smoomars_test_crs.prj"));
+        let wkt = {
+            use std::io::Read;
+            let mut wkt = String::new();
+            ::std::fs::File::open(utils::prj_path(path))
+                .unwrap()
+                .read_to_string(&mut wkt)
+                .unwrap();
+            wkt
+        };
+        assert!(wkt.contains("Lambert") && wkt.contains("2154"));
+    }
+
+    #[test]
+    fn test_geodesic_distance() {
+        type Geo = utils::GeodesicPtValue;
+        // JFK to LHR, a nearly antipodal pair (Karney, 2013) and a quarter meridian
+        let references = [(-73.8, 40.6, -0.5, 51.6, 5551759.400319),
+                          (0.0, -30.0, 179.8, 29.9, 19989832.827610),
+                          (0.0, 0.0, 0.0, 90.0, 10001965.729312)];
+        for &(lon1, lat1, lon2, lat2, dist) in &references {
+            assert!((Geo::distance_between(lon1, lat1, lon2, lat2) - dist).abs() < 1e-5);
+            assert!((Geo::distance_between(lon2, lat2, lon1, lat1) - dist).abs() < 1e-5);
+        }
+        for i in 0..200 {
+            let (lon1, lat1) = ((i * 37 % 360) as f64 - 180.0, (i * 13 % 170) as f64 - 85.0);
+            let (lon2, lat2) = (lon1 + (i % 11) as f64 * 0.7, lat1 + (i % 7) as f64 * 0.6 - 2.0);
+            let dist = Geo::distance_between(lon1, lat1, lon2, lat2);
+            let spherical = utils::SphericalPtValue::distance_between(lon1, lat1, lon2, lat2);
+            assert!((dist - spherical).abs() <= 0.006 * dist + 1e-9);
+            let window = Geo::search_window(lon1, lat1, dist);
+            assert!(window.min_x <= lon2.min(180.0) && lon2.min(180.0) <= window.max_x);
+            assert!(window.min_y <= lat2 && lat2 <= window.max_y);
+        }
+    }
+
+    #[test]
+    fn test_distance_metrics() {
+        assert_eq!(Euclidean.distance(0.0, 0.0, 3.0, 4.0), 5.0);
+        assert_eq!(Manhattan.distance(0.0, 0.0, 3.0, -4.0), 7.0);
+        assert_eq!(Chebyshev.distance(0.0, 0.0, 3.0, -4.0), 4.0);
+        let minkowski = Minkowski::new(3.0).unwrap();
+        assert!((minkowski.distance(0.0, 0.0, 3.0, 4.0) - 91f64.cbrt()).abs() < 1e-12);
+        assert!(Minkowski::new(0.5).is_err() && Minkowski::new(f64::INFINITY).is_err());
+        // Nashville to Los Angeles on the sphere of radius 6372.8 km, and half the equator
+        assert!((Haversine.distance(-86.67, 36.12, -118.40, 33.94) - 2887259.95).abs() < 0.01);
+        assert!((Haversine.distance(0.0, 0.0, 180.0, 0.0) - f64::consts::PI * 6372800.0).abs() <
+                1e-6);
+        assert_eq!(Haversine.distance(2.35, 48.85, 2.35, 48.85), 0.0);
+
+        let obs_pts = vec![Pt::new(0.0, 0.0, 0.0),
+                           Pt::new(0.0, 100.0, 6.0),
+                           Pt::new(75.0, 25.0, 3.1),
+                           Pt::new(100.0, 75.0, 7.4)];
+        let bbox = bbox::Bbox::new(0.0, 100.0, 0.0, 100.0);
+        let triplets = |grid: Vec<Pt>| grid.iter().map(|pt| pt.get_triplet()).collect::<Vec<_>>();
+        assert_eq!(triplets(idw_interpolation(12, 9, &bbox, &obs_pts, 2.0).unwrap()),
+                   triplets(idw_interpolation_with_metric(12, 9, &bbox, &obs_pts, 2.0, &Euclidean)
+                                .unwrap()));
+        let manhattan = |x1: f64, y1: f64, x2: f64, y2: f64| (x1 - x2).abs() + (y1 - y2).abs();
+        let res = triplets(stewart_interpolation_with_metric(12, 9, &bbox, &obs_pts, "pareto",
+                                                             30.0, None, &manhattan)
+                                   .unwrap());
+        assert_eq!(res,
+                   triplets(stewart_interpolation_with_metric(12, 9, &bbox, &obs_pts, "pareto",
+                                                              30.0, None, &Manhattan)
+                                    .unwrap()));
+        assert!(res !=
+                triplets(stewart_interpolation(12, 9, &bbox, &obs_pts, "pareto", 30.0, None)
+                             .unwrap()));
+
+        // Rbf follows the distance of the point type, here in metres
+        let spherical = vec![SphericalPtValue::new(2.0, 33.0, 1.0),
+                             SphericalPtValue::new(2.5, 33.4, 4.0),
+                             SphericalPtValue::new(3.1, 32.6, 2.0)];
+        let rbf = Rbf::new(&spherical, "gaussian", Some(2e-5)).unwrap();
+        let haversine = Rbf::with_metric(&spherical,
+                                         "gaussian",
+                                         Some(2e-5),
+                                         Polynomial::None,
+                                         0.0,
+                                         Haversine)
+                .unwrap();
+        assert_eq!(rbf.interp_point((2.4, 33.1)), haversine.interp_point((2.4, 33.1)));
+        assert!((rbf.interp_point((2.5, 33.4)) - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_network_distances() {
+        let path = ::std::env::temp_dir().join("smoomars_test_network.csv");
+        let path = path.to_str().unwrap();
+        {
+            use std::io::Write;
+            let mut file = ::std::fs::File::create(path).unwrap();
+            file.write_all(b"x1,y1,x2,y2,cost\n0,0,10,0,1\n10,0,10,10,1\n10,10,0,10,1\n\
+                             0,10,0,0,5\n50,50,60,50,1\n")
+                .unwrap();
+        }
+        let network = Network::from_csv(path).unwrap();
+        assert_eq!((6, 5), (network.nb_nodes(), network.nb_edges()));
+        let (origin, corner) = (network.nearest_node(-1.0, 0.5).unwrap(),
+                                network.nearest_node(1.0, 9.0).unwrap());
+        assert_eq!(network.shortest_paths(origin)[corner], 3.0);
+        assert!(Network::from_edges(&[((0.0, 0.0), (1.0, 0.0), -1.0)]).is_err());
+
+        // Distances from the nodes of the points, and between other locations
+        let obs_pts = vec![Pt::new(0.0, 0.0, 1.0), Pt::new(0.0, 10.0, 3.0)];
+        let metric = NetworkMetric::new(&network, &obs_pts);
+        assert_eq!(metric.distance(9.0, 1.0, 0.0, 10.0), 2.0);
+        assert_eq!(metric.distance(0.0, 10.0, 9.0, 1.0), 2.0);
+        assert_eq!(metric.distance(9.0, 1.0, 9.0, 9.0), 1.0);
+        assert_eq!(metric.distance(0.0, 0.0, 52.0, 50.0), f64::INFINITY);
+        let idw = idw::Idw::new(&obs_pts, 2.0, &metric);
+        assert!((idw.interp_point((9.0, 1.0)) - 1.4).abs() < 1e-12);
+        // Same distances with the locations snapped beforehand
+        let snapped = metric.clone().with_locations(&[(9.0, 1.0), (9.0, 9.0)]);
+        assert_eq!(snapped.distance(9.0, 1.0, 0.0, 10.0), 2.0);
+        assert_eq!(snapped.distance(9.0, 1.0, 9.0, 9.0), 1.0);
+
+        // Cost of a feature split along its segments
+        let path = ::std::env::temp_dir().join("smoomars_test_network.geojson");
+        let path = path.to_str().unwrap();
+        {
+            use std::io::Write;
+            let mut file = ::std::fs::File::create(path).unwrap();
+            file.write_all(br#"{"type": "FeatureCollection", "features": [{"type": "Feature",
+                "properties": {"time": 4}, "geometry": {"type": "LineString",
+                "coordinates": [[0, 0], [10, 0], [10, 30]]}}]}"#)
+                .unwrap();
+        }
+        let network = Network::from_geojson(path, Some("time"), &Euclidean).unwrap();
+        let (start, middle) = (network.nearest_node(0.0, 0.0).unwrap(),
+                               network.nearest_node(10.0, 0.0).unwrap());
+        assert_eq!(network.shortest_paths(start)[middle], 1.0);
+        let network = Network::from_geojson(path, None, &Euclidean).unwrap();
+        assert_eq!(network.shortest_paths(start)[middle], 10.0);
+        assert!(Network::from_geojson(path, Some("speed"), &Euclidean).is_err());
+    }
+
+    #[test]
+    fn test_cost_matrix_input() {
+        let obs_pts = vec![Pt::new(0.0, 0.0, 2.0), Pt::new(10.0, 0.0, 4.0)];
+        let targets = vec![Pt::new(0.0, 0.0, 0.0), Pt::new(5.0, 5.0, 0.0), Pt::new(9.0, 9.0, 0.0)];
+        let write = |name: &str, content: &[u8]| {
+            use std::io::Write;
+            let path = ::std::env::temp_dir().join(name);
+            ::std::fs::File::create(&path).unwrap().write_all(content).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let wide = CostMatrix::from_csv_wide(&write("smoomars_test_costs_wide.csv",
+                                                    b"a,b,c\n0,1,NA\n2,1,4\n"),
+                                             b',')
+                .unwrap();
+        let long = CostMatrix::from_csv_long(&write("smoomars_test_costs_long.csv",
+                                                    b"origin,destination,cost\n0,1,1\n1,0,2\n\
+                                                      0,0,0\n1,1,1\n1,2,4\n"),
+                                             b',',
+                                             2,
+                                             3)
+                .unwrap();
+        // float64 array of shape (2, 3) in Fortran order, with NaN for the missing cost
+        let mut npy = b"\x93NUMPY\x01\x00\x76\x00{'descr': '<f8', 'fortran_order': True, \
+                        'shape': (2, 3), }"
+                .to_vec();
+        npy.extend(vec![b' '; 127 - npy.len()]);
+        npy.push(b'\n');
+        for &cost in &[0.0, 2.0, 1.0, 1.0, f64::NAN, 4.0] {
+            npy.extend_from_slice(&cost.to_bits().to_le_bytes());
+        }
+        let npy = CostMatrix::from_npy(&write("smoomars_test_costs.npy", &npy)).unwrap();
+        assert_eq!(wide, long);
+        assert_eq!(wide, npy);
+        assert_eq!((2, 3), (wide.nb_origins(), wide.nb_destinations()));
+        assert_eq!(f64::INFINITY, wide.get(0, 2));
+
+        let res = idw_from_costs(&obs_pts, &targets, &wide, 1.0).unwrap();
+        let values = res.iter().map(|pt| pt.get_value()).collect::<Vec<f64>>();
+        assert_eq!(values, vec![2.0, 3.0, 4.0]);
+        let res = stewart_from_costs(&obs_pts, &targets, &wide, SmoothType::Exponential, 1.0, 1.0)
+            .unwrap();
+        let (x, y, value) = res[2].get_triplet();
+        assert!(x == 9.0 && y == 9.0 && (value - 4.0 * 0.5f64.powi(4)).abs() < 1e-12);
+        let pareto = "pareto".parse::<SmoothType>().unwrap();
+        let res = stewart_from_costs(&obs_pts, &targets, &wide, pareto, 1.0, 1.0).unwrap();
+        assert!((res[2].get_value() - 4.0 / 5.0).abs() < 1e-12);
+        assert!("gaussian".parse::<SmoothType>().is_err());
+
+        match idw_from_costs(&obs_pts, &targets[..2], &wide, 1.0) {
+            Err(Error(ErrorKind::DimensionMismatch(2, 3), _)) => (),
+            _ => panic!("expected a DimensionMismatch error"),
+        }
+        match CostMatrix::new(2, 2, vec![1.0; 3]) {
+            Err(Error(ErrorKind::DimensionMismatch(4, 3), _)) => (),
+            _ => panic!("expected a DimensionMismatch error"),
+        }
+        assert!(CostMatrix::new(1, 1, vec![-1.0]).is_err());
+    }
+
+    #[test]
+    fn test_barriers() {
+        let path = ::std::env::temp_dir().join("smoomars_test_barriers.geojson");
+        let path = path.to_str().unwrap();
+        {
+            use std::io::Write;
+            let mut file = ::std::fs::File::create(path).unwrap();
+            file.write_all(br#"{"type": "FeatureCollection", "features": [
+                {"type": "Feature", "properties": {}, "geometry": {"type": "LineString",
+                 "coordinates": [[5, -10], [5, 10]]}},
+                {"type": "Feature", "properties": {}, "geometry": {"type": "Polygon",
+                 "coordinates": [[[20, -1], [22, -1], [22, 1], [20, 1], [20, -1]]]}}]}"#)
+                .unwrap();
+        }
+        let barriers = Barriers::from_geojson(path).unwrap();
+        assert_eq!(barriers.nb_barriers(), 2);
+        assert!(barriers.crosses((1.0, 0.0), (9.0, 3.0)));
+        assert!(!barriers.crosses((1.0, 0.0), (4.0, 30.0)));
+        // Through the wall and the two sides of the square
+        assert_eq!(barriers.nb_crossed((0.0, 0.0), (30.0, 0.0)), 2);
+
+        let block = BarrierMetric::new(Euclidean, &barriers, Crossing::Block).unwrap();
+        let penalty = BarrierMetric::new(Euclidean, &barriers, Crossing::Penalty(100.0)).unwrap();
+        assert_eq!(block.distance(1.0, 0.0, 1.0, 5.0), 4.0);
+        assert_eq!(block.distance(1.0, 0.0, 9.0, 0.0), f64::INFINITY);
+        assert_eq!(penalty.distance(1.0, 0.0, 9.0, 0.0), 108.0);
+        assert_eq!(penalty.distance(0.0, 0.0, 30.0, 0.0), 230.0);
+        assert!(BarrierMetric::new(Euclidean, &barriers, Crossing::Penalty(-1.0)).is_err());
+
+        // Each side of the wall only sees its own observation point
+        let obs_pts = vec![Pt::new(0.0, 0.0, 1.0), Pt::new(10.0, 0.0, 3.0)];
+        let bbox = bbox::Bbox::new(0.0, 9.0, -2.0, 2.0);
+        let res = idw_interpolation_with_metric(4, 2, &bbox, &obs_pts, 2.0, &block).unwrap();
+        for pt in &res {
+            let (x, _, value) = pt.get_triplet();
+            assert_eq!(value, if x < 5.0 { 1.0 } else { 3.0 });
+        }
+        let conf = StewartPotentialGrid::new(5.0, 2.0, SmoothType::Exponential, &bbox, 4, 2, 1);
+        let res = stewart_with_metric(&conf, &obs_pts, &block).unwrap();
+        let left = stewart_with_metric(&conf, &obs_pts[..1], &Euclidean).unwrap();
+        let right = stewart_with_metric(&conf, &obs_pts[1..], &Euclidean).unwrap();
+        for ((pt, l), r) in res.iter().zip(&left).zip(&right) {
+            let (x, _, value) = pt.get_triplet();
+            let expected = if x < 5.0 { l } else { r }.get_triplet().2;
+            assert!((value - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_grid_spec() {
+        // Snapped to the multiples of 1000: the grid lines up with a 1 km reference grid
+        let bbox = bbox::Bbox::new(1234.0, 5678.0, 250.0, 1999.0);
+        let grid = GridSpec::from_cell_size(&bbox, 1000.0, 1000.0, Some((0.0, 0.0))).unwrap();
+        assert_eq!((grid.reso_x, grid.reso_y), (5, 2));
+        assert_eq!((grid.extent.min_x, grid.extent.max_x), (1000.0, 6000.0));
+        assert_eq!((grid.extent.min_y, grid.extent.max_y), (0.0, 2000.0));
+        assert_eq!(grid.cell_size(), (1000.0, 1000.0));
+        assert_eq!(grid.position, CellPosition::Corner);
+        let corners = parallel::grid_cells::<Pt>(&grid.sampling_bbox(), grid.reso_x, grid.reso_y);
+        assert_eq!(corners[0].get_coordinates(), (1000.0, 0.0));
+        assert_eq!(corners[9].get_coordinates(), (5000.0, 1000.0));
+        let grid = grid.with_position(CellPosition::Centre);
+        let centres = parallel::grid_cells::<Pt>(&grid.sampling_bbox(), grid.reso_x, grid.reso_y);
+        assert_eq!(centres[0].get_coordinates(), (1500.0, 500.0));
+        assert_eq!(centres[9].get_coordinates(), (5500.0, 1500.0));
+
+        // Not snapped: the grid starts at the box and is extended to whole cells
+        let bbox = bbox::Bbox::new(10.0, 3010.0000000001, -5.0, 995.0);
+        let grid = GridSpec::from_cell_size(&bbox, 1000.0, 500.0, None).unwrap();
+        assert_eq!((grid.reso_x, grid.reso_y), (3, 2));
+        assert_eq!((grid.extent.min_x, grid.extent.max_x), (10.0, 3010.0));
+        assert_eq!((grid.extent.min_y, grid.extent.max_y), (-5.0, 995.0));
+
+        assert!(GridSpec::from_cell_size(&bbox, 0.0, 500.0, None).is_err());
+        let bbox = bbox::Bbox::new(1.0, 0.0, 0.0, 1.0);
+        assert!(GridSpec::from_cell_size(&bbox, 1.0, 1.0, None).is_err());
+
+        // Same values as the grid functions called with the sampling box
+        let obs_pts = vec![Pt::new(100.0, 200.0, 5.0), Pt::new(2900.0, 800.0, 2.0)];
+        let grid = GridSpec::from_cell_size(&bbox::Bbox::new(0.0, 3000.0, 0.0, 1000.0),
+                                            250.0,
+                                            250.0,
+                                            Some((0.0, 0.0)))
+                .unwrap()
+                .with_position(CellPosition::Centre);
+        let res = idw_interpolation(grid.reso_x, grid.reso_y, &grid.sampling_bbox(), &obs_pts, 2.0)
+            .unwrap();
+        assert_eq!(res.len(), 12 * 4);
+        let (x, y, _) = res[res.len() - 1].get_triplet();
+        assert_eq!((x, y), (2875.0, 875.0));
+    }
+
+    #[test]
+    fn test_grid_template() {
+        use gdal::raster::{Dataset, Driver};
+        let dir = ::std::env::temp_dir();
+        let template = dir.join("smoomars_test_template.geotiff");
+        let template = template.to_str().unwrap();
+        let transform = [2.5, 0.5, 0.0, 48.0, 0.0, -0.25];
+        {
+            let dataset = Driver::get("GTiff")
+                .unwrap()
+                .create_with_band_type::<f64>(template, 6, 4, 1)
+                .unwrap();
+            dataset.set_geo_transform(&transform);
+        }
+        let grid = GridSpec::from_template(template).unwrap();
+        assert_eq!(grid.extent, bbox::Bbox::new(2.5, 5.5, 47.0, 48.0));
+        assert_eq!((grid.reso_x, grid.reso_y), (6, 4));
+        assert_eq!(grid.cell_size(), (0.5, 0.25));
+        assert_eq!(grid.position, CellPosition::Centre);
+
+        // The output has the geotransform of the template
+        let obs_pts = vec![Pt::new(3.0, 47.5, 5.0), Pt::new(5.0, 47.2, 2.0)];
+        let res = idw_interpolation(6, 4, &grid.sampling_bbox(), &obs_pts, 2.0).unwrap();
+        assert_eq!(res[0].get_coordinates(), (2.75, 47.125));
+        let output = dir.join("smoomars_test_template_output.geotiff");
+        let output = output.to_str().unwrap();
+        utils::write_to_raster(res, &grid.extent, (6, 4), output).unwrap();
+        let dataset = Dataset::open(::std::path::Path::new(output)).unwrap();
+        assert_eq!(dataset.size(), (6, 4));
+        assert_eq!(dataset.geo_transform().unwrap(), transform);
+
+        // South up templates are rejected rather than flipped
+        {
+            let dataset = Driver::get("GTiff")
+                .unwrap()
+                .create_with_band_type::<f64>(template, 6, 4, 1)
+                .unwrap();
+            dataset.set_geo_transform(&[2.5, 0.5, 0.0, 47.0, 0.0, 0.25]);
+        }
+        assert!(GridSpec::from_template(template).is_err());
+    }
+
+    #[test]
+    fn test_bbox_operations() {
+        let pts = vec![Pt::new(0.0, 0.0, 1.0), Pt::new(1.0, 1.0, 1.0), Pt::new(2.0, 2.0, 1.0)];
+        assert_eq!(bbox::Bbox::from_points(&pts), bbox::Bbox::new(0.0, 2.0, 0.0, 2.0));
+        assert_eq!(bbox::Bbox::from_points(&pts[1..2]), bbox::Bbox::new(1.0, 1.0, 1.0, 1.0));
+
+        let a = bbox::Bbox::new(0.0, 4.0, 0.0, 2.0);
+        let b = bbox::Bbox::new(3.0, 6.0, 1.0, 5.0);
+        assert_eq!((a.width(), a.height(), a.area()), (4.0, 2.0, 8.0));
+        assert!(a.contains(4.0, 1.0) && !a.contains(4.5, 1.0));
+        assert!(a.contains_bbox(&bbox::Bbox::new(1.0, 2.0, 0.0, 1.0)) && !a.contains_bbox(&b));
+        assert_eq!(a.union(&b), bbox::Bbox::new(0.0, 6.0, 0.0, 5.0));
+        assert_eq!(a.intersection(&b), Some(bbox::Bbox::new(3.0, 4.0, 1.0, 2.0)));
+        assert_eq!(a.intersection(&bbox::Bbox::new(5.0, 6.0, 0.0, 1.0)), None);
+        assert_eq!(a.buffer(1.0), bbox::Bbox::new(-1.0, 5.0, -1.0, 3.0));
+        assert_eq!(a.buffer_percent(50.0), bbox::Bbox::new(-2.0, 6.0, -1.0, 3.0));
+
+        let parsed = "1.5, -2, 3, 4".parse::<bbox::Bbox>().unwrap();
+        assert_eq!(parsed, bbox::Bbox::new(1.5, 3.0, -2.0, 4.0));
+        assert_eq!(parsed.to_string().parse::<bbox::Bbox>().unwrap(), parsed);
+        assert!("1,2,3".parse::<bbox::Bbox>().is_err());
+        assert!("3,2,1,4".parse::<bbox::Bbox>().is_err());
+        assert!("a,b,c,d".parse::<bbox::Bbox>().is_err());
+        let json = ::serde_json::to_string(&parsed).unwrap();
+        assert_eq!(::serde_json::from_str::<bbox::Bbox>(&json).unwrap(), parsed);
+
+        let (wgs84, mercator) = (Crs::from_epsg(4326).unwrap(), Crs::from_epsg(3857).unwrap());
+        let geo = bbox::Bbox::new(-5.0, 10.0, 40.0, 52.0);
+        assert_eq!(geo.transform(&wgs84, &wgs84), geo);
+        let projected = geo.transform(&wgs84, &mercator);
+        for &(lon, lat) in &[(-5.0, 40.0), (10.0, 52.0), (2.5, 40.0), (-5.0, 46.0)] {
+            let (x, y) = wgs84.transform(&mercator, lon, lat);
+            assert!(projected.contains(x, y));
+        }
+        assert!((projected.min_x - wgs84.transform(&mercator, -5.0, 40.0).0).abs() < 1e-6);
+        assert!((projected.max_y - wgs84.transform(&mercator, 10.0, 52.0).1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_pycnophylactic() {
+        let path = ::std::env::temp_dir().join("smoomars_test_zones.geojson");
+        let path = path.to_str().unwrap();
+        {
+            use std::io::Write;
+            let mut file = ::std::fs::File::create(path).unwrap();
+            file.write_all(br#"{"type": "FeatureCollection", "features": [
+                {"type": "Feature", "properties": {"pop": 1000}, "geometry": {"type": "Polygon",
+                 "coordinates": [[[0, 0], [5, 0], [5, 4], [0, 4], [0, 0]]]}},
+                {"type": "Feature", "properties": {"pop": "100"}, "geometry": {"type": "Polygon",
+                 "coordinates": [[[5, 0], [10, 0], [10, 4], [5, 4], [5, 0]]]}},
+                {"type": "Feature", "properties": {"pop": 50}, "geometry": {"type": "Polygon",
+                 "coordinates": [[[7.2, 2.2], [7.4, 2.2], [7.4, 2.4], [7.2, 2.4],
+                                  [7.2, 2.2]]]}}]}"#)
+                .unwrap();
+        }
+        let zones = parse_geojson_zones(path, "pop").unwrap();
+        assert_eq!(zones.iter().map(|z| z.count).collect::<Vec<f64>>(), vec![1000.0, 100.0, 50.0]);
+        assert!(parse_geojson_zones(path, "other").is_err());
+
+        let grid = GridSpec::new(bbox::Bbox::new(0.0, 12.0, 0.0, 4.0), 12, 4)
+            .with_position(CellPosition::Centre);
+        let (res, report) =
+            pycnophylactic::<Pt>(&zones, &grid, None, &PycnoConfig::default()).unwrap();
+        assert!(report.converged && report.unassigned_zones.is_empty());
+        // The third zone holds no centre: its count goes to the cell of the second
+        // one at (7.5, 2.5), which keeps its own count
+        assert_eq!(report.shared_zones, vec![2]);
+        let mut sums = vec![0.0; 2];
+        for pt in &res {
+            let (x, _, value) = pt.get_triplet();
+            if x > 10.0 {
+                // Outside of the zones
+                assert!(value.is_nan());
+            } else {
+                assert!(value >= 0.0);
+                sums[if x < 5.0 { 0 } else { 1 }] += value;
+            }
+        }
+        assert!((sums[0] - 1000.0).abs() < 1e-9 * 1000.0);
+        assert!((sums[1] - 150.0).abs() < 1e-9 * 150.0);
+        assert!(res[7 * 4 + 2].get_value() > 50.0);
+        // Without the second zone, the cell is given to the third one
+        let small = vec![zones[0].clone(), zones[2].clone()];
+        let (res, report) =
+            pycnophylactic::<Pt>(&small, &grid, None, &PycnoConfig::default()).unwrap();
+        assert!(report.shared_zones.is_empty() && report.unassigned_zones.is_empty());
+        assert!((res[7 * 4 + 2].get_value() - 50.0).abs() < 1e-9);
+        // Smooth across the boundary between the zones
+        let value_at = |i: usize, j: usize| res[i * 4 + j].get_value();
+        assert!(value_at(0, 0) > value_at(4, 0) && value_at(4, 0) > value_at(5, 0));
+        // A zone cropped out of the grid window is unassigned, not given a border cell
+        let outside = Zone::new(vec![vec![vec![(20.0, 1.0), (25.0, 1.0), (25.0, 3.0),
+                                               (20.0, 3.0), (20.0, 1.0)]]],
+                                30.0);
+        let cropped = vec![zones[0].clone(), zones[1].clone(), outside];
+        let (res, report) =
+            pycnophylactic::<Pt>(&cropped, &grid, None, &PycnoConfig::default()).unwrap();
+        assert_eq!(report.unassigned_zones, vec![2]);
+        assert!(report.shared_zones.is_empty());
+        let mut sums = vec![0.0; 2];
+        for pt in &res {
+            let (x, _, value) = pt.get_triplet();
+            if x > 10.0 {
+                assert!(value.is_nan());
+            } else {
+                sums[if x < 5.0 { 0 } else { 1 }] += value;
+            }
+        }
+        assert!((sums[0] - 1000.0).abs() < 1e-9 * 1000.0);
+        assert!((sums[1] - 100.0).abs() < 1e-9 * 100.0);
+
+        // Nothing on the cells of null weight
+        let mut weights = vec![1.0; 48];
+        for k in 6 * 4..10 * 4 {
+            weights[k] = 0.0;
+        }
+        weights[0] = f64::NAN;
+        let (res, _) =
+            pycnophylactic::<Pt>(&zones[..2], &grid, Some(&weights), &PycnoConfig::default())
+                .unwrap();
+        let mut sums = vec![0.0; 2];
+        for (k, pt) in res.iter().enumerate().filter(|&(k, _)| k < 40) {
+            let (x, _, value) = pt.get_triplet();
+            if weights[k] == 0.0 || k == 0 {
+                assert_eq!(value, 0.0);
+            }
+            sums[if x < 5.0 { 0 } else { 1 }] += value;
+        }
+        assert!((sums[0] - 1000.0).abs() < 1e-9 && (sums[1] - 100.0).abs() < 1e-9);
+
+        assert!(pycnophylactic::<Pt>(&zones, &grid, Some(&weights[1..]), &PycnoConfig::default())
+                    .is_err());
+        let config = PycnoConfig { relaxation: 0.0, ..PycnoConfig::default() };
+        assert!(pycnophylactic::<Pt>(&zones, &grid, None, &config).is_err());
+
+        // Weight rasters stored from the top or from the bottom, aligned with the grid
+        use gdal::raster::{Buffer, Driver};
+        let raster = ::std::env::temp_dir().join("smoomars_test_weights.geotiff");
+        let raster = raster.to_str().unwrap();
+        let write = |transform: [f64; 6], rows: Vec<f64>| {
+            let dataset = Driver::get("GTiff")
+                .unwrap()
+                .create_with_band_type::<f64>(raster, 12, 4, 1)
+                .unwrap();
+            dataset.set_geo_transform(&transform);
+            dataset.write_raster(1, (0, 0), (12, 4), Buffer::new((12, 4), rows));
+        };
+        // Value of the cell (i, j), j counted from the bottom
+        let value = |i: usize, j: usize| (10 * i + j) as f64;
+        let rows = |top_down: bool| {
+            let mut rows = Vec::new();
+            for row in 0..4 {
+                for i in 0..12 {
+                    rows.push(value(i, if top_down { 3 - row } else { row }));
+                }
+            }
+            rows
+        };
+        let expected = (0..48).map(|k| value(k / 4, k % 4)).collect::<Vec<f64>>();
+        write([0.0, 1.0, 0.0, 4.0, 0.0, -1.0], rows(true));
+        assert_eq!(read_weight_raster(raster, &grid).unwrap(), expected);
+        write([0.0, 1.0, 0.0, 0.0, 0.0, 1.0], rows(false));
+        assert_eq!(read_weight_raster(raster, &grid).unwrap(), expected);
+        write([1.0, 1.0, 0.0, 4.0, 0.0, -1.0], rows(true));
+        assert!(read_weight_raster(raster, &grid).is_err());
+    }
+
+    #[test]
+    fn test_normalize() {
+        let grid = GridSpec::new(bbox::Bbox::new(0.0, 4.0, 0.0, 1.0), 2, 2);
+        let cells = || {
+            let mut cells = parallel::grid_cells::<Pt>(&grid.extent, 2, 2);
+            for (cell, &value) in cells.iter_mut().zip(&[1.0, 3.0, f64::NAN, 4.0]) {
+                cell.set_value(value);
+            }
+            cells
+        };
+        assert_eq!(cell_areas::<Pt>(&grid), vec![1.0; 4]);
+        let values = |cells: &[Pt]| cells.iter().map(|c| c.get_value()).collect::<Vec<f64>>();
+
+        let mut res = cells();
+        normalize(&mut res, &grid, Normalization::Volume(16.0)).unwrap();
+        let res = values(&res);
+        assert_eq!((res[0], res[1], res[3]), (2.0, 6.0, 8.0));
+        assert!(res[2].is_nan());
+        let mut res = cells();
+        normalize(&mut res, &grid, Normalization::PercentOfMax).unwrap();
+        assert_eq!(values(&res)[..2], [25.0, 75.0]);
+        let mut res = cells();
+        normalize(&mut res, &grid, Normalization::Density).unwrap();
+        assert_eq!(values(&res)[3], 4.0);
+        let mut res = cells();
+        res.truncate(3);
+        assert!(normalize(&mut res, &grid, Normalization::Density).is_err());
+
+        // Cells of a global grid shrink towards the poles, their areas summing
+        // to the area of the sphere
+        let grid = GridSpec::new(bbox::Bbox::new(-180.0, 180.0, -90.0, 90.0), 36, 18);
+        let areas = cell_areas::<SphericalPtValue>(&grid);
+        let sphere = 4.0 * f64::consts::PI * 6372800.0f64.powi(2);
+        assert!((areas.iter().sum::<f64>() - sphere).abs() < 1e-9 * sphere);
+        assert!(areas[0] < areas[8] && (areas[8] - areas[9]).abs() < 1e-6 * areas[8]);
+        assert_eq!(areas[0], areas[18 * 35]);
+
+        // Potentials rescaled to the total stock of the observation points
+        let obs_points = utils::parse_json_points::<SphericalPtValue>("examples/ra.json").unwrap();
+        let grid = GridSpec::new(bbox::Bbox::new(1.0, 4.0, 32.0, 35.0), 20, 20)
+            .with_position(CellPosition::Centre);
+        let sampling = grid.sampling_bbox();
+        let conf = StewartPotentialGrid::new(15000.0, 2.0, SmoothType::Exponential, &sampling,
+                                             20, 20, 1);
+        let mut res = stewart(&conf, &obs_points).unwrap();
+        let stock = obs_points.iter().map(|pt| pt.get_value()).sum::<f64>();
+        normalize(&mut res, &grid, Normalization::Volume(stock)).unwrap();
+        let areas = cell_areas::<SphericalPtValue>(&grid);
+        let integral = res.iter()
+            .zip(&areas)
+            .map(|(pt, area)| pt.get_value() * area)
+            .sum::<f64>();
+        assert!((integral - stock).abs() < 1e-9 * stock);
+    }
 }
@@ -0,0 +1,151 @@
+//! Tiled computation of grids too large to be held in memory.
+//!
+//! The grid is evaluated block by block, each finished `Tile` being handed
+//! to a `TileWriter` (such as a GeoTIFF file) before the next one is
+//! computed, so the memory used only depends on the tile size.
+//! The values are the same as the ones of the in-memory computations, except
+//! for the Stewart potentials, whose FFT engine needs the whole grid: they are
+//! computed exactly where the in-memory `Auto` engine can take the FFT one
+//! (see `stewart_tiled`).
+use gdal::raster::{Buffer, Dataset, Driver};
+use errors::*;
+use bbox::Bbox;
+use kernel::map_coordinates;
+use projection::Crs;
+
+
+/// Default number of cells along each side of a tile.
+pub const DEFAULT_TILE_SIZE: usize = 512;
+
+/// A block of `width` by `height` cells of the grid, whose top left cell is at
+/// column `col_off` and row `row_off` (rows are counted from the top of the
+/// grid, at the maximum y).
+#[derive(Debug, Clone)]
+pub struct Tile {
+    pub col_off: usize,
+    pub row_off: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Values of the cells, row by row from the top left one.
+    pub values: Vec<f64>,
+}
+
+/// Destination of the tiles of a tiled computation.
+pub trait TileWriter {
+    fn write_tile(&mut self, tile: Tile) -> Result<()>;
+}
+
+impl<F> TileWriter for F
+    where F: FnMut(Tile) -> Result<()>
+{
+    fn write_tile(&mut self, tile: Tile) -> Result<()> {
+        self(tile)
+    }
+}
+
+/// Single band GeoTIFF file, written tile by tile.
+#[allow(missing_debug_implementations)]
+pub struct RasterTileWriter {
+    dataset: Dataset,
+}
+
+impl RasterTileWriter {
+    /// Create the file holding a grid of `reso_x` by `reso_y` cells over `bbox`.
+    pub fn create(path: &str, bbox: &Bbox, reso_x: usize, reso_y: usize) -> Result<Self> {
+        bbox.check_grid(reso_x, reso_y)?;
+        let driver = Driver::get("GTiff")
+            .map_err(|e| Error::from(format!("Unable to load the GTiff driver: {:?}", e)))?;
+        let dataset = driver
+            .create_with_band_type::<f64>(path, reso_x as isize, reso_y as isize, 1)
+            .map_err(|e| Error::from(format!("Unable to create {}: {:?}", path, e)))?;
+        let pixel_size_x = (bbox.max_x - bbox.min_x) / reso_x as f64;
+        let pixel_size_y = (bbox.max_y - bbox.min_y) / reso_y as f64;
+        dataset.set_geo_transform(&[bbox.min_x, pixel_size_x, 0.0, bbox.max_y, 0.0, -pixel_size_y]);
+        Ok(RasterTileWriter { dataset: dataset })
+    }
+
+    /// Record the CRS of the coordinates of the grid in the file.
+    pub fn set_crs(&mut self, crs: &Crs) -> Result<()> {
+        self.dataset.set_projection(&crs.to_wkt()?);
+        Ok(())
+    }
+}
+
+impl TileWriter for RasterTileWriter {
+    fn write_tile(&mut self, tile: Tile) -> Result<()> {
+        let size = (tile.width, tile.height);
+        self.dataset
+            .write_raster(1,
+                          (tile.col_off as isize, tile.row_off as isize),
+                          size,
+                          Buffer::new(size, tile.values));
+        Ok(())
+    }
+}
+
+/// Evaluate `func(x, y)` on each cell of the grid of `reso_x` by `reso_y`
+/// cells over `bbox`, by tiles of (at most) `tile_size` by `tile_size` cells
+/// computed in parallel, passing each of them to `writer` once finished.
+///
+/// The cells are located as in the in-memory computations (`x = min_x + i * x_step`,
+/// `y = min_y + j * y_step`) but the tiles are laid out as raster rows,
+/// from the top of the grid.
+pub fn compute_tiled<F, W>(bbox: &Bbox,
+                           reso_x: usize,
+                           reso_y: usize,
+                           tile_size: usize,
+                           func: F,
+                           writer: &mut W)
+                           -> Result<()>
+    where F: Fn(f64, f64) -> f64 + Sync,
+          W: TileWriter
+{
+    let batch = |cells: &[(f64, f64)], out: &mut [f64]| {
+        for (&(x, y), o) in cells.iter().zip(out.iter_mut()) {
+            *o = func(x, y);
+        }
+    };
+    compute_tiled_batched(bbox, reso_x, reso_y, tile_size, batch, writer)
+}
+
+/// Same as `compute_tiled`, the cells of each tile being evaluated by
+/// batches, `batch(coordinates, values)` setting the values of the cells
+/// at the given coordinates (as the in-memory computations do).
+pub fn compute_tiled_batched<F, W>(bbox: &Bbox,
+                                   reso_x: usize,
+                                   reso_y: usize,
+                                   tile_size: usize,
+                                   batch: F,
+                                   writer: &mut W)
+                                   -> Result<()>
+    where F: Fn(&[(f64, f64)], &mut [f64]) + Sync,
+          W: TileWriter
+{
+    bbox.check_grid(reso_x, reso_y)?;
+    if tile_size == 0 {
+        return Err("The tile size must be at least 1".into());
+    }
+    let x_step = (bbox.max_x - bbox.min_x) / reso_x as f64;
+    let y_step = (bbox.max_y - bbox.min_y) / reso_y as f64;
+    for row_off in (0..reso_y).step_by(tile_size) {
+        let height = tile_size.min(reso_y - row_off);
+        for col_off in (0..reso_x).step_by(tile_size) {
+            let width = tile_size.min(reso_x - col_off);
+            let coords = (0..width * height)
+                .map(|k| {
+                    let i = col_off + k % width;
+                    let j = reso_y - 1 - (row_off + k / width);
+                    (bbox.min_x + x_step * i as f64, bbox.min_y + y_step * j as f64)
+                })
+                .collect::<Vec<(f64, f64)>>();
+            writer.write_tile(Tile {
+                                  col_off: col_off,
+                                  row_off: row_off,
+                                  width: width,
+                                  height: height,
+                                  values: map_coordinates(&coords, &batch),
+                              })?;
+        }
+    }
+    Ok(())
+}
@@ -7,11 +7,15 @@ use csv;
 use errors::*;
 use bbox::Bbox;
 use geojson::{Feature, FeatureCollection, GeoJson, Geometry, Value};
+use geometry::feature_observations;
+use kernel::per_lane;
+use metric::{DistanceMetric, Euclidean, Geodesic, Haversine};
+use projection::Crs;
+pub use geometry::PolygonRepresentation;
 use gdal::raster::{Driver, Buffer};
+use wide::f64x4;
 
 
-static R: f64 = 6372.8 * 1000.0;
-
 pub trait PtValue {
     fn new(f64, f64, f64) -> Self;
     fn get_coordinates(&self) -> (f64, f64);
@@ -19,6 +23,38 @@ pub trait PtValue {
     fn set_value(&mut self, f64);
     fn get_triplet(&self) -> (f64, f64, f64);
     fn distance(&self, f64, f64) -> f64;
+    /// Distance between (x1, y1) and (x2, y2), the same as
+    /// `Self::new(x1, y1, _).distance(x2, y2)`.
+    fn distance_between(x1: f64, y1: f64, x2: f64, y2: f64) -> f64
+        where Self: Sized
+    {
+        Self::new(x1, y1, 0.0).distance(x2, y2)
+    }
+    /// `distance_between` from each lane of (`xs`, `ys`) and (x, y).
+    fn distance_lanes(xs: f64x4, ys: f64x4, x: f64, y: f64) -> f64x4
+        where Self: Sized
+    {
+        per_lane(xs, ys, x, y, Self::distance_between)
+    }
+    /// Whether `distance` is the euclidean distance between the coordinates.
+    fn is_planar() -> bool
+        where Self: Sized
+    {
+        false
+    }
+    /// Box (in the units of the coordinates) containing every location lying
+    /// within `dist` (in the units of `distance`) of (x, y).
+    fn search_window(x: f64, y: f64, dist: f64) -> Bbox
+        where Self: Sized
+    {
+        Bbox::new(x - dist, x + dist, y - dist, y + dist)
+    }
+    /// Names of the two coordinates, as used in headers of tabular outputs.
+    fn coordinate_names() -> (&'static str, &'static str)
+        where Self: Sized
+    {
+        ("x", "y")
+    }
 }
 
 
@@ -49,19 +85,63 @@ impl PtValue for SphericalPtValue {
     fn get_triplet(&self) -> (f64, f64, f64) {
         (self.lon, self.lat, self.value)
     }
+    fn coordinate_names() -> (&'static str, &'static str) {
+        ("lon", "lat")
+    }
+    fn search_window(lon: f64, lat: f64, dist: f64) -> Bbox {
+        Haversine.search_window(lon, lat, dist)
+    }
+    fn distance(&self, other_lon: f64, other_lat: f64) -> f64 {
+        SphericalPtValue::distance_between(self.lon, self.lat, other_lon, other_lat)
+    }
+    #[inline(always)]
+    fn distance_between(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+        Haversine.distance(lon1, lat1, lon2, lat2)
+    }
+}
+
+/// Point in geographic coordinates on the WGS84 ellipsoid, whose distances
+/// are the lengths of the geodesics, in metres: exact where the spherical
+/// approximation errs by up to 0.5%, but an order of magnitude slower.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GeodesicPtValue {
+    pub lat: f64,
+    pub lon: f64,
+    pub value: f64,
+}
+
+impl PtValue for GeodesicPtValue {
+    fn new(lon: f64, lat: f64, value: f64) -> Self {
+        GeodesicPtValue {
+            lon: lon,
+            lat: lat,
+            value: value,
+        }
+    }
+    fn get_coordinates(&self) -> (f64, f64) {
+        (self.lon, self.lat)
+    }
+    fn get_value(&self) -> f64 {
+        self.value
+    }
+    fn set_value(&mut self, value: f64) {
+        self.value = value;
+    }
+    fn get_triplet(&self) -> (f64, f64, f64) {
+        (self.lon, self.lat, self.value)
+    }
+    fn coordinate_names() -> (&'static str, &'static str) {
+        ("lon", "lat")
+    }
+    fn search_window(lon: f64, lat: f64, dist: f64) -> Bbox {
+        Geodesic.search_window(lon, lat, dist)
+    }
     fn distance(&self, other_lon: f64, other_lat: f64) -> f64 {
-        // let th1 = self.lat;
-        // let ph1 = self.lon;
-        // let th2 = other.lat;
-        // let ph2 = other.lon;
-        let mut _ph1 = self.lon - other_lon;
-        _ph1 = _ph1.to_radians();
-        let _th1 = self.lat.to_radians();
-        let _th2 = other_lat.to_radians();
-        let dz: f64 = _th1.sin() - _th2.sin();
-        let dx: f64 = _ph1.cos() * _th1.cos() - _th2.cos();
-        let dy: f64 = _ph1.sin() * _th1.cos();
-        ((dx * dx + dy * dy + dz * dz).sqrt() / 2.0).asin() * 2.0 * R
+        GeodesicPtValue::distance_between(self.lon, self.lat, other_lon, other_lat)
+    }
+    #[inline(always)]
+    fn distance_between(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+        Geodesic.distance(lon1, lat1, lon2, lat2)
     }
 }
 
@@ -92,14 +172,72 @@ impl PtValue for CartesianPtValue {
     fn get_triplet(&self) -> (f64, f64, f64) {
         (self.x, self.y, self.value)
     }
+    fn is_planar() -> bool {
+        true
+    }
     fn distance(&self, other_x: f64, other_y: f64) -> f64 {
-        let (dx, dy) = (self.x - other_x, self.y - other_y);
-        ((dx * dx) + (dy * dy)).sqrt()
+        CartesianPtValue::distance_between(self.x, self.y, other_x, other_y)
+    }
+    #[inline(always)]
+    fn distance_between(x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+        Euclidean.distance(x1, y1, x2, y2)
+    }
+    #[inline(always)]
+    fn distance_lanes(xs: f64x4, ys: f64x4, x: f64, y: f64) -> f64x4 {
+        Euclidean.distance_lanes(xs, ys, x, y)
+    }
+}
+
+/// Options controlling how features of a GeoJSON file become observation points.
+#[derive(Debug, Clone)]
+pub struct GeoJsonOptions {
+    pub polygon_representation: PolygonRepresentation,
+    /// Whether the value of a MultiPoint feature is split evenly between
+    /// its points (preserving the total stock) or given to each of them.
+    pub split_multipoint_value: bool,
+}
+
+impl Default for GeoJsonOptions {
+    fn default() -> Self {
+        GeoJsonOptions {
+            polygon_representation: PolygonRepresentation::Centroid,
+            split_multipoint_value: true,
+        }
+    }
+}
+
+/// Outcome of reading a GeoJSON file: the number of points read and the
+/// features which were skipped, as (feature index, reason) pairs.
+#[derive(Debug, Clone, Default)]
+pub struct GeoJsonReport {
+    pub nb_points: usize,
+    pub skipped_features: Vec<(usize, String)>,
+}
+
+impl GeoJsonReport {
+    pub fn nb_skipped(&self) -> usize {
+        self.skipped_features.len()
     }
 }
 
 pub fn parse_geojson_points<T>(path: &str, field_name: &str) -> Result<Vec<T>>
     where T: PtValue
+{
+    let (res, _) = parse_geojson_points_with::<T>(path, field_name, &GeoJsonOptions::default())?;
+    Ok(res)
+}
+
+/// Read observation points from the features of a GeoJSON FeatureCollection.
+///
+/// Points are used as is, MultiPoints are expanded and (Multi)Polygons are
+/// replaced by their centroid or by a point on their surface. Features which
+/// can't be used (other geometry types, missing or invalid value) are
+/// skipped and listed in the returned report.
+pub fn parse_geojson_points_with<T>(path: &str,
+                                    field_name: &str,
+                                    options: &GeoJsonOptions)
+                                    -> Result<(Vec<T>, GeoJsonReport)>
+    where T: PtValue
 {
     let mut file = File::open(path)?;
     let mut raw_json = String::new();
@@ -110,23 +248,34 @@ pub fn parse_geojson_points<T>(path: &str, field_name: &str) -> Result<Vec<T>>
         _ => return Err("Error: expected a FeatureCollection".into()),
     };
     let mut res = Vec::with_capacity(features.len());
-    for ft in features {
-        if let Some(ref geometry) = ft.geometry {
-            if let Value::Point(ref positions) = geometry.value {
-                let prop = ft.properties.unwrap();
-                let value = prop.get(field_name).unwrap();
-                let val = if value.is_number() {
-                    value.as_f64().unwrap()
-                } else {
-                    value.to_string().replace("\"", "").parse::<f64>()?
-                };
-                res.push(T::new(positions[0], positions[1], val));
+    let mut report = GeoJsonReport::default();
+    for (ix, ft) in features.iter().enumerate() {
+        match feature_observations(ft.geometry.as_ref().map(|g| &g.value),
+                                   ft.properties.as_ref(),
+                                   field_name,
+                                   options.polygon_representation,
+                                   options.split_multipoint_value) {
+            Ok(triplets) => {
+                for (x, y, val) in triplets {
+                    res.push(T::new(x, y, val));
+                }
             }
-        } else {
-            return Err("Error: empty FeatureCollection".into());
+            Err(reason) => report.skipped_features.push((ix, reason)),
         }
     }
-    Ok(res)
+    report.nb_points = res.len();
+    Ok((res, report))
+}
+
+fn json_field(elem: &serde_json::Value, name: &str) -> Result<f64> {
+    match elem.get(name) {
+        Some(&serde_json::Value::Number(ref val)) => {
+            val.as_f64().ok_or_else(|| "Invalid datastructure".into())
+        }
+        Some(&serde_json::Value::String(ref val)) => Ok(val.to_string().parse::<f64>()?),
+        Some(_) => Err("Invalid datastructure".into()),
+        None => Err(ErrorKind::MissingField(name.to_string()).into()),
+    }
 }
 
 pub fn parse_json_points<T>(path: &str) -> Result<Vec<T>>
@@ -136,31 +285,20 @@ pub fn parse_json_points<T>(path: &str) -> Result<Vec<T>>
     let mut raw_json = String::new();
     file.read_to_string(&mut raw_json)?;
     let decoded: serde_json::Value = serde_json::from_str(&raw_json)?;
-    let ref arr = if decoded.is_object() && !decoded.get("values").is_none() &&
-                     decoded["values"].is_array() {
-        decoded["values"].as_array().unwrap()
-    } else if decoded.is_array() {
-        decoded.as_array().unwrap()
-    } else {
-        return Err("Invalid datastructure".into());
+    let arr = match decoded.get("values").and_then(|values| values.as_array()) {
+        Some(arr) => arr,
+        None => {
+            match decoded.as_array() {
+                Some(arr) => arr,
+                None => return Err("Invalid datastructure".into()),
+            }
+        }
     };
     let mut res = Vec::with_capacity(arr.len());
     for elem in arr.iter() {
-        let value = match elem["value"] {
-            serde_json::Value::Number(ref val) => val.as_f64().unwrap(),
-            serde_json::Value::String(ref val) => val.to_string().parse::<f64>()?,
-            _ => return Err("Invalid datastructure".into()),
-        };
-        let y = match elem["lat"] {
-            serde_json::Value::Number(ref val) => val.as_f64().unwrap(),
-            serde_json::Value::String(ref val) => val.to_string().parse::<f64>()?,
-            _ => return Err("Invalid datastructure".into()),
-        };
-        let x = match elem["lon"] {
-            serde_json::Value::Number(ref val) => val.as_f64().unwrap(),
-            serde_json::Value::String(ref val) => val.to_string().parse::<f64>()?,
-            _ => return Err("Invalid datastructure".into()),
-        };
+        let value = json_field(elem, "value")?;
+        let y = json_field(elem, "lat")?;
+        let x = json_field(elem, "lon")?;
         res.push(T::new(x, y, value));
     }
     Ok(res)
@@ -175,11 +313,24 @@ pub fn save_json_points<T>(path: &str, result_points: Vec<T>) -> Result<()>
     Ok(())
 }
 
-pub fn save_geojson_points(path: &str, result_points: Vec<SphericalPtValue>) -> Result<()> {
+pub fn save_geojson_points<T>(path: &str, result_points: Vec<T>) -> Result<()>
+    where T: PtValue
+{
+    save_geojson_points_with_crs(path, result_points, None)
+}
+
+/// Same as `save_geojson_points`, naming the CRS of the coordinates in a
+/// "crs" member (as GDAL reads it) unless it is the default WGS84.
+pub fn save_geojson_points_with_crs<T>(path: &str,
+                                       result_points: Vec<T>,
+                                       crs: Option<&Crs>)
+                                       -> Result<()>
+    where T: PtValue
+{
     let mut features = Vec::with_capacity(result_points.len());
     for res_pt in result_points {
-        let (lat, lon, value) = res_pt.get_triplet();
-        let geometry = Geometry::new(Value::Point(vec![lon, lat]));
+        let (x, y, value) = res_pt.get_triplet();
+        let geometry = Geometry::new(Value::Point(vec![x, y]));
         let mut prop = serde_json::Map::new();
         prop.insert(String::from("value"), serde_json::to_value(value)?);
         features.push(Feature {
@@ -190,10 +341,25 @@ pub fn save_geojson_points(path: &str, result_points: Vec<SphericalPtValue>) ->
                           properties: Some(prop),
                       });
     }
+    let foreign_members = match crs {
+        Some(crs) if crs.epsg() != Some(4326) => {
+            let urn = crs.urn()
+                .ok_or_else(|| format!("The CRS {} has no EPSG code", crs))?;
+            let mut properties = serde_json::Map::new();
+            properties.insert(String::from("name"), serde_json::Value::String(urn));
+            let mut crs_member = serde_json::Map::new();
+            crs_member.insert(String::from("type"), serde_json::Value::String("name".into()));
+            crs_member.insert(String::from("properties"), serde_json::Value::Object(properties));
+            let mut members = serde_json::Map::new();
+            members.insert(String::from("crs"), serde_json::Value::Object(crs_member));
+            Some(members)
+        }
+        _ => None,
+    };
     let feature_collection = FeatureCollection {
         bbox: None,
         features: features,
-        foreign_members: None,
+        foreign_members: foreign_members,
     };
     let serialized = GeoJson::from(feature_collection).to_string();
     let mut file = File::create(path)?;
@@ -201,17 +367,282 @@ pub fn save_geojson_points(path: &str, result_points: Vec<SphericalPtValue>) ->
     Ok(())
 }
 
+/// Path of the sidecar file holding the CRS of the output at `path`: the same
+/// path with a `.prj` extension (as used alongside shapefiles).
+pub fn prj_path(path: &str) -> ::std::path::PathBuf {
+    ::std::path::Path::new(path).with_extension("prj")
+}
+
+/// Record `crs` in the sidecar file of the output at `path` (see `prj_path`),
+/// as its WKT definition, for the formats which can't hold it (CSV, JSON,
+/// GeoJSONSeq).
+pub fn save_prj(path: &str, crs: &Crs) -> Result<()> {
+    let wkt = crs.to_wkt()?;
+    let mut file = File::create(prj_path(path))?;
+    file.write_all(wkt.as_bytes())?;
+    Ok(())
+}
+
+/// Options controlling how results are written to a CSV file.
+#[derive(Debug, Clone)]
+pub struct CsvWriteOptions {
+    /// Number of decimals of the values (full precision if `None`), the
+    /// coordinates being always written in full.
+    pub precision: Option<usize>,
+    pub delimiter: u8,
+    /// Whether nodata cells (non-finite values, or values equal to `nodata`)
+    /// are written as is; otherwise they are omitted from point lists
+    /// and written as empty fields in grids.
+    pub include_nodata: bool,
+    pub nodata: Option<f64>,
+}
+
+impl Default for CsvWriteOptions {
+    fn default() -> Self {
+        CsvWriteOptions {
+            precision: None,
+            delimiter: b',',
+            include_nodata: true,
+            nodata: None,
+        }
+    }
+}
+
+impl CsvWriteOptions {
+    fn is_nodata(&self, value: f64) -> bool {
+        !value.is_finite() || self.nodata.map_or(false, |nd| nd == value)
+    }
+
+    fn format_value(&self, value: f64) -> String {
+        match self.precision {
+            Some(p) => format!("{:.*}", p, value),
+            None => value.to_string(),
+        }
+    }
+}
+
+/// Write the result points as a CSV file with one (x, y, value) row per point
+/// (or (lon, lat, value) for spherical points).
+pub fn save_csv_points<T>(path: &str, result_points: &[T], options: &CsvWriteOptions) -> Result<()>
+    where T: PtValue
+{
+    let mut wtr = csv::Writer::from_file(path)?.delimiter(options.delimiter);
+    let (name_x, name_y) = T::coordinate_names();
+    wtr.write(vec![name_x, name_y, "value"].into_iter())?;
+    for pt in result_points {
+        let (x, y, value) = pt.get_triplet();
+        if !options.include_nodata && options.is_nodata(value) {
+            continue;
+        }
+        wtr.write(vec![x.to_string(), y.to_string(), options.format_value(value)].into_iter())?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Write a result grid (as returned by the grid interpolation functions, for
+/// a resolution of `reso` cells) as a wide matrix: the header row holds the x
+/// coordinates of the columns, the first field of each row the y coordinate,
+/// and rows are ordered from the top (maximum y) to the bottom of the grid.
+pub fn save_csv_grid<T>(path: &str,
+                        result_points: &[T],
+                        reso: (usize, usize),
+                        options: &CsvWriteOptions)
+                        -> Result<()>
+    where T: PtValue
+{
+    let (reso_x, reso_y) = reso;
+    if result_points.len() != reso_x * reso_y {
+        return Err(format!("Expected {} grid cells, got {}",
+                           reso_x * reso_y,
+                           result_points.len())
+                           .into());
+    }
+    let mut wtr = csv::Writer::from_file(path)?.delimiter(options.delimiter);
+    let (name_x, name_y) = T::coordinate_names();
+    let mut header = Vec::with_capacity(reso_x + 1);
+    header.push(format!("{}/{}", name_y, name_x));
+    for i in 0..reso_x {
+        header.push(result_points[i * reso_y].get_coordinates().0.to_string());
+    }
+    wtr.write(header.into_iter())?;
+    for j in (0..reso_y).rev() {
+        let mut row = Vec::with_capacity(reso_x + 1);
+        row.push(result_points[j].get_coordinates().1.to_string());
+        for i in 0..reso_x {
+            let value = result_points[i * reso_y + j].get_value();
+            if !options.include_nodata && options.is_nodata(value) {
+                row.push(String::new());
+            } else {
+                row.push(options.format_value(value));
+            }
+        }
+        wtr.write(row.into_iter())?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Column of a CSV file, referenced either by its (zero-based) position
+/// or by its name in the header row.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CsvColumn {
+    Index(usize),
+    Name(String),
+}
+
+impl<'a> From<&'a str> for CsvColumn {
+    /// Interpret `s` as a column index if it is an integer, as a column name otherwise.
+    fn from(s: &'a str) -> Self {
+        match s.trim().parse::<usize>() {
+            Ok(ix) => CsvColumn::Index(ix),
+            Err(_) => CsvColumn::Name(s.to_string()),
+        }
+    }
+}
+
+/// Options controlling how observation points are read from a CSV file.
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    pub x_column: CsvColumn,
+    pub y_column: CsvColumn,
+    pub value_column: CsvColumn,
+    pub delimiter: u8,
+    pub quote: u8,
+    pub decimal_separator: char,
+    pub has_headers: bool,
+    pub skip_missing: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            x_column: CsvColumn::Index(0),
+            y_column: CsvColumn::Index(1),
+            value_column: CsvColumn::Index(2),
+            delimiter: b',',
+            quote: b'"',
+            decimal_separator: '.',
+            has_headers: true,
+            skip_missing: false,
+        }
+    }
+}
+
+/// Rows skipped while reading a CSV file, as (line number, reason) pairs.
+#[derive(Debug, Clone, Default)]
+pub struct CsvReport {
+    pub skipped_rows: Vec<(usize, String)>,
+}
+
+impl CsvReport {
+    pub fn nb_skipped(&self) -> usize {
+        self.skipped_rows.len()
+    }
+}
+
+fn resolve_csv_column(column: &CsvColumn, headers: &[String]) -> Result<usize> {
+    match *column {
+        CsvColumn::Index(ix) => Ok(ix),
+        CsvColumn::Name(ref name) => {
+            if headers.is_empty() {
+                return Err(format!("Column \"{}\" referenced by name but the file has no header",
+                                   name)
+                                   .into());
+            }
+            headers
+                .iter()
+                .position(|h| h.trim() == name.as_str())
+                .ok_or_else(|| format!("Column \"{}\" not found in header", name).into())
+        }
+    }
+}
+
+fn is_missing(field: &str) -> bool {
+    let f = field.trim();
+    f.is_empty() || f == "NA" || f == "NaN" || f == "nan"
+}
+
+fn parse_csv_number(field: &str, decimal_separator: char) -> Result<f64> {
+    let f = field.trim();
+    if decimal_separator == '.' {
+        Ok(f.parse::<f64>()?)
+    } else {
+        Ok(f.replace(decimal_separator, ".").parse::<f64>()?)
+    }
+}
+
 pub fn parse_csv_points<T>(path: &str) -> Result<Vec<T>>
     where T: PtValue
 {
-    let mut rdr = csv::Reader::from_file(path)?;
-    rdr = rdr.has_headers(true);
+    let (res, _) = parse_csv_points_with::<T>(path, &CsvOptions::default())?;
+    Ok(res)
+}
+
+/// Read observation points from a CSV file according to `options`.
+///
+/// Returns the points along with a report of the rows which were skipped
+/// because of a missing or invalid value, by the line they start on (only
+/// when `options.skip_missing` is set, such rows are an error otherwise).
+pub fn parse_csv_points_with<T>(path: &str, options: &CsvOptions) -> Result<(Vec<T>, CsvReport)>
+    where T: PtValue
+{
+    let mut rdr = csv::Reader::from_file(path)?
+        .has_headers(options.has_headers)
+        .delimiter(options.delimiter)
+        .quote(options.quote)
+        .flexible(true);
+    let headers = if options.has_headers {
+        rdr.headers()?
+    } else {
+        Vec::new()
+    };
+    let ix_x = resolve_csv_column(&options.x_column, &headers)?;
+    let ix_y = resolve_csv_column(&options.y_column, &headers)?;
+    let ix_value = resolve_csv_column(&options.value_column, &headers)?;
+    // Quoted fields may hold line breaks: each record starts on the line following
+    // the last one of the previous record (or of the header)
+    let nb_lines = |fields: &[String]| {
+        1 + fields.iter().map(|f| f.matches('\n').count()).sum::<usize>()
+    };
+    let mut line = if options.has_headers { 1 + nb_lines(&headers) } else { 1 };
     let mut res = Vec::new();
-    for record in rdr.decode() {
-        let (x, y, val): (f64, f64, f64) = record?;
-        res.push(T::new(x, y, val));
+    let mut report = CsvReport::default();
+    for record in rdr.records() {
+        let record = record.chain_err(|| format!("Line {}", line))?;
+        let record_line = line;
+        line += nb_lines(&record);
+        let mut values = [0.0; 3];
+        let mut invalid = None;
+        let columns = [(ix_x, "x"), (ix_y, "y"), (ix_value, "value")];
+        for (value, &(ix, name)) in values.iter_mut().zip(columns.iter()) {
+            let reason = match record.get(ix) {
+                Some(field) if !is_missing(field) => {
+                    match parse_csv_number(field, options.decimal_separator) {
+                        Ok(parsed) => {
+                            *value = parsed;
+                            continue;
+                        }
+                        Err(_) => {
+                            format!("invalid {} value '{}' (column {})", name, field.trim(), ix)
+                        }
+                    }
+                }
+                _ => format!("missing {} value (column {})", name, ix),
+            };
+            invalid = Some(reason);
+            break;
+        }
+        if let Some(reason) = invalid {
+            if options.skip_missing {
+                report.skipped_rows.push((record_line, reason));
+                continue;
+            }
+            return Err(format!("Line {}: {}", record_line, reason).into());
+        }
+        res.push(T::new(values[0], values[1], values[2]));
     }
-    Ok(res)
+    Ok((res, report))
 }
 
 pub fn almost_equal(a: f64, b: f64, epsilon: f64) -> bool {
@@ -225,30 +656,53 @@ pub fn almost_equal(a: f64, b: f64, epsilon: f64) -> bool {
     }
 }
 
+/// Write the values of a grid of `reso` cells over `bbox` (in the order of the
+/// grid functions) to the GeoTIFF at `path`, as a single band laid out as
+/// `tiled::RasterTileWriter` does: north up, row by row from the top.
 pub fn write_to_raster<T>(result_points: Vec<T>,
                           bbox: &Bbox,
-                          reso: (u32, u32),
+                          reso: (usize, usize),
                           path: &str)
                           -> Result<()>
     where T: PtValue
 {
-    let driver = Driver::get("GTiff").unwrap();
-    println!("{}", driver.long_name());
+    write_to_raster_with_crs(result_points, bbox, reso, path, None)
+}
+
+/// Same as `write_to_raster`, recording the CRS of the coordinates in the file.
+pub fn write_to_raster_with_crs<T>(result_points: Vec<T>,
+                                   bbox: &Bbox,
+                                   reso: (usize, usize),
+                                   path: &str,
+                                   crs: Option<&Crs>)
+                                   -> Result<()>
+    where T: PtValue
+{
+    bbox.check_grid(reso.0, reso.1)?;
+    if result_points.len() != reso.0 * reso.1 {
+        return Err(ErrorKind::DimensionMismatch(reso.0 * reso.1, result_points.len()).into());
+    }
+    let driver = Driver::get("GTiff")
+        .map_err(|e| Error::from(format!("Unable to load the GTiff driver: {:?}", e)))?;
     let pixel_size_x = (bbox.max_x - bbox.min_x) / reso.0 as f64;
     let pixel_size_y = (bbox.max_y - bbox.min_y) / reso.1 as f64;
     let dataset = driver
-        .create_with_band_type::<f64>(path, reso.0 as isize, reso.1 as isize, 3)
-        .unwrap();
-    dataset.set_geo_transform(&[bbox.min_x, pixel_size_x, 0.0, bbox.max_y, 0.0, pixel_size_y]);
+        .create_with_band_type::<f64>(path, reso.0 as isize, reso.1 as isize, 1)
+        .map_err(|e| Error::from(format!("Unable to create {}: {:?}", path, e)))?;
+    dataset.set_geo_transform(&[bbox.min_x, pixel_size_x, 0.0, bbox.max_y, 0.0, -pixel_size_y]);
+    if let Some(crs) = crs {
+        dataset.set_projection(&crs.to_wkt()?);
+    }
+    // The cells of the grid are ordered by column, from the bottom (k = i * reso_y + j)
+    let (reso_x, reso_y) = reso;
     let mut data = Vec::with_capacity(result_points.len());
-    for pt in result_points {
-        data.push(pt.get_value());
+    for row in 0..reso_y {
+        for i in 0..reso_x {
+            data.push(result_points[i * reso_y + reso_y - 1 - row].get_value());
+        }
     }
-    let buffer = Buffer::new((reso.0 as usize, reso.1 as usize), data);
-    dataset.write_raster(3 as isize,
-                         (0, 0),
-                         (reso.0 as usize, reso.1 as usize),
-                         buffer);
+    let buffer = Buffer::new(reso, data);
+    dataset.write_raster(1, (0, 0), reso, buffer);
     Ok(())
 }
 
@@ -0,0 +1,40 @@
+use std::env::temp_dir;
+use std::fs::File;
+use std::io::Write;
+use std::process::{Command, Output};
+
+fn smoomars(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_smoomars"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_single_input_point() {
+    let input = temp_dir().join("smoomars_cli_single_point.csv");
+    let input = input.to_str().unwrap();
+    let output = temp_dir().join("smoomars_cli_single_point_out.csv");
+    let output = output.to_str().unwrap();
+    File::create(input)
+        .unwrap()
+        .write_all(b"x,y,z\n3.5,3.5,100\n")
+        .unwrap();
+    let args = ["idw", "-i", input, "-o", output, "-d", "Euclidian", "-s", "4-4"];
+
+    // The extent of the data has no size: the error tells how to give it one
+    let res = smoomars(&args);
+    assert!(!res.status.success());
+    let stderr = String::from_utf8_lossy(&res.stderr);
+    assert!(stderr.contains("--window") && stderr.contains("--buffer"), "{}", stderr);
+
+    let mut buffered = args.to_vec();
+    buffered.extend_from_slice(&["--buffer", "2"]);
+    assert!(smoomars(&buffered).status.success());
+    let mut window = args.to_vec();
+    window.extend_from_slice(&["--window", "0,0,8,8"]);
+    assert!(smoomars(&window).status.success());
+    let mut cell_size = args[..args.len() - 2].to_vec();
+    cell_size.extend_from_slice(&["--cell_size", "1"]);
+    assert!(smoomars(&cell_size).status.success());
+}